@@ -0,0 +1,171 @@
+use std::str::FromStr;
+
+use crate::geometry::Vec3;
+
+/// An arbitrary output variable: an auxiliary image rendered alongside the
+/// beauty image for compositing and denoising.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Aov {
+    /// The reflectance of the surface first seen through the pixel.
+    Albedo,
+    /// The shading normal of the first surface, in world space.
+    Normal,
+    /// The distance from the camera to the first surface.
+    Depth,
+    /// The world space position of the first surface.
+    Position,
+    /// The surface parameterization of the first surface.
+    Uv,
+    /// One more than the index of the first object in the world, or zero.
+    ObjectId,
+    /// One more than the kind of the first object's material, or zero.
+    MaterialId,
+    /// Light reflected once off a diffuse surface into the camera.
+    DirectDiffuse,
+    /// Light reflected more than once, first seen off a diffuse surface.
+    IndirectDiffuse,
+    /// Light reflected once off a specular surface into the camera.
+    DirectSpecular,
+    /// Light reflected more than once, first seen off a specular surface.
+    IndirectSpecular,
+}
+
+impl Aov {
+    pub const NAMES: &'static [&'static str] = &[
+        "albedo",
+        "normal",
+        "depth",
+        "position",
+        "uv",
+        "object_id",
+        "material_id",
+        "direct_diffuse",
+        "indirect_diffuse",
+        "direct_specular",
+        "indirect_specular",
+    ];
+
+    pub fn name(self) -> &'static str {
+        Aov::NAMES[self as usize]
+    }
+
+    /// The names of the AOV's channels in layered images such as OpenEXR.
+    pub fn channels(self) -> &'static [&'static str] {
+        match self {
+            Aov::Depth => &["Z"],
+            Aov::Uv => &["U", "V"],
+            Aov::ObjectId | Aov::MaterialId => &["id"],
+            Aov::Normal | Aov::Position => &["X", "Y", "Z"],
+            _ => &["R", "G", "B"],
+        }
+    }
+
+    /// Whether the AOV labels samples rather than measuring a quantity, so
+    /// that it must not be blended across samples.
+    pub fn is_id(self) -> bool {
+        matches!(self, Aov::ObjectId | Aov::MaterialId)
+    }
+}
+
+impl FromStr for Aov {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Aov, String> {
+        match s {
+            "albedo" => Ok(Aov::Albedo),
+            "normal" => Ok(Aov::Normal),
+            "depth" => Ok(Aov::Depth),
+            "position" => Ok(Aov::Position),
+            "uv" => Ok(Aov::Uv),
+            "object_id" => Ok(Aov::ObjectId),
+            "material_id" => Ok(Aov::MaterialId),
+            "direct_diffuse" => Ok(Aov::DirectDiffuse),
+            "indirect_diffuse" => Ok(Aov::IndirectDiffuse),
+            "direct_specular" => Ok(Aov::DirectSpecular),
+            "indirect_specular" => Ok(Aov::IndirectSpecular),
+            _ => Err(format!("unknown AOV: {}", s)),
+        }
+    }
+}
+
+/// The values of every AOV along one camera path.
+#[derive(Debug, Default)]
+pub struct AovSample {
+    pub albedo: Vec3,
+    pub normal: Vec3,
+    pub depth: f32,
+    pub position: Vec3,
+    pub uv: (f32, f32),
+    pub object_id: u32,
+    pub material_id: u32,
+    pub direct_diffuse: Vec3,
+    pub indirect_diffuse: Vec3,
+    pub direct_specular: Vec3,
+    pub indirect_specular: Vec3,
+}
+
+impl AovSample {
+    /// Sorts light that reached the camera after `bounces` reflections into
+    /// the direct or indirect pass for the kind of surface first seen.
+    pub fn add_light(&mut self, bounces: u32, specular: bool, light: &Vec3) {
+        let pass = match (bounces, specular) {
+            (0, _) => return,
+            (1, false) => &mut self.direct_diffuse,
+            (_, false) => &mut self.indirect_diffuse,
+            (1, true) => &mut self.direct_specular,
+            (_, true) => &mut self.indirect_specular,
+        };
+        *pass += light.clone();
+    }
+
+    pub fn value(&self, aov: Aov) -> Vec3 {
+        match aov {
+            Aov::Albedo => self.albedo.clone(),
+            Aov::Normal => self.normal.clone(),
+            Aov::Depth => Vec3::new(self.depth, 0.0, 0.0),
+            Aov::Position => self.position.clone(),
+            Aov::Uv => Vec3::new(self.uv.0, self.uv.1, 0.0),
+            Aov::ObjectId => Vec3::new(self.object_id as f32, 0.0, 0.0),
+            Aov::MaterialId => Vec3::new(self.material_id as f32, 0.0, 0.0),
+            Aov::DirectDiffuse => self.direct_diffuse.clone(),
+            Aov::IndirectDiffuse => self.indirect_diffuse.clone(),
+            Aov::DirectSpecular => self.direct_specular.clone(),
+            Aov::IndirectSpecular => self.indirect_specular.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn channels(v: &Vec3) -> [f32; 3] {
+        [v.r(), v.g(), v.b()]
+    }
+
+    #[test]
+    fn names_parse_back() {
+        for name in Aov::NAMES {
+            let aov: Aov = name.parse().unwrap();
+            assert_eq!(aov.name(), *name);
+        }
+        assert!("beauty".parse::<Aov>().is_err());
+    }
+
+    #[test]
+    fn sorts_light_into_passes() {
+        let mut sample = AovSample::default();
+        let light = Vec3::new(1.0, 2.0, 3.0);
+        sample.add_light(0, false, &light);
+        sample.add_light(1, false, &light);
+        sample.add_light(2, false, &light);
+        sample.add_light(3, false, &light);
+        sample.add_light(1, true, &light);
+        sample.add_light(4, true, &light);
+        let value = |aov| channels(&sample.value(aov));
+        assert_eq!(value(Aov::DirectDiffuse), [1.0, 2.0, 3.0]);
+        assert_eq!(value(Aov::IndirectDiffuse), [2.0, 4.0, 6.0]);
+        assert_eq!(value(Aov::DirectSpecular), [1.0, 2.0, 3.0]);
+        assert_eq!(value(Aov::IndirectSpecular), [1.0, 2.0, 3.0]);
+    }
+}
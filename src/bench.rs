@@ -0,0 +1,261 @@
+use std::hint::black_box;
+use std::io;
+use std::time::Instant;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde_json::{json, Value};
+
+use crate::camera::CameraSample;
+use crate::geometry::{Ray, Vec3};
+use crate::material::{Dielectric, DiffuseLight, Isotropic, Lambertian, Material, Metal};
+use crate::object::{Hit, Hittable, Sphere};
+use crate::renderer::{RenderSettings, Renderer};
+use crate::scene::Scene;
+use crate::texture::Uniform;
+
+// Benchmarks of the renderer's hot paths, for tracking its performance from
+// commit to commit. Each benchmark does a fixed amount of work on inputs drawn
+// from a generator with a fixed seed, so that every run on every commit times
+// the same work. A benchmark is run once to warm up and then a number of
+// times, and its rate is that of the best run, which the rest of the machine
+// disturbed the least.
+
+const SEED: u64 = 0x5eed;
+
+/// The timings of a benchmark.
+pub struct Measurement {
+    pub name: String,
+    /// What the benchmark counts, such as rays.
+    pub unit: &'static str,
+    /// The number of units each run does.
+    pub count: u64,
+    /// The seconds each run took.
+    pub times: Vec<f64>,
+}
+
+impl Measurement {
+    pub fn best(&self) -> f64 {
+        self.times.iter().cloned().fold(f64::INFINITY, f64::min)
+    }
+
+    pub fn mean(&self) -> f64 {
+        self.times.iter().sum::<f64>() / self.times.len() as f64
+    }
+
+    /// The units done per second in the best run.
+    pub fn rate(&self) -> f64 {
+        self.count as f64 / self.best()
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "name": self.name,
+            "unit": self.unit,
+            "count": self.count,
+            "runs": self.times.len(),
+            "best_seconds": self.best(),
+            "mean_seconds": self.mean(),
+            "per_second": self.rate(),
+        })
+    }
+}
+
+/// Times `runs` runs of `run`, after one to warm up. Each run returns the
+/// number of units it did.
+pub fn measure<F>(name: &str, unit: &'static str, runs: usize, mut run: F) -> Measurement
+where
+    F: FnMut() -> u64,
+{
+    let mut count = run();
+    let mut times = Vec::with_capacity(runs);
+    for _ in 0..runs {
+        let start = Instant::now();
+        count = run();
+        times.push(start.elapsed().as_secs_f64());
+    }
+    Measurement {
+        name: name.to_string(),
+        unit,
+        count,
+        times,
+    }
+}
+
+fn random_vec(rng: &mut StdRng, size: f32) -> Vec3 {
+    Vec3::new(
+        rng.gen_range(-size, size),
+        rng.gen_range(-size, size),
+        rng.gen_range(-size, size),
+    )
+}
+
+/// Adds, subtracts, scales, crosses, dots and normalizes vectors, counting
+/// each as an operation.
+pub fn vec3_arithmetic(runs: usize) -> Measurement {
+    let mut rng = StdRng::seed_from_u64(SEED);
+    let vectors: Vec<(Vec3, Vec3)> = (0..1_000_000)
+        .map(|_| (random_vec(&mut rng, 1.0), random_vec(&mut rng, 1.0)))
+        .collect();
+    measure("vec3_arithmetic", "operations", runs, || {
+        let mut sum = Vec3::default();
+        for (a, b) in black_box(&vectors) {
+            let c = (a + b).cross(&(a - b)) * a.dot(b);
+            sum += (c + Vec3::new(1.0, 0.0, 0.0)).normalized();
+        }
+        black_box(sum);
+        6 * vectors.len() as u64
+    })
+}
+
+/// Traces rays at a unit sphere from all around it, about half of which hit.
+pub fn sphere_hit(runs: usize) -> Measurement {
+    let sphere = Sphere::new(Vec3::default(), 1.0, Dielectric::new(1.5));
+    let mut rng = StdRng::seed_from_u64(SEED);
+    let rays: Vec<Ray> = (0..1_000_000)
+        .map(|_| {
+            let origin = random_vec(&mut rng, 1.0).normalized() * 3.0;
+            let target = random_vec(&mut rng, 1.5);
+            Ray::new(origin.clone(), target - origin, 0.0)
+        })
+        .collect();
+    measure("sphere_hit", "rays", runs, || {
+        let hits = black_box(&rays)
+            .iter()
+            .filter(|ray| sphere.hit(ray, 0.001, f32::MAX).is_some())
+            .count();
+        black_box(hits);
+        rays.len() as u64
+    })
+}
+
+/// Traces camera rays into the world of the random scene.
+pub fn world_hit(runs: usize) -> Measurement {
+    let scene = Scene::random(SEED, 1.5);
+    let mut rng = StdRng::seed_from_u64(SEED);
+    let rays: Vec<Ray> = (0..20_000)
+        .map(|_| {
+            scene.camera().ray(&CameraSample {
+                film: (rng.gen(), rng.gen()),
+                lens: (rng.gen(), rng.gen()),
+                time: rng.gen(),
+            })
+        })
+        .collect();
+    measure("world_hit", "rays", runs, || {
+        let hits = black_box(&rays)
+            .iter()
+            .filter(|ray| scene.world().hit(ray, 0.001, f32::MAX).is_some())
+            .count();
+        black_box(hits);
+        rays.len() as u64
+    })
+}
+
+/// Scatters rays arriving from random directions above a surface facing +z.
+pub fn scatter(name: &str, material: &dyn Material, runs: usize) -> Measurement {
+    let hit = Hit {
+        t: 1.0,
+        p: Vec3::default(),
+        normal: Vec3::new(0.0, 0.0, 1.0),
+        u: 0.5,
+        v: 0.5,
+        object_id: 0,
+        material,
+    };
+    let mut rng = StdRng::seed_from_u64(SEED);
+    let samples: Vec<(Ray, f32, (f32, f32))> = (0..1_000_000)
+        .map(|_| {
+            let mut direction = random_vec(&mut rng, 1.0);
+            if direction.z() > 0.0 {
+                direction = -direction;
+            }
+            let ray = Ray::new(-direction.clone(), direction, 0.0);
+            (ray, rng.gen(), (rng.gen(), rng.gen()))
+        })
+        .collect();
+    measure(&format!("scatter_{}", name), "rays", runs, || {
+        let scattered = black_box(&samples)
+            .iter()
+            .filter(|(ray, uc, u)| material.scatter(ray, &hit, *uc, *u).is_some())
+            .count();
+        black_box(scattered);
+        samples.len() as u64
+    })
+}
+
+/// Times `scatter` for each material.
+pub fn materials(runs: usize) -> Vec<Measurement> {
+    let albedo = || Box::new(Uniform::new(Vec3::new(0.8, 0.5, 0.2)));
+    let materials: Vec<(&str, Box<dyn Material>)> = vec![
+        ("lambertian", Box::new(Lambertian::new(albedo()))),
+        ("metal", Box::new(Metal::new(Vec3::new(0.9, 0.6, 0.3), 0.3))),
+        ("dielectric", Box::new(Dielectric::new(1.5))),
+        ("diffuse_light", Box::new(DiffuseLight::new(albedo()))),
+        ("isotropic", Box::new(Isotropic::new(albedo()))),
+    ];
+    materials
+        .iter()
+        .map(|(name, material)| scatter(name, &**material, runs))
+        .collect()
+}
+
+/// The benchmarks of the pieces of a render, each with `runs` runs.
+pub fn micro(runs: usize) -> Vec<Measurement> {
+    let mut measurements = vec![vec3_arithmetic(runs), sphere_hit(runs), world_hit(runs)];
+    measurements.extend(materials(runs));
+    measurements
+}
+
+/// Times whole renders of the scene, counting every ray they trace.
+pub fn render(scene: &Scene, settings: &RenderSettings, runs: usize) -> io::Result<Measurement> {
+    let run = || -> io::Result<(u64, f64)> {
+        let start = Instant::now();
+        let rendered = futures::executor::block_on(Renderer::new(settings.clone()).render(scene))?;
+        Ok((rendered.rays, start.elapsed().as_secs_f64()))
+    };
+    let (mut count, _) = run()?;
+    let mut times = Vec::with_capacity(runs);
+    for _ in 0..runs {
+        let (rays, time) = run()?;
+        count = rays;
+        times.push(time);
+    }
+    Ok(Measurement {
+        name: "render".to_string(),
+        unit: "rays",
+        count,
+        times,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_count_every_ray() {
+        let settings = RenderSettings {
+            width: 8,
+            height: 6,
+            samples: 4,
+            ..RenderSettings::default()
+        };
+        let scene = Scene::random(1, 8.0 / 6.0);
+        let measurement = render(&scene, &settings, 2).unwrap();
+        assert_eq!(measurement.times.len(), 2);
+        // Every sample traces its camera ray, and some bounce.
+        let samples = (8 * 6 * 4) as u64;
+        assert!(measurement.count > samples);
+        assert!(measurement.count <= samples * (settings.max_depth as u64 + 1));
+        assert_eq!(
+            render(&scene, &settings, 1).unwrap().count,
+            measurement.count
+        );
+
+        let json = measurement.to_json();
+        assert_eq!(json["unit"], "rays");
+        assert_eq!(json["runs"], 2);
+        assert!(json["per_second"].as_f64().unwrap() > 0.0);
+    }
+}
@@ -0,0 +1,484 @@
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+
+use serde_json::{json, Value};
+
+use crate::camera::Camera;
+use crate::generator::{Exclusion, Generator};
+use crate::geometry::Vec3;
+use crate::material::{Dielectric, DiffuseLight, Lambertian, Metal};
+use crate::object::{ConstantMedium, Cuboid, Hittable, Rect, RotateY, Sphere, Translate, World};
+use crate::scene::{Background, Scene};
+use crate::texture::{Checkered, Marble, Uniform, UvCheckered};
+
+/// The scenes that come with the renderer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Builtin {
+    Random,
+    Demo,
+    Cornell,
+    MaterialGrid,
+    TextureShowcase,
+    Volume,
+}
+
+impl Builtin {
+    pub const NAMES: &'static [&'static str] = &[
+        "random",
+        "demo",
+        "cornell",
+        "material_grid",
+        "texture_showcase",
+        "volume",
+    ];
+
+    pub const ALL: &'static [Builtin] = &[
+        Builtin::Random,
+        Builtin::Demo,
+        Builtin::Cornell,
+        Builtin::MaterialGrid,
+        Builtin::TextureShowcase,
+        Builtin::Volume,
+    ];
+
+    pub fn name(self) -> &'static str {
+        Builtin::NAMES[self as usize]
+    }
+
+    /// A sentence describing the scene.
+    pub fn about(self) -> &'static str {
+        match self {
+            Builtin::Random => {
+                "The cover of Ray Tracing in One Weekend: hundreds of small spheres \
+                 of random materials, scattered by the seed."
+            }
+            Builtin::Demo => "Diffuse, metal and hollow glass spheres on a ground sphere.",
+            Builtin::Cornell => "The Cornell box, with two rotated blocks under an area light.",
+            Builtin::MaterialGrid => {
+                "Rows of spheres sweeping the albedo of diffuse, the fuzz of metal and \
+                 the refractive index of glass."
+            }
+            Builtin::TextureShowcase => {
+                "Spheres of each texture on a checkered ground, with marble veined by \
+                 the seed."
+            }
+            Builtin::Volume => "The Cornell box with blocks of smoke and fog.",
+        }
+    }
+}
+
+impl FromStr for Builtin {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Builtin, String> {
+        match Builtin::NAMES.iter().position(|name| *name == s) {
+            Some(index) => Ok(Builtin::ALL[index]),
+            None => Err(format!("unknown scene: {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for Builtin {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Everything it takes to build a built-in scene, so that workers and
+/// servers can build the same scene as the command line.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SceneDescription {
+    pub builtin: Builtin,
+    /// The seed of the scene's randomness, for scenes that have any.
+    pub seed: u64,
+    /// The parameters of the random scene.
+    pub generator: Generator,
+}
+
+impl SceneDescription {
+    pub fn new(builtin: Builtin, seed: u64) -> SceneDescription {
+        SceneDescription {
+            builtin,
+            seed,
+            generator: Generator::default(),
+        }
+    }
+
+    /// Builds the scene, viewed through a camera with the given aspect ratio.
+    pub fn build(&self, aspect: f32) -> Scene {
+        let scene = match self.builtin {
+            Builtin::Random => random(&self.generator, self.seed, aspect),
+            Builtin::Demo => demo(aspect),
+            Builtin::Cornell => cornell(aspect),
+            Builtin::MaterialGrid => material_grid(aspect),
+            Builtin::TextureShowcase => texture_showcase(self.seed, aspect),
+            Builtin::Volume => volume(aspect),
+        };
+        scene.with_description(self.clone())
+    }
+
+    /// The description as a JSON object such as
+    ///
+    /// ```json
+    /// {"name": "random", "seed": 3, "extent": 11, "radius": [0.2, 0.2],
+    ///  "mix": {"diffuse": 0.8, "metal": 0.1, "glass": 0.1},
+    ///  "exclusions": [{"center": [4, 0.2, 0], "radius": 0.9}]}
+    /// ```
+    ///
+    /// where the fields after the seed are the parameters of the random scene.
+    pub fn to_json(&self) -> Value {
+        let generator = &self.generator;
+        let mix = &generator.mix;
+        let exclusions: Vec<Value> = generator
+            .exclusions
+            .iter()
+            .map(|zone| {
+                let center = &zone.center;
+                json!({"center": [center.x(), center.y(), center.z()], "radius": zone.radius})
+            })
+            .collect();
+        json!({
+            "name": self.builtin.name(),
+            "seed": self.seed,
+            "extent": generator.extent,
+            "radius": [generator.radius.0, generator.radius.1],
+            "mix": {"diffuse": mix.diffuse, "metal": mix.metal, "glass": mix.glass},
+            "exclusions": exclusions,
+        })
+    }
+
+    /// Parses a description from a JSON object, where any field may be left
+    /// out for the random scene, `default_seed` or the default parameters.
+    pub fn from_json(value: &Value, default_seed: u64) -> Result<SceneDescription, String> {
+        let object = value.as_object().ok_or("the scene must be an object")?;
+        let mut description = SceneDescription::new(Builtin::Random, default_seed);
+        let generator = &mut description.generator;
+        for (key, value) in object {
+            match key.as_str() {
+                "name" => match value.as_str() {
+                    Some(name) => description.builtin = name.parse()?,
+                    None => return Err(format!("unknown scene: {}", value)),
+                },
+                "seed" => description.seed = integer(value, key)?,
+                "extent" => {
+                    generator.extent = u32::try_from(integer(value, key)?)
+                        .map_err(|_| format!("{} is out of range", key))?
+                }
+                "radius" => {
+                    let radius = numbers(value, key, 2)?;
+                    generator.radius = (radius[0], radius[1]);
+                }
+                "mix" => {
+                    let mix = value
+                        .as_object()
+                        .ok_or_else(|| format!("{} must be an object", key))?;
+                    for (key, value) in mix {
+                        let weight = number(value, key)?;
+                        match key.as_str() {
+                            "diffuse" => generator.mix.diffuse = weight,
+                            "metal" => generator.mix.metal = weight,
+                            "glass" => generator.mix.glass = weight,
+                            _ => return Err(format!("unknown material: {}", key)),
+                        }
+                    }
+                }
+                "exclusions" => {
+                    generator.exclusions = value
+                        .as_array()
+                        .ok_or_else(|| format!("{} must be an array", key))?
+                        .iter()
+                        .map(|zone| {
+                            let center = numbers(&zone["center"], "an exclusion's center", 3)?;
+                            Ok(Exclusion {
+                                center: Vec3::new(center[0], center[1], center[2]),
+                                radius: number(&zone["radius"], "an exclusion's radius")?,
+                            })
+                        })
+                        .collect::<Result<_, String>>()?
+                }
+                _ => return Err(format!("unknown scene field: {}", key)),
+            }
+        }
+        description.generator.validate()?;
+        Ok(description)
+    }
+}
+
+fn integer(value: &Value, key: &str) -> Result<u64, String> {
+    value
+        .as_u64()
+        .ok_or_else(|| format!("{} must be a non-negative integer", key))
+}
+
+fn number(value: &Value, key: &str) -> Result<f32, String> {
+    value
+        .as_f64()
+        .map(|number| number as f32)
+        .ok_or_else(|| format!("{} must be a number", key))
+}
+
+/// Parses an array of `count` numbers.
+fn numbers(value: &Value, key: &str, count: usize) -> Result<Vec<f32>, String> {
+    match value.as_array() {
+        Some(values) if values.len() == count => {
+            values.iter().map(|value| number(value, key)).collect()
+        }
+        _ => Err(format!("{} must be an array of {} numbers", key, count)),
+    }
+}
+
+fn lambertian(r: f32, g: f32, b: f32) -> Lambertian {
+    Lambertian::new(Box::new(Uniform::new(Vec3::new(r, g, b))))
+}
+
+fn random(generator: &Generator, seed: u64, aspect: f32) -> Scene {
+    let camera = Camera::from_fov(
+        Vec3::new(4.0, 1.5, -3.0),
+        Vec3::new(0.0, -0.5, 1.0),
+        Vec3::new(0.0, 1.0, 0.0),
+        90.0,
+        aspect,
+    );
+    Scene::new(generator.generate(seed), camera, Background::Sky)
+}
+
+fn demo(aspect: f32) -> Scene {
+    let camera = Camera::from_fov(
+        Vec3::new(-2.0, 2.0, 1.0),
+        Vec3::new(0.0, 0.0, -1.0),
+        Vec3::new(0.0, 1.0, 0.0),
+        50.0,
+        aspect,
+    );
+    Scene::new(World::demo(), camera, Background::Sky)
+}
+
+/// The red, green and white walls of the Cornell box, 555 units on a side and
+/// open towards -z, with a light of the given extent and radiance in the
+/// ceiling.
+fn cornell_walls(
+    light: ((f32, f32), (f32, f32)),
+    radiance: f32,
+) -> Vec<Box<dyn Hittable + Send + Sync>> {
+    let (x, z) = light;
+    let side = (0.0, 555.0);
+    vec![
+        Box::new(Rect::yz(side, side, 555.0, lambertian(0.12, 0.45, 0.15))),
+        Box::new(Rect::yz(side, side, 0.0, lambertian(0.65, 0.05, 0.05))),
+        Box::new(Rect::xz(
+            x,
+            z,
+            554.0,
+            DiffuseLight::new(Box::new(Uniform::new(Vec3::new(
+                radiance, radiance, radiance,
+            )))),
+        )),
+        Box::new(Rect::xz(side, side, 0.0, lambertian(0.73, 0.73, 0.73))),
+        Box::new(Rect::xz(side, side, 555.0, lambertian(0.73, 0.73, 0.73))),
+        Box::new(Rect::xy(side, side, 555.0, lambertian(0.73, 0.73, 0.73))),
+    ]
+}
+
+/// The height, rotation in degrees and position of the Cornell box's tall
+/// and short blocks.
+const CORNELL_BLOCKS: [(f32, f32, [f32; 3]); 2] = [
+    (330.0, 15.0, [265.0, 0.0, 295.0]),
+    (165.0, -18.0, [130.0, 0.0, 65.0]),
+];
+
+/// Block number `index` of the Cornell box, made of whatever `make` makes of
+/// an axis-aligned block at the origin.
+fn cornell_block<F>(index: usize, make: F) -> Box<dyn Hittable + Send + Sync>
+where
+    F: FnOnce(Cuboid<Lambertian>) -> Box<dyn Hittable + Send + Sync>,
+{
+    let (height, degrees, [x, y, z]) = CORNELL_BLOCKS[index];
+    let cuboid = Cuboid::new(
+        Vec3::default(),
+        Vec3::new(165.0, height, 165.0),
+        lambertian(0.73, 0.73, 0.73),
+    );
+    Box::new(Translate::new(
+        Box::new(RotateY::new(make(cuboid), degrees)),
+        Vec3::new(x, y, z),
+    ))
+}
+
+fn cornell_camera(aspect: f32) -> Camera {
+    Camera::from_fov(
+        Vec3::new(278.0, 278.0, -800.0),
+        Vec3::new(278.0, 278.0, 0.0),
+        Vec3::new(0.0, 1.0, 0.0),
+        40.0,
+        aspect,
+    )
+}
+
+fn cornell(aspect: f32) -> Scene {
+    let mut objects = cornell_walls(((213.0, 343.0), (227.0, 332.0)), 15.0);
+    objects.extend((0..2).map(|index| cornell_block(index, |cuboid| Box::new(cuboid))));
+    Scene::new(
+        World::new(objects),
+        cornell_camera(aspect),
+        Background::Uniform(Vec3::default()),
+    )
+}
+
+fn volume(aspect: f32) -> Scene {
+    let mut objects = cornell_walls(((113.0, 443.0), (127.0, 432.0)), 7.0);
+    // A block of black smoke and one of white fog.
+    let colors = [Vec3::new(0.0, 0.0, 0.0), Vec3::new(1.0, 1.0, 1.0)];
+    objects.extend((0..2).map(|index| {
+        cornell_block(index, |cuboid| {
+            Box::new(ConstantMedium::new(
+                Box::new(cuboid),
+                0.01,
+                Box::new(Uniform::new(colors[index].clone())),
+            ))
+        })
+    }));
+    Scene::new(
+        World::new(objects),
+        cornell_camera(aspect),
+        Background::Uniform(Vec3::default()),
+    )
+}
+
+fn material_grid(aspect: f32) -> Scene {
+    const COLUMNS: usize = 6;
+    let mut objects: Vec<Box<dyn Hittable + Send + Sync>> = vec![Box::new(Sphere::new(
+        Vec3::new(0.0, -1000.0, 0.0),
+        1000.0,
+        lambertian(0.5, 0.5, 0.5),
+    ))];
+    for column in 0..COLUMNS {
+        let t = column as f32 / (COLUMNS - 1) as f32;
+        let x = 2.0 * column as f32 - (COLUMNS - 1) as f32;
+        let center = |z: f32| Vec3::new(x, 0.8, z);
+        let albedo = 0.1 + 0.8 * t;
+        objects.push(Box::new(Sphere::new(
+            center(-2.0),
+            0.8,
+            lambertian(albedo, albedo, albedo),
+        )));
+        objects.push(Box::new(Sphere::new(
+            center(0.0),
+            0.8,
+            Metal::new(Vec3::new(0.8, 0.8, 0.8), t),
+        )));
+        objects.push(Box::new(Sphere::new(
+            center(2.0),
+            0.8,
+            Dielectric::new(1.1 + t),
+        )));
+    }
+    let camera = Camera::from_fov(
+        Vec3::new(0.0, 7.0, 11.0),
+        Vec3::new(0.0, 0.5, 0.0),
+        Vec3::new(0.0, 1.0, 0.0),
+        45.0,
+        aspect,
+    );
+    Scene::new(World::new(objects), camera, Background::Sky)
+}
+
+fn texture_showcase(seed: u64, aspect: f32) -> Scene {
+    let uniform = |r, g, b| Box::new(Uniform::new(Vec3::new(r, g, b)));
+    let objects: Vec<Box<dyn Hittable + Send + Sync>> = vec![
+        Box::new(Sphere::new(
+            Vec3::new(0.0, -1000.0, 0.0),
+            1000.0,
+            Lambertian::new(Box::new(Checkered::new(
+                uniform(0.2, 0.3, 0.1),
+                uniform(0.9, 0.9, 0.9),
+            ))),
+        )),
+        Box::new(Sphere::new(
+            Vec3::new(-2.5, 1.0, 0.0),
+            1.0,
+            Lambertian::new(Box::new(UvCheckered::new(
+                uniform(0.8, 0.1, 0.1),
+                uniform(0.9, 0.9, 0.9),
+                8.0,
+            ))),
+        )),
+        Box::new(Sphere::new(
+            Vec3::new(0.0, 1.0, 0.0),
+            1.0,
+            Lambertian::new(Box::new(Marble::new(seed, Vec3::new(0.9, 0.9, 0.85), 4.0))),
+        )),
+        Box::new(Sphere::new(
+            Vec3::new(2.5, 1.0, 0.0),
+            1.0,
+            Lambertian::new(Box::new(Checkered::new(
+                uniform(0.1, 0.1, 0.4),
+                uniform(0.9, 0.8, 0.2),
+            ))),
+        )),
+    ];
+    let camera = Camera::from_fov(
+        Vec3::new(0.0, 2.0, 7.0),
+        Vec3::new(0.0, 1.0, 0.0),
+        Vec3::new(0.0, 1.0, 0.0),
+        45.0,
+        aspect,
+    );
+    Scene::new(World::new(objects), camera, Background::Sky)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generator::MaterialMix;
+
+    #[test]
+    fn descriptions_round_trip() {
+        for &builtin in Builtin::ALL {
+            assert_eq!(builtin.name().parse(), Ok(builtin));
+            let description = SceneDescription::new(builtin, 7);
+            assert_eq!(
+                SceneDescription::from_json(&description.to_json(), 0),
+                Ok(description)
+            );
+        }
+        assert_eq!(
+            SceneDescription::from_json(&json!({}), 3),
+            Ok(SceneDescription::new(Builtin::Random, 3))
+        );
+        assert!(SceneDescription::from_json(&json!({"name": "teapot"}), 0).is_err());
+        assert!(SceneDescription::from_json(&json!({"size": 3}), 0).is_err());
+
+        let mut description = SceneDescription::new(Builtin::Random, 2);
+        description.generator = Generator {
+            extent: 30,
+            radius: (0.05, 0.25),
+            mix: MaterialMix {
+                diffuse: 1.0,
+                metal: 2.0,
+                glass: 0.5,
+            },
+            exclusions: Vec::new(),
+        };
+        assert_eq!(
+            SceneDescription::from_json(&description.to_json(), 0),
+            Ok(description)
+        );
+        assert!(SceneDescription::from_json(&json!({"radius": [0.3, 0.1]}), 0).is_err());
+        assert!(SceneDescription::from_json(&json!({"mix": {"wood": 1}}), 0).is_err());
+        assert!(SceneDescription::from_json(&json!({"extent": 5000}), 0).is_err());
+        assert!(SceneDescription::from_json(&json!({"extent": 4294967296u64}), 0).is_err());
+    }
+
+    #[test]
+    fn every_scene_is_bounded() {
+        for &builtin in Builtin::ALL {
+            let scene = SceneDescription::new(builtin, 0).build(1.5);
+            let bounds = scene.world().bounding_box().unwrap();
+            for axis in 0..3 {
+                assert!(bounds.min()[axis].is_finite() && bounds.max()[axis].is_finite());
+                assert!(bounds.min()[axis] <= bounds.max()[axis], "{}", builtin);
+            }
+        }
+    }
+}
@@ -1,4 +1,14 @@
 use crate::geometry::{Ray, Vec3};
+use crate::sampling;
+
+/// The sample values used to generate a camera ray: the position on the film
+/// in [0, 1)^2, a position on the lens, and a time within the shutter interval.
+#[derive(Debug, Default)]
+pub struct CameraSample {
+    pub film: (f32, f32),
+    pub lens: (f32, f32),
+    pub time: f32,
+}
 
 #[derive(Debug, Default)]
 pub struct Camera {
@@ -6,6 +16,9 @@ pub struct Camera {
     lower_left: Vec3,
     horizontal: Vec3,
     vertical: Vec3,
+    u: Vec3,
+    v: Vec3,
+    lens_radius: f32,
 }
 
 impl Camera {
@@ -15,6 +28,7 @@ impl Camera {
             lower_left,
             horizontal,
             vertical,
+            ..Camera::default()
         }
     }
 
@@ -34,15 +48,56 @@ impl Camera {
         Camera {
             lower_left: &origin - half_width * &u - half_height * &v - &w,
             origin,
-            horizontal: 2.0 * half_width * u,
-            vertical: 2.0 * half_height * v,
+            horizontal: 2.0 * half_width * &u,
+            vertical: 2.0 * half_height * &v,
+            u,
+            v,
+            lens_radius: 0.0,
+        }
+    }
+
+    pub fn origin(&self) -> &Vec3 {
+        &self.origin
+    }
+
+    /// The matrix taking world space to the camera's space, in which the
+    /// camera looks down -z with y up, as 16 floats in row-major order.
+    pub fn world_to_camera(&self) -> [f32; 16] {
+        let w = self.u.cross(&self.v);
+        let mut matrix = [0.0; 16];
+        for (row, axis) in [&self.u, &self.v, &w].iter().enumerate() {
+            matrix[4 * row] = axis.x();
+            matrix[4 * row + 1] = axis.y();
+            matrix[4 * row + 2] = axis.z();
+            matrix[4 * row + 3] = -axis.dot(&self.origin);
         }
+        matrix[15] = 1.0;
+        matrix
+    }
+
+    /// The vertical field of view in degrees.
+    pub fn vertical_fov(&self) -> f32 {
+        let distance = (&self.lower_left + 0.5 * &self.horizontal + 0.5 * &self.vertical
+            - &self.origin)
+            .length();
+        2.0 * (0.5 * self.vertical.length() / distance)
+            .atan()
+            .to_degrees()
+    }
+
+    pub fn lens_radius(&self) -> f32 {
+        self.lens_radius
     }
 
-    pub fn ray(&self, u: f32, v: f32) -> Ray {
+    pub fn ray(&self, sample: &CameraSample) -> Ray {
+        let ((x, y), _pdf) = sampling::uniform_disk(sample.lens);
+        let offset = self.lens_radius * (x * &self.u + y * &self.v);
+        let origin = &self.origin + offset;
+        let (s, t) = sample.film;
         Ray::new(
-            self.origin.clone(),
-            &self.lower_left + u * &self.horizontal + v * &self.vertical - &self.origin,
+            origin.clone(),
+            &self.lower_left + s * &self.horizontal + t * &self.vertical - &origin,
+            sample.time,
         )
     }
 }
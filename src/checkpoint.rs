@@ -0,0 +1,100 @@
+use std::fs::File;
+use std::io::{self, BufReader, Read, Write};
+use std::path::Path;
+
+use crate::film::Film;
+use crate::output;
+
+// Checkpoints hold the film as accumulated after some number of passes. The
+// samplers derive every sample value from the pixel, the sample index and the
+// seed alone, so the next pass is all it takes to pick up their streams where
+// they left off.
+
+const MAGIC: &[u8; 4] = b"RTCK";
+const VERSION: u32 = 1;
+
+pub struct Checkpoint {
+    /// A description of the settings the render depends on, which must match
+    /// for the render to be resumed.
+    pub settings: String,
+    /// The number of passes rendered into the film.
+    pub passes: usize,
+    pub film: Film,
+}
+
+pub fn read_u32(input: &mut dyn Read) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    input.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+pub fn read_u64(input: &mut dyn Read) -> io::Result<u64> {
+    let mut bytes = [0; 8];
+    input.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+pub fn read_f32(input: &mut dyn Read) -> io::Result<f32> {
+    Ok(f32::from_bits(read_u32(input)?))
+}
+
+/// The longest string that `read_string` accepts, so that a corrupt length
+/// can't ask for an enormous allocation.
+pub const MAX_STRING: usize = 1 << 20;
+
+pub fn read_string(input: &mut dyn Read) -> io::Result<String> {
+    let length = read_u32(input)? as usize;
+    if length > MAX_STRING {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("a string of {} bytes is too long", length),
+        ));
+    }
+    let mut bytes = vec![0; length];
+    input.read_exact(&mut bytes)?;
+    String::from_utf8(bytes).map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))
+}
+
+pub fn write_string(out: &mut dyn Write, value: &str) -> io::Result<()> {
+    out.write_all(&(value.len() as u32).to_le_bytes())?;
+    out.write_all(value.as_bytes())
+}
+
+pub fn write_checkpoint(
+    settings: &str,
+    passes: usize,
+    film: &Film,
+    out: &mut dyn Write,
+) -> io::Result<()> {
+    out.write_all(MAGIC)?;
+    out.write_all(&VERSION.to_le_bytes())?;
+    write_string(out, settings)?;
+    out.write_all(&(passes as u64).to_le_bytes())?;
+    film.write_state(out)
+}
+
+pub fn read_checkpoint(input: &mut dyn Read) -> io::Result<Checkpoint> {
+    let mut magic = [0; 4];
+    input.read_exact(&mut magic)?;
+    if &magic != MAGIC || read_u32(input)? != VERSION {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "not a checkpoint, or one from another version",
+        ));
+    }
+    Ok(Checkpoint {
+        settings: read_string(input)?,
+        passes: read_u64(input)? as usize,
+        film: Film::read_state(input)?,
+    })
+}
+
+/// Replaces the checkpoint at `path`, such that an interruption leaves either
+/// the old checkpoint or the new one.
+pub fn save(settings: &str, passes: usize, film: &Film, path: &Path) -> io::Result<()> {
+    output::replace_atomically(path, |out| write_checkpoint(settings, passes, film, out))
+}
+
+pub fn load(path: &Path) -> io::Result<Checkpoint> {
+    read_checkpoint(&mut BufReader::new(File::open(path)?))
+}
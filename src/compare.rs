@@ -0,0 +1,308 @@
+use std::io;
+
+use crate::film::Film;
+use crate::geometry::Vec3;
+use crate::tonemap::srgb_encode;
+
+// Comparing renders statistically. Renders of the same scene by different
+// sample sequences differ by noise alone, so rather than asking for equal
+// pixels, a comparison asks whether the differences are plausible given the
+// noise the samples of both show in each pixel. A reference without sample
+// statistics, such as one read from an image file, is taken to be as noisy as
+// the render, as it would be if rendered with the same settings.
+//
+// For images without sample statistics, `Metrics` has the usual measures of
+// difference, for judging whether a change made an image less noisy.
+
+/// The luminance a pixel may be off by regardless of its noise, relative to
+/// its luminance, so that noiseless pixels such as the sky compare equal
+/// despite rounding.
+const TOLERANCE: f32 = 1e-3;
+
+/// How a render differs from a reference image.
+pub struct Comparison {
+    width: u32,
+    height: u32,
+    /// The root-mean-square difference in luminance.
+    pub rmse: f32,
+    /// The fraction of pixels whose difference in luminance is more than
+    /// `OUTLIER` standard errors.
+    pub outliers: f32,
+    /// The difference in the images' mean luminance, in standard errors.
+    pub bias: f32,
+    /// Each pixel's difference in luminance in standard errors.
+    pub scores: Vec<f32>,
+}
+
+impl Comparison {
+    /// The number of standard errors beyond which a pixel is an outlier.
+    pub const OUTLIER: f32 = 4.0;
+
+    /// Compares `render` to `reference`, which must be the same size.
+    pub fn new(render: &Film, reference: &Film) -> Comparison {
+        assert_eq!(
+            (render.width(), render.height()),
+            (reference.width(), reference.height())
+        );
+        let mut squares = 0.0;
+        let mut difference = 0.0;
+        let mut variance = 0.0;
+        let mut scores = Vec::new();
+        for (x, y) in render.bounds().pixels() {
+            let (a, b) = (
+                render.pixel(x, y).color().luminance(),
+                reference.pixel(x, y).color().luminance(),
+            );
+            let floor = TOLERANCE * 0.5 * (a.abs() + b.abs()) + 1e-6;
+            let render_variance = render.statistics(x, y).variance_of_mean();
+            let reference_statistics = reference.statistics(x, y);
+            let reference_variance = if reference_statistics.count < 2 {
+                render_variance
+            } else {
+                reference_statistics.variance_of_mean()
+            };
+            let pixel_variance = render_variance + reference_variance + floor * floor;
+            scores.push((a - b) / pixel_variance.sqrt());
+            squares += f64::from((a - b) * (a - b));
+            difference += f64::from(a - b);
+            variance += f64::from(pixel_variance);
+        }
+        let pixels = scores.len() as f64;
+        let outliers = scores
+            .iter()
+            .filter(|score| score.abs() > Comparison::OUTLIER)
+            .count();
+        Comparison {
+            width: render.width(),
+            height: render.height(),
+            rmse: (squares / pixels).sqrt() as f32,
+            outliers: (outliers as f64 / pixels) as f32,
+            bias: (difference / variance.sqrt()) as f32,
+            scores,
+        }
+    }
+
+    /// Whether the differences are plausibly noise: no more than
+    /// `max_outliers` of the pixels are outliers, and the mean luminance is
+    /// within `max_bias` standard errors.
+    pub fn is_plausible(&self, max_outliers: f32, max_bias: f32) -> bool {
+        self.outliers <= max_outliers && self.bias.abs() <= max_bias
+    }
+
+    /// A false-color image of how many standard errors each pixel is off by.
+    pub fn score_image(&self) -> Film {
+        let scores: Vec<f32> = self.scores.iter().map(|score| score.abs()).collect();
+        false_color(&scores, self.width, self.height, 2.0 * Comparison::OUTLIER)
+    }
+}
+
+/// Maps each value to a color, going from black through red and yellow to
+/// white as it goes from 0 to `max`.
+pub fn false_color(values: &[f32], width: u32, height: u32, max: f32) -> Film {
+    let mut film = Film::new(width, height, &[]);
+    for ((x, y), value) in film.bounds().pixels().zip(values) {
+        let t = 3.0 * (value / max).clamp(0.0, 1.0);
+        let color = Vec3::new(t.min(1.0), (t - 1.0).clamp(0.0, 1.0), (t - 2.0).max(0.0));
+        film.set_color(x, y, &color);
+    }
+    film
+}
+
+/// The offset added to squared reference values when computing relative
+/// errors, so that nearly black pixels don't dominate.
+const RELATIVE_OFFSET: f32 = 0.01;
+
+/// Measures of how an image differs from a reference image.
+#[derive(Clone, Debug)]
+pub struct Metrics {
+    /// The mean squared error over all color channels.
+    pub mse: f32,
+    /// The peak signal-to-noise ratio in decibels, taking a radiance of 1 as
+    /// the peak.
+    pub psnr: f32,
+    /// The mean squared error relative to the squared reference values.
+    pub relative_mse: f32,
+    /// The mean structural similarity of the images' luminance as they would
+    /// be displayed, from 1 for identical images down.
+    pub ssim: f32,
+    /// The largest difference in luminance of any pixel.
+    pub max_difference: f32,
+}
+
+impl Metrics {
+    /// Measures how `image` differs from `reference`, which must be the same
+    /// size.
+    pub fn new(image: &Film, reference: &Film) -> io::Result<Metrics> {
+        if (image.width(), image.height()) != (reference.width(), reference.height()) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "the images differ in size: {}x{} and {}x{}",
+                    image.width(),
+                    image.height(),
+                    reference.width(),
+                    reference.height()
+                ),
+            ));
+        }
+        let mut squares = 0.0;
+        let mut relative_squares = 0.0;
+        let mut max_difference: f32 = 0.0;
+        for (x, y) in image.bounds().pixels() {
+            let (a, b) = (image.pixel(x, y).color(), reference.pixel(x, y).color());
+            for channel in 0..3 {
+                let error = a[channel] - b[channel];
+                squares += f64::from(error * error);
+                relative_squares +=
+                    f64::from(error * error / (b[channel] * b[channel] + RELATIVE_OFFSET));
+            }
+            max_difference = max_difference.max((a.luminance() - b.luminance()).abs());
+        }
+        let values = 3.0 * f64::from(image.width() * image.height());
+        let mse = (squares / values) as f32;
+        Ok(Metrics {
+            mse,
+            psnr: -10.0 * mse.log10(),
+            relative_mse: (relative_squares / values) as f32,
+            ssim: ssim(image, reference),
+            max_difference,
+        })
+    }
+}
+
+/// Blurs the values of an image with a Gaussian of standard deviation 1.5
+/// pixels, renormalizing the weights at its edges.
+fn blur(values: &[f32], width: usize, height: usize) -> Vec<f32> {
+    const RADIUS: isize = 5;
+    let weights: Vec<f32> = (-RADIUS..=RADIUS)
+        .map(|offset| (-((offset * offset) as f32) / (2.0 * 1.5 * 1.5)).exp())
+        .collect();
+    let pass = |values: &[f32], step: usize, length: usize| {
+        (0..values.len())
+            .map(|index| {
+                let position = (index / step % length) as isize;
+                let (mut sum, mut total) = (0.0, 0.0);
+                for offset in -RADIUS..=RADIUS {
+                    let neighbor = position + offset;
+                    if 0 <= neighbor && neighbor < length as isize {
+                        let weight = weights[(offset + RADIUS) as usize];
+                        sum += weight * values[(index as isize + offset * step as isize) as usize];
+                        total += weight;
+                    }
+                }
+                sum / total
+            })
+            .collect::<Vec<f32>>()
+    };
+    pass(&pass(values, 1, width), width, height)
+}
+
+/// The mean structural similarity index of the images' sRGB-encoded
+/// luminance, after Wang et al.
+fn ssim(image: &Film, reference: &Film) -> f32 {
+    const C1: f32 = 0.01 * 0.01;
+    const C2: f32 = 0.03 * 0.03;
+    let (width, height) = (image.width() as usize, image.height() as usize);
+    let displayed = |film: &Film| -> Vec<f32> {
+        film.pixels()
+            .iter()
+            .map(|pixel| srgb_encode(pixel.color().luminance().clamp(0.0, 1.0)))
+            .collect()
+    };
+    let (a, b) = (displayed(image), displayed(reference));
+    let product = |x: &[f32], y: &[f32]| -> Vec<f32> {
+        let products: Vec<f32> = x.iter().zip(y).map(|(x, y)| x * y).collect();
+        blur(&products, width, height)
+    };
+    let (mean_a, mean_b) = (blur(&a, width, height), blur(&b, width, height));
+    let (square_a, square_b, cross) = (product(&a, &a), product(&b, &b), product(&a, &b));
+    let sum: f64 = (0..a.len())
+        .map(|i| {
+            let (mu_a, mu_b) = (mean_a[i], mean_b[i]);
+            let variance_a = square_a[i] - mu_a * mu_a;
+            let variance_b = square_b[i] - mu_b * mu_b;
+            let covariance = cross[i] - mu_a * mu_b;
+            let similarity = ((2.0 * mu_a * mu_b + C1) * (2.0 * covariance + C2))
+                / ((mu_a * mu_a + mu_b * mu_b + C1) * (variance_a + variance_b + C2));
+            f64::from(similarity)
+        })
+        .sum();
+    (sum / a.len() as f64) as f32
+}
+
+/// A false-color image of the difference in luminance of each pixel of
+/// `image` from `reference`, which is white where it is `max` or more.
+pub fn difference_image(image: &Film, reference: &Film, max: f32) -> Film {
+    let differences: Vec<f32> = image
+        .pixels()
+        .iter()
+        .zip(reference.pixels())
+        .map(|(a, b)| (a.color().luminance() - b.color().luminance()).abs())
+        .collect();
+    false_color(&differences, image.width(), image.height(), max)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builtin::{Builtin, SceneDescription};
+    use crate::renderer::{RenderSettings, Renderer};
+
+    fn render(seed: u64) -> Film {
+        let settings = RenderSettings {
+            width: 16,
+            height: 12,
+            samples: 64,
+            seed,
+            ..RenderSettings::default()
+        };
+        let scene = SceneDescription::new(Builtin::Demo, 0).build(16.0 / 12.0);
+        futures::executor::block_on(Renderer::new(settings).render(&scene))
+            .unwrap()
+            .film
+    }
+
+    #[test]
+    fn tells_noise_from_bias() {
+        let (render, reference) = (render(1), render(2));
+        let comparison = Comparison::new(&render, &reference);
+        assert!(comparison.is_plausible(0.01, 4.0), "{}", comparison.bias);
+        assert_eq!(Comparison::new(&reference, &reference).rmse, 0.0);
+
+        let mut darker = Film::new(render.width(), render.height(), &[]);
+        for (x, y) in render.bounds().pixels() {
+            darker.set_color(x, y, &(0.97 * render.pixel(x, y).color()));
+        }
+        let comparison = Comparison::new(&render, &darker);
+        assert!(!comparison.is_plausible(0.01, 4.0));
+    }
+
+    fn uniform(width: u32, value: f32) -> Film {
+        let mut film = Film::new(width, 8, &[]);
+        for (x, y) in film.bounds().pixels() {
+            film.set_color(x, y, &Vec3::new(value, value, value));
+        }
+        film
+    }
+
+    #[test]
+    fn measures_differences() {
+        let reference = uniform(8, 0.5);
+        let same = Metrics::new(&reference, &reference).unwrap();
+        assert_eq!(same.mse, 0.0);
+        assert_eq!(same.psnr, f32::INFINITY);
+        assert!((same.ssim - 1.0).abs() < 1e-6);
+
+        let brighter = Metrics::new(&uniform(8, 0.6), &reference).unwrap();
+        assert!((brighter.mse - 0.01).abs() < 1e-6);
+        assert!((brighter.psnr - 20.0).abs() < 1e-3);
+        assert!((brighter.relative_mse - 0.01 / 0.26).abs() < 1e-6);
+        assert!((brighter.max_difference - 0.1).abs() < 1e-6);
+        assert!(brighter.ssim < 1.0);
+
+        let noisy = render(1);
+        let noisier = Metrics::new(&render(2), &noisy).unwrap();
+        assert!(noisier.ssim < 1.0 && noisier.mse > 0.0);
+        assert!(Metrics::new(&uniform(9, 0.5), &reference).is_err());
+    }
+}
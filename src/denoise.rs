@@ -0,0 +1,220 @@
+use crate::aov::Aov;
+use crate::film::Film;
+use crate::geometry::Vec3;
+
+/// Albedo components below this are too dark to divide out of the color.
+const MIN_ALBEDO: f32 = 0.01;
+
+/// A joint bilateral filter that removes Monte Carlo noise from a film,
+/// guided by its albedo and normal AOVs where they were rendered.
+///
+/// The color is first divided by the albedo, so that texture detail is kept
+/// out of the blur, and the remaining illumination is averaged over nearby
+/// pixels weighted by how much their features resemble the center pixel's:
+/// pixels across a geometric edge differ in normal and pixels across a
+/// texture or material edge differ in albedo. A lightly prefiltered copy of
+/// the illumination guides the color term, which is too noisy on its own.
+pub struct Denoiser {
+    radius: u32,
+    sigma_spatial: f32,
+    sigma_color: f32,
+    sigma_albedo: f32,
+    sigma_normal: f32,
+}
+
+impl Default for Denoiser {
+    fn default() -> Denoiser {
+        Denoiser {
+            radius: 6,
+            sigma_spatial: 3.0,
+            sigma_color: 0.5,
+            sigma_albedo: 0.1,
+            sigma_normal: 0.3,
+        }
+    }
+}
+
+/// The per-pixel inputs of the filter.
+struct Features {
+    illumination: Vec<Vec3>,
+    guide: Vec<Vec3>,
+    albedo: Vec<Option<Vec3>>,
+    normal: Vec<Option<Vec3>>,
+}
+
+fn demodulate(color: &Vec3, albedo: &Vec3) -> Vec3 {
+    let divide = |c: f32, a: f32| if a < MIN_ALBEDO { c } else { c / a };
+    Vec3::new(
+        divide(color.x(), albedo.x()),
+        divide(color.y(), albedo.y()),
+        divide(color.z(), albedo.z()),
+    )
+}
+
+fn remodulate(illumination: &Vec3, albedo: &Vec3) -> Vec3 {
+    let multiply = |c: f32, a: f32| if a < MIN_ALBEDO { c } else { c * a };
+    Vec3::new(
+        multiply(illumination.x(), albedo.x()),
+        multiply(illumination.y(), albedo.y()),
+        multiply(illumination.z(), albedo.z()),
+    )
+}
+
+impl Denoiser {
+    fn features(&self, film: &Film) -> Features {
+        let (width, height) = (film.width(), film.height());
+        let find = |kind| film.aovs().iter().position(|aov| *aov == kind);
+        let (albedo_index, normal_index) = (find(Aov::Albedo), find(Aov::Normal));
+
+        let mut features = Features {
+            illumination: Vec::new(),
+            guide: Vec::new(),
+            albedo: Vec::new(),
+            normal: Vec::new(),
+        };
+        for (x, y) in film.bounds().pixels() {
+            let albedo = albedo_index.map(|index| film.aov(index, x, y));
+            let color = film.pixel(x, y).color();
+            features.illumination.push(match &albedo {
+                Some(albedo) => demodulate(&color, albedo),
+                None => color,
+            });
+            features.albedo.push(albedo);
+            features
+                .normal
+                .push(normal_index.map(|index| film.aov(index, x, y)));
+        }
+
+        // A 3x3 box blur of the illumination tames the noise in the color term.
+        for (x, y) in film.bounds().pixels() {
+            let (x0, x1) = (x.saturating_sub(1), (x + 1).min(width - 1));
+            let (y0, y1) = (y.saturating_sub(1), (y + 1).min(height - 1));
+            let mut sum = Vec3::default();
+            for j in y0..=y1 {
+                for i in x0..=x1 {
+                    sum += features.illumination[(j * width + i) as usize].clone();
+                }
+            }
+            features
+                .guide
+                .push(sum / ((x1 - x0 + 1) * (y1 - y0 + 1)) as f32);
+        }
+        features
+    }
+
+    /// The weight of pixel `q` in the filtered value of pixel `p`, which are
+    /// `distance_sq` square pixels apart.
+    fn weight(&self, features: &Features, p: usize, q: usize, distance_sq: f32) -> f32 {
+        let mut exponent = distance_sq / (2.0 * self.sigma_spatial * self.sigma_spatial);
+
+        let (gp, gq) = (&features.guide[p], &features.guide[q]);
+        let color_sq = (gp - gq).sq_length() / (1e-2 + gp.sq_length() + gq.sq_length());
+        exponent += color_sq / (2.0 * self.sigma_color * self.sigma_color);
+
+        if let (Some(ap), Some(aq)) = (&features.albedo[p], &features.albedo[q]) {
+            exponent += (ap - aq).sq_length() / (2.0 * self.sigma_albedo * self.sigma_albedo);
+        }
+        if let (Some(np), Some(nq)) = (&features.normal[p], &features.normal[q]) {
+            exponent += (np - nq).sq_length() / (2.0 * self.sigma_normal * self.sigma_normal);
+        }
+        (-exponent).exp()
+    }
+
+    /// Returns a copy of the film with its beauty image denoised. AOVs are left
+    /// as they were.
+    pub fn apply(&self, film: &Film) -> Film {
+        let (width, height) = (film.width(), film.height());
+        let features = self.features(film);
+        let radius = self.radius as i64;
+
+        let mut denoised = film.clone();
+        for (x, y) in film.bounds().pixels() {
+            let p = (y * width + x) as usize;
+            let mut sum = Vec3::default();
+            let mut total = 0.0;
+            for dy in -radius..=radius {
+                for dx in -radius..=radius {
+                    let (i, j) = (x as i64 + dx, y as i64 + dy);
+                    if i < 0 || j < 0 || i >= width as i64 || j >= height as i64 {
+                        continue;
+                    }
+                    let q = (j * width as i64 + i) as usize;
+                    let weight = self.weight(&features, p, q, (dx * dx + dy * dy) as f32);
+                    sum += weight * &features.illumination[q];
+                    total += weight;
+                }
+            }
+            // The center pixel has weight one, so the total is never zero.
+            let illumination = sum / total;
+            let color = match &features.albedo[p] {
+                Some(albedo) => remodulate(&illumination, albedo),
+                None => illumination,
+            };
+            denoised.set_color(x, y, &color);
+        }
+        denoised
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aov::AovSample;
+    use crate::filter::BoxFilter;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    /// Renders a noisy film of a constant illumination of one on a surface
+    /// whose albedo changes sharply halfway across, returning it along with
+    /// the noise-free colors.
+    fn noisy_film() -> (Film, Vec<Vec3>) {
+        let (width, height) = (32, 32);
+        let filter = BoxFilter::new(0.5);
+        let mut film = Film::new(width, height, &[Aov::Albedo, Aov::Normal]);
+        let mut tile = film.tile(film.bounds(), &filter);
+        let mut rng = StdRng::seed_from_u64(1);
+        let mut truth = Vec::new();
+        for (x, y) in film.bounds().pixels() {
+            let albedo = if x < width / 2 {
+                Vec3::new(0.8, 0.2, 0.2)
+            } else {
+                Vec3::new(0.1, 0.1, 0.9)
+            };
+            let aov = AovSample {
+                albedo: albedo.clone(),
+                normal: Vec3::new(0.0, 1.0, 0.0),
+                ..AovSample::default()
+            };
+            let noise = rng.gen_range(0.5, 1.5);
+            let center = (x as f32 + 0.5, y as f32 + 0.5);
+            tile.add_sample(&filter, center, &(noise * &albedo), &aov);
+            truth.push(albedo);
+        }
+        film.merge(tile);
+        (film, truth)
+    }
+
+    fn error(film: &Film, truth: &[Vec3]) -> f32 {
+        film.pixels()
+            .iter()
+            .zip(truth)
+            .map(|(pixel, truth)| (pixel.color() - truth).sq_length())
+            .sum::<f32>()
+            / truth.len() as f32
+    }
+
+    #[test]
+    fn reduces_noise_without_blurring_across_albedo_edges() {
+        let (film, truth) = noisy_film();
+        let denoised = Denoiser::default().apply(&film);
+        assert!(error(&denoised, &truth) < 0.1 * error(&film, &truth));
+
+        // The colors on either side of the edge stay apart.
+        let width = film.width();
+        for y in 0..film.height() {
+            let left = denoised.pixel(width / 2 - 1, y).color();
+            let right = denoised.pixel(width / 2, y).color();
+            assert!(left.x() > 4.0 * right.x() && right.z() > 2.0 * left.z());
+        }
+    }
+}
@@ -0,0 +1,544 @@
+use std::collections::VecDeque;
+use std::convert::TryFrom;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::net::TcpStream;
+use std::sync::{mpsc, Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::aov::Aov;
+use crate::builtin::SceneDescription;
+use crate::checkpoint::{read_f32, read_string, read_u32, read_u64, write_string};
+use crate::film::{Bounds, FilmTile, MAX_PIXELS};
+use crate::filter::FilterKind;
+use crate::progress::CancellationToken;
+use crate::renderer::{render_pass_tile, RenderSettings};
+use crate::sampler::SamplerKind;
+
+// Rendering tiles on worker processes over TCP. A coordinator connects to each
+// worker and sends it the render settings and the description of the built-in
+// scene as JSON, from which the worker builds the scene. It then sends one
+// tile at a time as a pass number followed by the empty film tile, carrying
+// the statistics of the pixels so far, and the worker replies with the tile
+// rendered. Since the tiles come back exactly as a local render would make
+// them, and are merged in the same order, the image is identical to a local
+// render no matter which worker rendered which tile.
+
+const MAGIC: &[u8; 4] = b"RTWK";
+const VERSION: u32 = 3;
+
+fn write_option_f32(out: &mut dyn Write, value: Option<f32>) -> io::Result<()> {
+    match value {
+        Some(value) => {
+            out.write_all(&[1])?;
+            out.write_all(&value.to_le_bytes())
+        }
+        None => out.write_all(&[0]),
+    }
+}
+
+fn read_option_f32(input: &mut dyn Read) -> io::Result<Option<f32>> {
+    let mut flag = [0];
+    input.read_exact(&mut flag)?;
+    match flag[0] {
+        0 => Ok(None),
+        _ => Ok(Some(read_f32(input)?)),
+    }
+}
+
+fn parse<T>(value: String) -> io::Result<T>
+where
+    T: std::str::FromStr<Err = String>,
+{
+    value
+        .parse()
+        .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))
+}
+
+/// Writes the settings that determine the samples of each tile. The stopping
+/// criteria are left to the coordinator.
+fn write_settings(out: &mut dyn Write, settings: &RenderSettings) -> io::Result<()> {
+    out.write_all(&settings.width.to_le_bytes())?;
+    out.write_all(&settings.height.to_le_bytes())?;
+    out.write_all(&(settings.samples as u64).to_le_bytes())?;
+    out.write_all(&(settings.pass_samples as u64).to_le_bytes())?;
+    out.write_all(&settings.max_depth.to_le_bytes())?;
+    out.write_all(&settings.roulette_depth.to_le_bytes())?;
+    write_string(out, SamplerKind::NAMES[settings.sampler as usize])?;
+    out.write_all(&settings.seed.to_le_bytes())?;
+    write_option_f32(out, settings.adaptive_threshold)?;
+    out.write_all(&(settings.min_samples as u64).to_le_bytes())?;
+    out.write_all(&(settings.aovs.len() as u32).to_le_bytes())?;
+    for aov in &settings.aovs {
+        write_string(out, aov.name())?;
+    }
+    write_string(out, FilterKind::NAMES[settings.filter as usize])?;
+    write_option_f32(out, settings.filter_radius)
+}
+
+fn read_settings(input: &mut dyn Read) -> io::Result<RenderSettings> {
+    let settings = RenderSettings {
+        width: read_u32(input)?,
+        height: read_u32(input)?,
+        samples: read_u64(input)? as usize,
+        pass_samples: read_u64(input)? as usize,
+        max_depth: read_u32(input)?,
+        roulette_depth: read_u32(input)?,
+        sampler: parse(read_string(input)?)?,
+        seed: read_u64(input)?,
+        adaptive_threshold: read_option_f32(input)?,
+        min_samples: read_u64(input)? as usize,
+        aovs: (0..read_u32(input)?)
+            .map(|_| parse(read_string(input)?))
+            .collect::<io::Result<Vec<Aov>>>()?,
+        filter: parse(read_string(input)?)?,
+        filter_radius: read_option_f32(input)?,
+        ..RenderSettings::default()
+    };
+    let invalid = |error: String| io::Error::new(io::ErrorKind::InvalidData, error);
+    settings.validate().map_err(invalid)?;
+    let pixels = settings.width as u64 * settings.height as u64;
+    if pixels > MAX_PIXELS {
+        return Err(invalid(format!("a film of {} pixels is too large", pixels)));
+    }
+    Ok(settings)
+}
+
+/// The bounds of the film that the settings render.
+fn film_bounds(settings: &RenderSettings) -> Bounds {
+    Bounds {
+        x0: 0,
+        y0: 0,
+        x1: settings.width,
+        y1: settings.height,
+    }
+}
+
+fn read_description(input: &mut dyn Read) -> io::Result<SceneDescription> {
+    let invalid = |error: String| io::Error::new(io::ErrorKind::InvalidData, error);
+    let json =
+        serde_json::from_str(&read_string(input)?).map_err(|error| invalid(error.to_string()))?;
+    SceneDescription::from_json(&json, 0).map_err(invalid)
+}
+
+/// Serves a coordinator's requests on `stream` until it hangs up, or until it
+/// sends nothing for `idle_timeout`.
+pub fn serve(stream: TcpStream, idle_timeout: Duration) -> io::Result<()> {
+    stream.set_nodelay(true)?;
+    stream.set_read_timeout(Some(idle_timeout))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = BufWriter::new(stream);
+
+    let mut magic = [0; 4];
+    reader.read_exact(&mut magic)?;
+    if &magic != MAGIC || read_u32(&mut reader)? != VERSION {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "not a coordinator, or one from another version",
+        ));
+    }
+    let settings = read_settings(&mut reader)?;
+    let scene =
+        read_description(&mut reader)?.build(settings.width as f32 / settings.height as f32);
+    let filter = settings.filter.build(settings.filter_radius);
+    let film = film_bounds(&settings);
+
+    loop {
+        let pass = match read_u64(&mut reader) {
+            Ok(pass) => pass,
+            Err(error) if error.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+            Err(error) => return Err(error),
+        };
+        // The pass must begin within the sample budget.
+        let pass = usize::try_from(pass)
+            .ok()
+            .filter(|pass| {
+                pass.checked_mul(settings.pass_samples)
+                    .is_some_and(|begin| begin < settings.samples)
+            })
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("there is no pass {}", pass),
+                )
+            })?;
+        let tile = FilmTile::read_state(&mut reader, &film)?;
+        let tile = render_pass_tile(&settings, &scene, &*filter, tile, pass);
+        tile.write_state(&mut writer)?;
+        writer.flush()?;
+    }
+}
+
+/// A connection to a worker.
+struct Worker {
+    address: String,
+    film: Bounds,
+    reader: BufReader<TcpStream>,
+    writer: BufWriter<TcpStream>,
+}
+
+impl Worker {
+    fn connect(
+        address: &str,
+        settings: &RenderSettings,
+        description: &SceneDescription,
+        timeout: Duration,
+    ) -> io::Result<Worker> {
+        let stream = TcpStream::connect(address)?;
+        stream.set_nodelay(true)?;
+        stream.set_read_timeout(Some(timeout))?;
+        stream.set_write_timeout(Some(timeout))?;
+        let mut worker = Worker {
+            address: address.to_string(),
+            film: film_bounds(settings),
+            reader: BufReader::new(stream.try_clone()?),
+            writer: BufWriter::new(stream),
+        };
+        worker.writer.write_all(MAGIC)?;
+        worker.writer.write_all(&VERSION.to_le_bytes())?;
+        write_settings(&mut worker.writer, settings)?;
+        write_string(&mut worker.writer, &description.to_json().to_string())?;
+        worker.writer.flush()?;
+        Ok(worker)
+    }
+
+    fn render(&mut self, pass: usize, tile: &FilmTile) -> io::Result<FilmTile> {
+        self.writer.write_all(&(pass as u64).to_le_bytes())?;
+        tile.write_state(&mut self.writer)?;
+        self.writer.flush()?;
+        let rendered = FilmTile::read_state(&mut self.reader, &self.film)?;
+        if rendered.pixel_bounds() != tile.pixel_bounds() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "the worker rendered a different tile",
+            ));
+        }
+        Ok(rendered)
+    }
+}
+
+/// What happened while a cluster rendered a pass.
+pub enum ClusterEvent {
+    /// The tile with these pixels was rendered.
+    Tile(Bounds),
+    /// The worker at this address failed and was dropped from the cluster.
+    /// Its tile went back to the others.
+    WorkerFailed(String, io::Error),
+}
+
+/// The tiles of a pass waiting for a worker, and the number yet to be done.
+struct Queue {
+    tiles: VecDeque<(usize, FilmTile)>,
+    remaining: usize,
+}
+
+/// The workers rendering a render's tiles.
+pub struct Cluster {
+    workers: Vec<Worker>,
+}
+
+impl Cluster {
+    /// Connects to the workers at `addresses` and sends them the settings and
+    /// the scene, returning the errors of those that couldn't be reached.
+    /// Workers that take longer than `timeout` to answer are given up on.
+    pub fn connect(
+        addresses: &[String],
+        settings: &RenderSettings,
+        description: &SceneDescription,
+        timeout: Duration,
+    ) -> (Cluster, Vec<(String, io::Error)>) {
+        let mut workers = Vec::new();
+        let mut failures = Vec::new();
+        for address in addresses {
+            match Worker::connect(address, settings, description, timeout) {
+                Ok(worker) => workers.push(worker),
+                Err(error) => failures.push((address.clone(), error)),
+            }
+        }
+        (Cluster { workers }, failures)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.workers.is_empty()
+    }
+
+    /// Renders the tiles of pass number `pass` on the workers, returning the
+    /// rendered tiles in the same order. A tile whose worker fails is handed
+    /// to another, and the pass only fails if every worker does. Once
+    /// `cancellation` is cancelled no more tiles are handed out, and the tiles
+    /// returned are those rendered so far.
+    pub fn render_pass<F>(
+        &mut self,
+        pass: usize,
+        tiles: Vec<FilmTile>,
+        cancellation: &CancellationToken,
+        mut on_event: F,
+    ) -> io::Result<Vec<FilmTile>>
+    where
+        F: FnMut(ClusterEvent),
+    {
+        let total = tiles.len();
+        let queue = Mutex::new(Queue {
+            tiles: tiles.into_iter().enumerate().collect(),
+            remaining: total,
+        });
+        let ready = Condvar::new();
+        let mut rendered: Vec<Option<FilmTile>> = (0..total).map(|_| None).collect();
+
+        let workers = std::mem::take(&mut self.workers);
+        self.workers = thread::scope(|scope| {
+            let (sender, receiver) = mpsc::channel();
+            let handles: Vec<_> = workers
+                .into_iter()
+                .map(|mut worker| {
+                    let sender = sender.clone();
+                    let (queue, ready) = (&queue, &ready);
+                    scope.spawn(move || loop {
+                        let (index, tile) = {
+                            let mut queue = queue.lock().unwrap();
+                            loop {
+                                if queue.remaining == 0 || cancellation.is_cancelled() {
+                                    return Some(worker);
+                                }
+                                if let Some(task) = queue.tiles.pop_front() {
+                                    break task;
+                                }
+                                queue = ready.wait(queue).unwrap();
+                            }
+                        };
+                        match worker.render(pass, &tile) {
+                            Ok(tile) => {
+                                queue.lock().unwrap().remaining -= 1;
+                                ready.notify_all();
+                                let _ = sender.send((index, Ok(tile), worker.address.clone()));
+                            }
+                            Err(error) => {
+                                queue.lock().unwrap().tiles.push_front((index, tile));
+                                ready.notify_all();
+                                let _ = sender.send((index, Err(error), worker.address.clone()));
+                                return None;
+                            }
+                        }
+                    })
+                })
+                .collect();
+            drop(sender);
+
+            // The channel closes once every worker is done or dead.
+            for (index, result, address) in receiver {
+                match result {
+                    Ok(tile) => {
+                        on_event(ClusterEvent::Tile(tile.pixel_bounds()));
+                        rendered[index] = Some(tile);
+                    }
+                    Err(error) => on_event(ClusterEvent::WorkerFailed(address, error)),
+                }
+            }
+            handles
+                .into_iter()
+                .filter_map(|handle| handle.join().unwrap())
+                .collect()
+        });
+
+        let rendered: Vec<FilmTile> = rendered.into_iter().flatten().collect();
+        if rendered.len() < total && !cancellation.is_cancelled() {
+            return Err(io::Error::other("every worker failed"));
+        }
+        Ok(rendered)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builtin::Builtin;
+    use crate::film::Film;
+    use crate::progress::RenderObserver;
+    use crate::renderer::Renderer;
+    use crate::scene::Scene;
+    use std::net::TcpListener;
+
+    fn settings() -> RenderSettings {
+        RenderSettings {
+            width: 40,
+            height: 24,
+            samples: 6,
+            pass_samples: 3,
+            seed: 9,
+            adaptive_threshold: Some(0.3),
+            min_samples: 2,
+            aovs: vec![Aov::Normal, Aov::ObjectId],
+            filter: FilterKind::Mitchell,
+            ..RenderSettings::default()
+        }
+    }
+
+    fn scene(settings: &RenderSettings) -> Scene {
+        SceneDescription::new(Builtin::Volume, settings.seed)
+            .build(settings.width as f32 / settings.height as f32)
+    }
+
+    /// Starts a worker on a free port, returning its address.
+    fn start_worker() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let _ = serve(stream.unwrap(), Duration::from_secs(60));
+            }
+        });
+        address
+    }
+
+    /// Starts a worker that takes a tile and then hangs up without answering.
+    fn start_failing_worker() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        thread::spawn(move || {
+            let mut reader = BufReader::new(listener.accept().unwrap().0);
+            let mut header = [0; 8];
+            reader.read_exact(&mut header).unwrap();
+            let settings = read_settings(&mut reader).unwrap();
+            read_description(&mut reader).unwrap();
+            read_u64(&mut reader).unwrap();
+            FilmTile::read_state(&mut reader, &film_bounds(&settings)).unwrap();
+        });
+        address
+    }
+
+    fn state(film: &Film) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        film.write_state(&mut bytes).unwrap();
+        bytes
+    }
+
+    #[derive(Default)]
+    struct Failures(Vec<String>);
+
+    impl RenderObserver for &mut Failures {
+        fn on_worker_failed(&mut self, worker: &str, _error: &io::Error) {
+            self.0.push(worker.to_string());
+        }
+    }
+
+    /// Serves one coordinator on a free port, returning the port's address and
+    /// a receiver of how serving it ended.
+    fn serve_once(idle_timeout: Duration) -> (String, mpsc::Receiver<io::Result<()>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            let stream = listener.accept().unwrap().0;
+            let _ = sender.send(serve(stream, idle_timeout));
+        });
+        (address, receiver)
+    }
+
+    #[test]
+    fn workers_hang_up_on_bad_or_idle_coordinators() {
+        let settings = settings();
+        let description = SceneDescription::new(Builtin::Volume, settings.seed);
+        let timeout = Duration::from_secs(10);
+        let film = film_bounds(&settings);
+        let tile = Film::new(settings.width, settings.height, &settings.aovs)
+            .tile(film, &*settings.filter.build(None));
+
+        // A pass so far out that its first sample overflows.
+        let (address, ended) = serve_once(timeout);
+        let mut worker = Worker::connect(&address, &settings, &description, timeout).unwrap();
+        assert!(worker.render(usize::MAX, &tile).is_err());
+        let error = ended.recv().unwrap().unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+
+        // A coordinator that stalls after connecting.
+        let (address, ended) = serve_once(Duration::from_millis(50));
+        let _worker = Worker::connect(&address, &settings, &description, timeout).unwrap();
+        let error = ended.recv_timeout(timeout).unwrap().unwrap_err();
+        assert!(matches!(
+            error.kind(),
+            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+        ));
+    }
+
+    #[test]
+    fn settings_round_trip() {
+        let mut bytes = Vec::new();
+        write_settings(&mut bytes, &settings()).unwrap();
+        let read = read_settings(&mut bytes.as_slice()).unwrap();
+        assert_eq!(read.checkpoint_key(), settings().checkpoint_key());
+    }
+
+    #[test]
+    fn rejects_corrupt_frames() {
+        let settings = settings();
+        let film = film_bounds(&settings);
+        let tile = Film::new(settings.width, settings.height, &settings.aovs)
+            .tile(film, &*settings.filter.build(settings.filter_radius));
+        let mut bytes = Vec::new();
+        tile.write_state(&mut bytes).unwrap();
+        assert!(FilmTile::read_state(&mut bytes.as_slice(), &film).is_ok());
+
+        // The bounds of the tile's pixels, then of the tile itself.
+        let bounds = |bytes: &mut Vec<u8>, at: usize, values: [u32; 4]| {
+            for (index, value) in values.iter().enumerate() {
+                let offset = at + 4 * index;
+                bytes[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+            }
+        };
+        for &(at, values) in &[
+            (0, [8, 0, 4, 24]),
+            (0, [0, 0, 41, 24]),
+            (16, [0, 0, 40, 25]),
+            (16, [1, 0, 40, 24]),
+        ] {
+            let mut corrupt = bytes.clone();
+            bounds(&mut corrupt, at, values);
+            let read = FilmTile::read_state(&mut corrupt.as_slice(), &film);
+            assert_eq!(
+                read.err().map(|error| error.kind()),
+                Some(io::ErrorKind::InvalidData)
+            );
+        }
+
+        // A string claiming to be 4 GiB long.
+        let error = read_string(&mut [0xff; 8].as_ref()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+
+        let mut bytes = Vec::new();
+        let huge = RenderSettings {
+            width: 70000,
+            height: 70000,
+            ..settings
+        };
+        write_settings(&mut bytes, &huge).unwrap();
+        let error = read_settings(&mut bytes.as_slice()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn distributed_render_matches_local_render() {
+        let settings = settings();
+        let scene = scene(&settings);
+        let local = futures::executor::block_on(Renderer::new(settings.clone()).render(&scene));
+
+        let failing = start_failing_worker();
+        let workers = vec![failing.clone(), start_worker(), start_worker()];
+        let mut failures = Failures::default();
+        let renderer = Renderer::new(settings)
+            .workers(workers)
+            .observer(&mut failures);
+        let distributed = futures::executor::block_on(renderer.render(&scene)).unwrap();
+
+        assert_eq!(failures.0, vec![failing]);
+        assert_eq!(distributed.passes, 2);
+        assert_eq!(state(&distributed.film), state(&local.unwrap().film));
+    }
+
+    #[test]
+    fn render_fails_without_workers() {
+        let failing = start_failing_worker();
+        let renderer = Renderer::new(settings()).workers(vec![failing]);
+        let scene = scene(&settings());
+        assert!(futures::executor::block_on(renderer.render(&scene)).is_err());
+    }
+}
@@ -0,0 +1,732 @@
+use std::io::{self, Read, Write};
+use std::str::FromStr;
+
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+
+use crate::aov::Aov;
+use crate::film::Film;
+use crate::geometry::Vec3;
+
+// A writer and reader for single-part scanline OpenEXR images. The beauty
+// image is written as the unnamed layer (R, G, B) and each AOV as a layer
+// named after it, e.g. `normal.X`, so that compositing applications can pick
+// them apart.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PixelType {
+    Half,
+    Float,
+}
+
+impl PixelType {
+    pub const NAMES: &'static [&'static str] = &["half", "float"];
+
+    fn code(self) -> i32 {
+        match self {
+            PixelType::Half => 1,
+            PixelType::Float => 2,
+        }
+    }
+
+    fn write(self, value: f32, out: &mut Vec<u8>) {
+        match self {
+            PixelType::Half => out.extend_from_slice(&f32_to_f16(value).to_le_bytes()),
+            PixelType::Float => out.extend_from_slice(&value.to_le_bytes()),
+        }
+    }
+}
+
+impl FromStr for PixelType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<PixelType, String> {
+        match s {
+            "half" => Ok(PixelType::Half),
+            "float" => Ok(PixelType::Float),
+            _ => Err(format!("unknown pixel type: {}", s)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Zip,
+}
+
+impl Compression {
+    pub const NAMES: &'static [&'static str] = &["none", "zip"];
+
+    fn code(self) -> u8 {
+        match self {
+            Compression::None => 0,
+            Compression::Zip => 3,
+        }
+    }
+
+    fn scanlines_per_block(self) -> u32 {
+        match self {
+            Compression::None => 1,
+            Compression::Zip => 16,
+        }
+    }
+}
+
+impl FromStr for Compression {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Compression, String> {
+        match s {
+            "none" => Ok(Compression::None),
+            "zip" => Ok(Compression::Zip),
+            _ => Err(format!("unknown compression: {}", s)),
+        }
+    }
+}
+
+/// The value of a header attribute.
+#[derive(Clone, Debug)]
+pub enum Attribute {
+    Int(i32),
+    Float(f32),
+    String(String),
+    V3f(Vec3),
+    M44f([f32; 16]),
+}
+
+impl Attribute {
+    fn type_name(&self) -> &'static str {
+        match self {
+            Attribute::Int(_) => "int",
+            Attribute::Float(_) => "float",
+            Attribute::String(_) => "string",
+            Attribute::V3f(_) => "v3f",
+            Attribute::M44f(_) => "m44f",
+        }
+    }
+
+    fn value(&self) -> Vec<u8> {
+        match self {
+            Attribute::Int(value) => value.to_le_bytes().to_vec(),
+            Attribute::Float(value) => value.to_le_bytes().to_vec(),
+            Attribute::String(value) => value.as_bytes().to_vec(),
+            Attribute::V3f(value) => [value.x(), value.y(), value.z()]
+                .iter()
+                .flat_map(|v| v.to_le_bytes().to_vec())
+                .collect(),
+            Attribute::M44f(value) => value
+                .iter()
+                .flat_map(|v| v.to_le_bytes().to_vec())
+                .collect(),
+        }
+    }
+}
+
+pub struct ExrOptions {
+    pub pixel_type: PixelType,
+    pub compression: Compression,
+    /// Extra header attributes describing the render.
+    pub attributes: Vec<(String, Attribute)>,
+}
+
+/// Converts to the nearest half-precision float, rounding ties to even.
+fn f32_to_f16(value: f32) -> u16 {
+    let bits = value.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exponent = ((bits >> 23) & 0xff) as i32;
+    let mantissa = bits & 0x007f_ffff;
+    if exponent == 0xff {
+        // Infinity, or NaN with a mantissa bit kept set.
+        let nan = if mantissa != 0 { 0x200 } else { 0 };
+        return sign | 0x7c00 | nan;
+    }
+    let exponent = exponent - 127 + 15;
+    if exponent >= 0x1f {
+        return sign | 0x7c00;
+    }
+    if exponent <= 0 {
+        // A subnormal half, or zero once shifted out entirely.
+        if exponent < -10 {
+            return sign;
+        }
+        let mantissa = mantissa | 0x0080_0000;
+        let shift = (14 - exponent) as u32;
+        let half = mantissa >> shift;
+        let remainder = mantissa & ((1 << shift) - 1);
+        let halfway = 1 << (shift - 1);
+        let round = remainder > halfway || (remainder == halfway && half & 1 == 1);
+        return sign | (half + round as u32) as u16;
+    }
+    let half = ((exponent as u32) << 10) | (mantissa >> 13);
+    let remainder = mantissa & 0x1fff;
+    let round = remainder > 0x1000 || (remainder == 0x1000 && half & 1 == 1);
+    // A carry out of the mantissa correctly bumps the exponent, up to infinity.
+    sign | (half + round as u32) as u16
+}
+
+/// A channel of the image and where its values come from.
+struct Channel {
+    name: String,
+    aov: Option<usize>,
+    component: usize,
+}
+
+fn channels(film: &Film) -> Vec<Channel> {
+    let mut channels: Vec<Channel> = ["R", "G", "B"]
+        .iter()
+        .enumerate()
+        .map(|(component, name)| Channel {
+            name: name.to_string(),
+            aov: None,
+            component,
+        })
+        .collect();
+    for (index, aov) in film.aovs().iter().enumerate() {
+        for (component, name) in aov.channels().iter().enumerate() {
+            channels.push(Channel {
+                name: format!("{}.{}", aov.name(), name),
+                aov: Some(index),
+                component,
+            });
+        }
+    }
+    // Readers expect the channel list in alphabetical order.
+    channels.sort_by(|a, b| a.name.cmp(&b.name));
+    channels
+}
+
+fn write_attribute(header: &mut Vec<u8>, name: &str, type_name: &str, value: &[u8]) {
+    header.extend_from_slice(name.as_bytes());
+    header.push(0);
+    header.extend_from_slice(type_name.as_bytes());
+    header.push(0);
+    header.extend_from_slice(&(value.len() as i32).to_le_bytes());
+    header.extend_from_slice(value);
+}
+
+fn box2i(width: u32, height: u32) -> Vec<u8> {
+    [0, 0, width as i32 - 1, height as i32 - 1]
+        .iter()
+        .flat_map(|v| v.to_le_bytes().to_vec())
+        .collect()
+}
+
+/// Applies the ZIP compression's byte interleaving and delta predictor, which
+/// make the bytes of smoothly varying floats compress better, then deflates.
+fn zip(data: &[u8]) -> io::Result<Vec<u8>> {
+    let half = data.len().div_ceil(2);
+    let mut interleaved = vec![0; data.len()];
+    for (i, byte) in data.iter().enumerate() {
+        let index = if i % 2 == 0 { i / 2 } else { half + i / 2 };
+        interleaved[index] = *byte;
+    }
+    let mut previous = interleaved.first().cloned().unwrap_or(0);
+    for byte in interleaved.iter_mut().skip(1) {
+        let current = *byte;
+        *byte = current.wrapping_sub(previous).wrapping_add(128);
+        previous = current;
+    }
+    let mut encoder = ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+    encoder.write_all(&interleaved)?;
+    encoder.finish()
+}
+
+/// Writes the film and its AOVs as an OpenEXR image.
+pub fn write_exr(film: &Film, options: &ExrOptions, out: &mut dyn Write) -> io::Result<()> {
+    let (width, height) = (film.width(), film.height());
+    let channels = channels(film);
+
+    let mut header = Vec::new();
+    header.extend_from_slice(&[0x76, 0x2f, 0x31, 0x01, 2, 0, 0, 0]);
+    let mut list = Vec::new();
+    for channel in &channels {
+        list.extend_from_slice(channel.name.as_bytes());
+        list.push(0);
+        list.extend_from_slice(&options.pixel_type.code().to_le_bytes());
+        list.extend_from_slice(&[0, 0, 0, 0]);
+        list.extend_from_slice(&1i32.to_le_bytes());
+        list.extend_from_slice(&1i32.to_le_bytes());
+    }
+    list.push(0);
+    write_attribute(&mut header, "channels", "chlist", &list);
+    write_attribute(
+        &mut header,
+        "compression",
+        "compression",
+        &[options.compression.code()],
+    );
+    write_attribute(&mut header, "dataWindow", "box2i", &box2i(width, height));
+    write_attribute(&mut header, "displayWindow", "box2i", &box2i(width, height));
+    write_attribute(&mut header, "lineOrder", "lineOrder", &[0]);
+    write_attribute(
+        &mut header,
+        "pixelAspectRatio",
+        "float",
+        &1f32.to_le_bytes(),
+    );
+    let center: Vec<u8> = [0f32, 0f32]
+        .iter()
+        .flat_map(|v| v.to_le_bytes().to_vec())
+        .collect();
+    write_attribute(&mut header, "screenWindowCenter", "v2f", &center);
+    write_attribute(
+        &mut header,
+        "screenWindowWidth",
+        "float",
+        &1f32.to_le_bytes(),
+    );
+    for (name, attribute) in &options.attributes {
+        write_attribute(&mut header, name, attribute.type_name(), &attribute.value());
+    }
+    header.push(0);
+
+    let lines = options.compression.scanlines_per_block();
+    let mut blocks = Vec::new();
+    for y0 in (0..height).step_by(lines as usize) {
+        let mut data = Vec::new();
+        for y in y0..(y0 + lines).min(height) {
+            for channel in &channels {
+                for x in 0..width {
+                    let color = match channel.aov {
+                        Some(index) => film.aov(index, x, y),
+                        None => film.pixel(x, y).color(),
+                    };
+                    let value = [color.x(), color.y(), color.z()][channel.component];
+                    options.pixel_type.write(value, &mut data);
+                }
+            }
+        }
+        if options.compression == Compression::Zip {
+            let compressed = zip(&data)?;
+            // Blocks that fail to shrink are stored as they are.
+            if compressed.len() < data.len() {
+                data = compressed;
+            }
+        }
+        blocks.push((y0, data));
+    }
+
+    out.write_all(&header)?;
+    let mut offset = (header.len() + 8 * blocks.len()) as u64;
+    for (_, data) in &blocks {
+        out.write_all(&offset.to_le_bytes())?;
+        offset += 8 + data.len() as u64;
+    }
+    for (y0, data) in &blocks {
+        out.write_all(&(*y0 as i32).to_le_bytes())?;
+        out.write_all(&(data.len() as i32).to_le_bytes())?;
+        out.write_all(data)?;
+    }
+    Ok(())
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+/// Converts a half-precision float to single precision, which holds it
+/// exactly.
+fn f16_to_f32(half: u16) -> f32 {
+    let sign = ((half & 0x8000) as u32) << 16;
+    let exponent = ((half >> 10) & 0x1f) as u32;
+    let mantissa = (half & 0x3ff) as u32;
+    let bits = match exponent {
+        0 if mantissa == 0 => sign,
+        0 => {
+            // A subnormal half, which normalizes in single precision.
+            let shift = mantissa.leading_zeros() - 21;
+            let mantissa = (mantissa << shift) & 0x3ff;
+            sign | ((127 - 15 + 1 - shift) << 23) | (mantissa << 13)
+        }
+        0x1f => sign | 0x7f80_0000 | (mantissa << 13),
+        _ => sign | ((exponent + 127 - 15) << 23) | (mantissa << 13),
+    };
+    f32::from_bits(bits)
+}
+
+/// Reverses `zip`.
+fn unzip(data: &[u8], size: usize) -> io::Result<Vec<u8>> {
+    let mut interleaved = Vec::with_capacity(size);
+    ZlibDecoder::new(data).read_to_end(&mut interleaved)?;
+    if interleaved.len() != size {
+        return Err(invalid("a block of the image has the wrong size"));
+    }
+    for i in 1..interleaved.len() {
+        interleaved[i] = interleaved[i - 1]
+            .wrapping_add(interleaved[i])
+            .wrapping_sub(128);
+    }
+    let half = size.div_ceil(2);
+    Ok((0..size)
+        .map(|i| {
+            if i % 2 == 0 {
+                interleaved[i / 2]
+            } else {
+                interleaved[half + i / 2]
+            }
+        })
+        .collect())
+}
+
+/// Reads the bytes of an image in memory.
+struct Cursor<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn take(&mut self, count: usize) -> io::Result<&'a [u8]> {
+        let end = self.position.saturating_add(count);
+        let bytes = self
+            .bytes
+            .get(self.position..end)
+            .ok_or_else(|| invalid("the image is truncated"))?;
+        self.position = end;
+        Ok(bytes)
+    }
+
+    fn i32(&mut self) -> io::Result<i32> {
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(self.take(4)?);
+        Ok(i32::from_le_bytes(bytes))
+    }
+
+    fn u64(&mut self) -> io::Result<u64> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.take(8)?);
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    /// Reads a null-terminated string.
+    fn string(&mut self) -> io::Result<&'a str> {
+        let length = self.bytes[self.position.min(self.bytes.len())..]
+            .iter()
+            .position(|byte| *byte == 0)
+            .ok_or_else(|| invalid("the image is truncated"))?;
+        let string = self.take(length)?;
+        self.take(1)?;
+        std::str::from_utf8(string).map_err(|_| invalid("a name in the header isn't UTF-8"))
+    }
+}
+
+/// A channel of an image being read, and where its values go.
+struct InputChannel {
+    pixel_type: i32,
+    /// The AOV the channel belongs to, or `None` for the beauty image, and the
+    /// component of it the channel holds. Other channels are skipped.
+    target: Option<(Option<usize>, usize)>,
+}
+
+impl InputChannel {
+    fn size(&self) -> usize {
+        match self.pixel_type {
+            1 => 2,
+            _ => 4,
+        }
+    }
+
+    fn value(&self, bytes: &[u8]) -> f32 {
+        match self.pixel_type {
+            0 => u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f32,
+            1 => f16_to_f32(u16::from_le_bytes([bytes[0], bytes[1]])),
+            _ => f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+        }
+    }
+}
+
+/// Reads a single-part scanline OpenEXR image, uncompressed or compressed
+/// with ZIP, into a film. Its R, G and B channels, or Y for a grayscale
+/// image, become the beauty image and layers named after AOVs become those
+/// AOVs. Other channels are ignored.
+pub fn read_exr(input: &mut dyn Read) -> io::Result<Film> {
+    let mut bytes = Vec::new();
+    input.read_to_end(&mut bytes)?;
+    let mut cursor = Cursor {
+        bytes: &bytes,
+        position: 0,
+    };
+    if cursor.take(4)? != [0x76, 0x2f, 0x31, 0x01] {
+        return Err(invalid("not an OpenEXR image"));
+    }
+    let version = cursor.i32()?;
+    if version & 0xff != 2 || version & !0xff != 0 {
+        return Err(invalid(
+            "only single-part scanline OpenEXR images are supported",
+        ));
+    }
+
+    let mut names = Vec::new();
+    let mut channels = Vec::new();
+    let mut compression = None;
+    let mut window = None;
+    loop {
+        let name = cursor.string()?;
+        if name.is_empty() {
+            break;
+        }
+        let _type_name = cursor.string()?;
+        let size = cursor.i32()?;
+        let mut value = Cursor {
+            bytes: cursor.take(size.max(0) as usize)?,
+            position: 0,
+        };
+        match name {
+            "channels" => loop {
+                let name = value.string()?;
+                if name.is_empty() {
+                    break;
+                }
+                let pixel_type = value.i32()?;
+                value.take(4)?;
+                if value.i32()? != 1 || value.i32()? != 1 {
+                    return Err(invalid("subsampled channels are not supported"));
+                }
+                if !(0..=2).contains(&pixel_type) {
+                    return Err(invalid("unknown pixel type"));
+                }
+                names.push(name.to_string());
+                channels.push(InputChannel {
+                    pixel_type,
+                    target: None,
+                });
+            },
+            "compression" => compression = Some(value.take(1)?[0]),
+            "dataWindow" => {
+                let (x0, y0, x1, y1) = (value.i32()?, value.i32()?, value.i32()?, value.i32()?);
+                window = Some((x0, y0, x1, y1));
+            }
+            _ => {}
+        }
+    }
+    let lines = match compression {
+        Some(0) => 1,
+        Some(2) => 1,
+        Some(3) => 16,
+        _ => return Err(invalid("only uncompressed and ZIP images are supported")),
+    };
+    let (x0, y0, x1, y1) = window.ok_or_else(|| invalid("the image has no data window"))?;
+    if x1 < x0 || y1 < y0 {
+        return Err(invalid("the image is empty"));
+    }
+    let (width, height) = ((x1 - x0 + 1) as u32, (y1 - y0 + 1) as u32);
+
+    // Find the beauty image and the AOVs among the channels.
+    let mut aovs: Vec<Aov> = Vec::new();
+    let has_rgb = names
+        .iter()
+        .any(|name| name == "R" || name == "G" || name == "B");
+    for (name, channel) in names.iter().zip(&mut channels) {
+        channel.target = match name.as_str() {
+            "R" => Some((None, 0)),
+            "G" => Some((None, 1)),
+            "B" => Some((None, 2)),
+            "Y" if !has_rgb => Some((None, 3)),
+            _ => name.find('.').and_then(|dot| {
+                let aov: Aov = name[..dot].parse().ok()?;
+                let component = aov.channels().iter().position(|c| *c == &name[dot + 1..])?;
+                let index = match aovs.iter().position(|other| *other == aov) {
+                    Some(index) => index,
+                    None => {
+                        aovs.push(aov);
+                        aovs.len() - 1
+                    }
+                };
+                Some((Some(index), component))
+            }),
+        };
+    }
+
+    let mut film = Film::new(width, height, &aovs);
+    let mut colors = vec![[0.0; 3]; (width * height) as usize];
+    let mut aov_values = vec![vec![[0.0; 3]; aovs.len()]; (width * height) as usize];
+    let line_size: usize =
+        channels.iter().map(|channel| channel.size()).sum::<usize>() * width as usize;
+    let blocks = height.div_ceil(lines);
+    for _ in 0..blocks {
+        let offset = cursor.u64()? as usize;
+        let mut block = Cursor {
+            bytes: &bytes,
+            position: offset,
+        };
+        let y = block.i32()?;
+        if y < y0 || y > y1 {
+            return Err(invalid("a block of the image is out of bounds"));
+        }
+        let first = (y - y0) as u32;
+        let count = lines.min(height - first) as usize;
+        let size = block.i32()?.max(0) as usize;
+        let data = block.take(size)?;
+        let data = if size < count * line_size {
+            unzip(data, count * line_size)?
+        } else {
+            data.to_vec()
+        };
+
+        let mut values = Cursor {
+            bytes: &data,
+            position: 0,
+        };
+        for line in 0..count as u32 {
+            let row = (first + line) * width;
+            for channel in &channels {
+                for x in 0..width {
+                    let value = channel.value(values.take(channel.size())?);
+                    let pixel = (row + x) as usize;
+                    match channel.target {
+                        Some((None, 3)) => colors[pixel] = [value; 3],
+                        Some((None, component)) => colors[pixel][component] = value,
+                        Some((Some(aov), component)) => aov_values[pixel][aov][component] = value,
+                        None => {}
+                    }
+                }
+            }
+        }
+    }
+
+    for (x, y) in film.bounds().pixels() {
+        let pixel = (y * width + x) as usize;
+        let [r, g, b] = colors[pixel];
+        film.set_color(x, y, &Vec3::new(r, g, b));
+        for (index, [vx, vy, vz]) in aov_values[pixel].iter().enumerate() {
+            film.set_aov(index, x, y, &Vec3::new(*vx, *vy, *vz));
+        }
+    }
+    Ok(film)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aov::Aov;
+
+    /// A film of smoothly varying values, so that ZIP blocks compress, whose
+    /// height leaves the last block of 16 scanlines partly filled.
+    fn film() -> Film {
+        let aovs = [Aov::Normal, Aov::Depth, Aov::Uv, Aov::ObjectId, Aov::Albedo];
+        let mut film = Film::new(7, 19, &aovs);
+        for (x, y) in film.bounds().pixels() {
+            let value = |offset: f32| offset + 0.125 * x as f32 - 0.0625 * y as f32;
+            film.set_color(x, y, &Vec3::new(value(1.0), value(2.0), value(3.0)));
+            for index in 0..aovs.len() {
+                let base = 10.0 * (index + 1) as f32;
+                let aov = Vec3::new(value(base), value(base + 1.0), value(base + 2.0));
+                film.set_aov(index, x, y, &aov);
+            }
+        }
+        film
+    }
+
+    fn write(film: &Film, pixel_type: PixelType, compression: Compression) -> Vec<u8> {
+        let options = ExrOptions {
+            pixel_type,
+            compression,
+            attributes: vec![("renderer".to_string(), Attribute::String("test".into()))],
+        };
+        let mut bytes = Vec::new();
+        write_exr(film, &options, &mut bytes).unwrap();
+        bytes
+    }
+
+    /// The value of each channel of the image at each pixel, in channel order.
+    fn values(film: &Film) -> Vec<(String, f32)> {
+        let mut values = Vec::new();
+        for channel in channels(film) {
+            for (x, y) in film.bounds().pixels() {
+                let color = match channel.aov {
+                    Some(index) => film.aov(index, x, y),
+                    None => film.pixel(x, y).color(),
+                };
+                let value = [color.x(), color.y(), color.z()][channel.component];
+                values.push((channel.name.clone(), value));
+            }
+        }
+        values
+    }
+
+    #[test]
+    fn round_trips_layers() {
+        let film = film();
+        let names: Vec<String> = channels(&film).into_iter().map(|c| c.name).collect();
+        let mut sorted = names.clone();
+        sorted.sort();
+        assert_eq!(names, sorted);
+        assert!(names.contains(&"depth.Z".to_string()) && names.contains(&"uv.V".to_string()));
+
+        let zipped = write(&film, PixelType::Float, Compression::Zip);
+        assert!(zipped.len() < write(&film, PixelType::Float, Compression::None).len());
+        let read = read_exr(&mut zipped.as_slice()).unwrap();
+        // The layers come back in the order of their channels.
+        let names = |film: &Film| {
+            let mut names: Vec<&str> = film.aovs().iter().map(|aov| aov.name()).collect();
+            names.sort_unstable();
+            names
+        };
+        assert_eq!(names(&read), names(&film));
+        assert_eq!(values(&read), values(&film));
+
+        let halves = write(&film, PixelType::Half, Compression::Zip);
+        let read = read_exr(&mut halves.as_slice()).unwrap();
+        let expected: Vec<(String, f32)> = values(&film)
+            .into_iter()
+            .map(|(name, value)| (name, f16_to_f32(f32_to_f16(value))))
+            .collect();
+        assert_eq!(values(&read), expected);
+    }
+
+    #[test]
+    fn zip_round_trips_odd_lengths() {
+        for size in &[0, 1, 2, 7, 1000] {
+            let data: Vec<u8> = (0..*size).map(|i| (i * 37 % 251) as u8).collect();
+            assert_eq!(unzip(&zip(&data).unwrap(), data.len()).unwrap(), data);
+        }
+    }
+
+    #[test]
+    fn converts_halves() {
+        let tiny = 2f32.powi(-24);
+        for &(value, half) in &[
+            (0.0, 0x0000),
+            (-0.0, 0x8000),
+            (1.0, 0x3c00),
+            (-2.0, 0xc000),
+            (65504.0, 0x7bff),
+            // Ties round to even, in normal and subnormal halves alike.
+            (1.0 + 2f32.powi(-11), 0x3c00),
+            (1.0 + 3.0 * 2f32.powi(-11), 0x3c02),
+            (1.0 + 2f32.powi(-11) + 2f32.powi(-20), 0x3c01),
+            (tiny, 0x0001),
+            (0.5 * tiny, 0x0000),
+            (0.5 * tiny * 1.001, 0x0001),
+            (1.5 * tiny, 0x0002),
+            (2.5 * tiny, 0x0002),
+            (1023.0 * tiny, 0x03ff),
+            // Rounding up out of the subnormals and out of the finite halves.
+            (1023.75 * tiny, 0x0400),
+            (65520.0, 0x7c00),
+            (1e10, 0x7c00),
+            (1e-10, 0x0000),
+            (f32::INFINITY, 0x7c00),
+            (f32::NEG_INFINITY, 0xfc00),
+        ] {
+            assert_eq!(f32_to_f16(value), half, "{:e}", value);
+        }
+        let nan = f32_to_f16(f32::NAN);
+        assert_eq!(nan & 0x7c00, 0x7c00);
+        assert_ne!(nan & 0x3ff, 0);
+
+        // Every half survives the round trip through single precision.
+        for half in 0..=0xffffu16 {
+            let value = f16_to_f32(half);
+            if value.is_nan() {
+                assert!(half & 0x7c00 == 0x7c00 && half & 0x3ff != 0);
+                assert!(f16_to_f32(f32_to_f16(value)).is_nan());
+            } else {
+                assert_eq!(f32_to_f16(value), half, "{:#06x}", half);
+            }
+        }
+        assert_eq!(f16_to_f32(0x0001), tiny);
+        assert_eq!(f16_to_f32(0x03ff), 1023.0 * tiny);
+        assert_eq!(f16_to_f32(0x7bff), 65504.0);
+    }
+}
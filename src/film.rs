@@ -0,0 +1,704 @@
+use std::io::{self, Read, Write};
+
+use crate::aov::{Aov, AovSample};
+use crate::checkpoint::{read_f32, read_string, read_u32, read_u64, write_string};
+use crate::filter::Filter;
+use crate::geometry::Vec3;
+
+/// A rectangle of pixels [x0, x1) x [y0, y1) in raster coordinates, where y
+/// increases down the image.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Bounds {
+    pub x0: u32,
+    pub y0: u32,
+    pub x1: u32,
+    pub y1: u32,
+}
+
+impl Bounds {
+    pub fn width(&self) -> u32 {
+        self.x1 - self.x0
+    }
+
+    pub fn height(&self) -> u32 {
+        self.y1 - self.y0
+    }
+
+    pub fn area(&self) -> usize {
+        (self.width() * self.height()) as usize
+    }
+
+    /// The pixels of the rectangle in rows from the top.
+    pub fn pixels(&self) -> impl Iterator<Item = (u32, u32)> {
+        let (x0, x1) = (self.x0, self.x1);
+        (self.y0..self.y1).flat_map(move |y| (x0..x1).map(move |x| (x, y)))
+    }
+
+    /// Whether `other` lies within these bounds.
+    pub fn encloses(&self, other: &Bounds) -> bool {
+        self.x0 <= other.x0 && self.y0 <= other.y0 && other.x1 <= self.x1 && other.y1 <= self.y1
+    }
+
+    fn index(&self, (x, y): (u32, u32)) -> usize {
+        ((y - self.y0) * self.width() + (x - self.x0)) as usize
+    }
+}
+
+/// The accumulated samples of one pixel. `rgb` is the filter-weighted sum of
+/// sample radiance and `weight` the sum of filter weights, while `samples`
+/// counts the samples taken within the pixel itself. `aovs` holds the
+/// weighted sums of the film's AOVs in the same way, except for ID AOVs,
+/// which hold the ID of the pixel's first sample.
+#[derive(Clone, Debug, Default)]
+pub struct Pixel {
+    pub rgb: Vec3,
+    pub weight: f32,
+    pub samples: usize,
+    pub aovs: Vec<Vec3>,
+}
+
+impl Pixel {
+    fn new(aovs: usize) -> Pixel {
+        Pixel {
+            aovs: vec![Vec3::default(); aovs],
+            ..Pixel::default()
+        }
+    }
+
+    fn resolve(&self, sum: &Vec3) -> Vec3 {
+        if self.weight != 0.0 {
+            sum / self.weight
+        } else {
+            Vec3::default()
+        }
+    }
+
+    /// The reconstructed linear color of the pixel.
+    pub fn color(&self) -> Vec3 {
+        self.resolve(&self.rgb)
+    }
+}
+
+/// Running mean and variance of a pixel's sample luminances (Welford's
+/// algorithm), used to decide when the pixel has converged.
+#[derive(Clone, Debug, Default)]
+pub struct PixelStatistics {
+    pub count: usize,
+    mean: f32,
+    m2: f32,
+}
+
+impl PixelStatistics {
+    pub fn add(&mut self, value: f32) {
+        self.count += 1;
+        let delta = value - self.mean;
+        self.mean += delta / self.count as f32;
+        self.m2 += delta * (value - self.mean);
+    }
+
+    /// The estimated variance of the mean.
+    pub fn variance_of_mean(&self) -> f32 {
+        if self.count < 2 {
+            return f32::INFINITY;
+        }
+        self.m2 / ((self.count - 1) * self.count) as f32
+    }
+
+    /// The standard error of the mean relative to the mean itself.
+    pub fn relative_error(&self) -> f32 {
+        let standard_error = self.variance_of_mean().sqrt();
+        if standard_error == 0.0 {
+            0.0
+        } else {
+            standard_error / self.mean.abs().max(1e-4)
+        }
+    }
+}
+
+/// The most pixels a film read from a file or the network may have, which
+/// allows for 16384 by 16384.
+pub const MAX_PIXELS: u64 = 1 << 28;
+
+/// The linear RGB framebuffer that samples accumulate into over the course of
+/// a render, in rows from the top of the image down, along with each pixel's
+/// sample statistics so that adaptive sampling can pick up where it left off.
+#[derive(Clone)]
+pub struct Film {
+    width: u32,
+    height: u32,
+    aovs: Vec<Aov>,
+    pixels: Vec<Pixel>,
+    statistics: Vec<PixelStatistics>,
+}
+
+impl Film {
+    pub fn new(width: u32, height: u32, aovs: &[Aov]) -> Film {
+        Film {
+            width,
+            height,
+            aovs: aovs.to_vec(),
+            pixels: vec![Pixel::new(aovs.len()); width as usize * height as usize],
+            statistics: vec![PixelStatistics::default(); width as usize * height as usize],
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn bounds(&self) -> Bounds {
+        Bounds {
+            x0: 0,
+            y0: 0,
+            x1: self.width,
+            y1: self.height,
+        }
+    }
+
+    /// Splits the image into square tiles, in rows from the top.
+    pub fn tiles(&self, size: u32) -> Vec<Bounds> {
+        let (width, height) = (self.width, self.height);
+        (0..height)
+            .step_by(size as usize)
+            .flat_map(|y0| {
+                (0..width).step_by(size as usize).map(move |x0| Bounds {
+                    x0,
+                    y0,
+                    x1: (x0 + size).min(width),
+                    y1: (y0 + size).min(height),
+                })
+            })
+            .collect()
+    }
+
+    pub fn pixel(&self, x: u32, y: u32) -> &Pixel {
+        &self.pixels[(y * self.width + x) as usize]
+    }
+
+    /// The AOVs rendered alongside the beauty image.
+    pub fn aovs(&self) -> &[Aov] {
+        &self.aovs
+    }
+
+    /// The reconstructed value of the `index`th AOV of the film at `(x, y)`.
+    pub fn aov(&self, index: usize, x: u32, y: u32) -> Vec3 {
+        let pixel = self.pixel(x, y);
+        if self.aovs[index].is_id() {
+            pixel.aovs[index].clone()
+        } else {
+            pixel.resolve(&pixel.aovs[index])
+        }
+    }
+
+    /// Replaces the reconstructed color of pixel `(x, y)`, such as with the
+    /// result of post-processing.
+    pub fn set_color(&mut self, x: u32, y: u32, color: &Vec3) {
+        let pixel = &mut self.pixels[(y * self.width + x) as usize];
+        if pixel.weight == 0.0 {
+            pixel.weight = 1.0;
+        }
+        pixel.rgb = pixel.weight * color;
+    }
+
+    /// Replaces the reconstructed value of the `index`th AOV at `(x, y)`, such
+    /// as with one read from an image.
+    pub fn set_aov(&mut self, index: usize, x: u32, y: u32, value: &Vec3) {
+        let is_id = self.aovs[index].is_id();
+        let pixel = &mut self.pixels[(y * self.width + x) as usize];
+        if pixel.weight == 0.0 {
+            pixel.weight = 1.0;
+        }
+        pixel.aovs[index] = if is_id {
+            value.clone()
+        } else {
+            pixel.weight * value
+        };
+    }
+
+    /// A copy of the film with only its beauty image.
+    pub fn without_aovs(&self) -> Film {
+        Film {
+            width: self.width,
+            height: self.height,
+            aovs: Vec::new(),
+            pixels: self
+                .pixels
+                .iter()
+                .map(|pixel| Pixel {
+                    aovs: Vec::new(),
+                    ..pixel.clone()
+                })
+                .collect(),
+            statistics: self.statistics.clone(),
+        }
+    }
+
+    /// The pixels in rows from the top of the image down.
+    pub fn pixels(&self) -> &[Pixel] {
+        &self.pixels
+    }
+
+    pub fn statistics(&self, x: u32, y: u32) -> &PixelStatistics {
+        &self.statistics[(y * self.width + x) as usize]
+    }
+
+    /// A tile for rendering the given pixels of this film, continuing from the
+    /// pixels' statistics so far.
+    pub fn tile(&self, pixels: Bounds, filter: &dyn Filter) -> FilmTile {
+        let mut tile = FilmTile::new(pixels, filter.radius(), self.bounds(), &self.aovs);
+        tile.statistics = pixels
+            .pixels()
+            .map(|(x, y)| self.statistics(x, y).clone())
+            .collect();
+        tile
+    }
+
+    pub fn merge(&mut self, tile: FilmTile) {
+        let width = self.width;
+        for (x, y) in tile.bounds.pixels() {
+            let source = &tile.pixels[tile.bounds.index((x, y))];
+            let pixel = &mut self.pixels[(y * width + x) as usize];
+            pixel.rgb += source.rgb.clone();
+            pixel.weight += source.weight;
+            pixel.samples += source.samples;
+            for (index, aov) in self.aovs.iter().enumerate() {
+                if !aov.is_id() {
+                    pixel.aovs[index] += source.aovs[index].clone();
+                }
+            }
+        }
+        // Only the pixels the tile labelled take its IDs, so that passes after
+        // the one with each pixel's first sample leave them alone.
+        for (x, y) in tile.pixel_bounds.pixels() {
+            let index = tile.pixel_bounds.index((x, y));
+            self.statistics[(y * width + x) as usize] = tile.statistics[index].clone();
+            if tile.labelled[index] {
+                let source = &tile.pixels[tile.bounds.index((x, y))];
+                let pixel = &mut self.pixels[(y * width + x) as usize];
+                for (index, aov) in self.aovs.iter().enumerate() {
+                    if aov.is_id() {
+                        pixel.aovs[index] = source.aovs[index].clone();
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn total_samples(&self) -> usize {
+        self.pixels.iter().map(|pixel| pixel.samples).sum()
+    }
+
+    /// The average number of samples taken per pixel.
+    pub fn average_samples(&self) -> f32 {
+        self.total_samples() as f32 / self.pixels.len() as f32
+    }
+
+    /// Estimates the error of the image as the root of the mean relative
+    /// squared error of the pixels' luminance, in which the squared mean is
+    /// offset so that nearly black pixels don't dominate.
+    pub fn estimated_error(&self) -> f32 {
+        let sum: f32 = self
+            .statistics
+            .iter()
+            .map(|statistics| statistics.variance_of_mean() / (statistics.mean.powi(2) + 0.01))
+            .sum();
+        (sum / self.statistics.len() as f32).sqrt()
+    }
+
+    /// Writes everything accumulated in the film so far, exactly, so that the
+    /// render can be continued from it.
+    pub fn write_state(&self, out: &mut dyn Write) -> io::Result<()> {
+        out.write_all(&self.width.to_le_bytes())?;
+        out.write_all(&self.height.to_le_bytes())?;
+        write_aovs(out, &self.aovs)?;
+        for (pixel, statistics) in self.pixels.iter().zip(&self.statistics) {
+            write_pixel(out, pixel)?;
+            write_statistics(out, statistics)?;
+        }
+        Ok(())
+    }
+
+    /// Reads a film written by `write_state`.
+    pub fn read_state(input: &mut dyn Read) -> io::Result<Film> {
+        let width = read_u32(input)?;
+        let height = read_u32(input)?;
+        if width as u64 * height as u64 > MAX_PIXELS {
+            return Err(invalid(format!(
+                "a film of {}x{} is too large",
+                width, height
+            )));
+        }
+        let aovs = read_aovs(input)?;
+        let mut film = Film::new(width, height, &aovs);
+        for (pixel, statistics) in film.pixels.iter_mut().zip(&mut film.statistics) {
+            read_pixel(input, pixel)?;
+            *statistics = read_statistics(input)?;
+        }
+        Ok(film)
+    }
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn write_aovs(out: &mut dyn Write, aovs: &[Aov]) -> io::Result<()> {
+    out.write_all(&(aovs.len() as u32).to_le_bytes())?;
+    for aov in aovs {
+        write_string(out, aov.name())?;
+    }
+    Ok(())
+}
+
+fn read_aovs(input: &mut dyn Read) -> io::Result<Vec<Aov>> {
+    let count = read_u32(input)?;
+    if count as usize > Aov::NAMES.len() {
+        return Err(invalid(format!("{} AOVs are more than there are", count)));
+    }
+    (0..count)
+        .map(|_| read_string(input)?.parse().map_err(invalid))
+        .collect()
+}
+
+fn write_vec3(out: &mut dyn Write, v: &Vec3) -> io::Result<()> {
+    for value in &[v.x(), v.y(), v.z()] {
+        out.write_all(&value.to_le_bytes())?;
+    }
+    Ok(())
+}
+
+fn read_vec3(input: &mut dyn Read) -> io::Result<Vec3> {
+    Ok(Vec3::new(
+        read_f32(input)?,
+        read_f32(input)?,
+        read_f32(input)?,
+    ))
+}
+
+fn write_pixel(out: &mut dyn Write, pixel: &Pixel) -> io::Result<()> {
+    write_vec3(out, &pixel.rgb)?;
+    out.write_all(&pixel.weight.to_le_bytes())?;
+    out.write_all(&(pixel.samples as u64).to_le_bytes())?;
+    for aov in &pixel.aovs {
+        write_vec3(out, aov)?;
+    }
+    Ok(())
+}
+
+/// Reads a pixel written by `write_pixel` into one with the same AOVs.
+fn read_pixel(input: &mut dyn Read, pixel: &mut Pixel) -> io::Result<()> {
+    pixel.rgb = read_vec3(input)?;
+    pixel.weight = read_f32(input)?;
+    pixel.samples = read_u64(input)? as usize;
+    for aov in &mut pixel.aovs {
+        *aov = read_vec3(input)?;
+    }
+    Ok(())
+}
+
+fn write_statistics(out: &mut dyn Write, statistics: &PixelStatistics) -> io::Result<()> {
+    out.write_all(&(statistics.count as u64).to_le_bytes())?;
+    out.write_all(&statistics.mean.to_le_bytes())?;
+    out.write_all(&statistics.m2.to_le_bytes())
+}
+
+fn read_statistics(input: &mut dyn Read) -> io::Result<PixelStatistics> {
+    Ok(PixelStatistics {
+        count: read_u64(input)? as usize,
+        mean: read_f32(input)?,
+        m2: read_f32(input)?,
+    })
+}
+
+fn write_bounds(out: &mut dyn Write, bounds: &Bounds) -> io::Result<()> {
+    for value in &[bounds.x0, bounds.y0, bounds.x1, bounds.y1] {
+        out.write_all(&value.to_le_bytes())?;
+    }
+    Ok(())
+}
+
+/// Reads bounds, which must lie within `within`.
+fn read_bounds(input: &mut dyn Read, within: &Bounds) -> io::Result<Bounds> {
+    let bounds = Bounds {
+        x0: read_u32(input)?,
+        y0: read_u32(input)?,
+        x1: read_u32(input)?,
+        y1: read_u32(input)?,
+    };
+    if bounds.x0 > bounds.x1 || bounds.y0 > bounds.y1 || !within.encloses(&bounds) {
+        return Err(invalid(format!(
+            "the bounds ({}, {}) to ({}, {}) aren't within ({}, {}) to ({}, {})",
+            bounds.x0, bounds.y0, bounds.x1, bounds.y1, within.x0, within.y0, within.x1, within.y1
+        )));
+    }
+    Ok(bounds)
+}
+
+/// The filtered samples of one tile of the film. Samples near the edge of the
+/// tile are splatted into neighboring pixels too, so the tile accumulates into
+/// a region padded by the filter radius.
+pub struct FilmTile {
+    pixel_bounds: Bounds,
+    bounds: Bounds,
+    aovs: Vec<Aov>,
+    pixels: Vec<Pixel>,
+    statistics: Vec<PixelStatistics>,
+    /// Whether each of the tile's own pixels was labelled in the ID AOVs.
+    labelled: Vec<bool>,
+    rays: u64,
+}
+
+impl FilmTile {
+    fn new(pixels: Bounds, radius: f32, film: Bounds, aovs: &[Aov]) -> FilmTile {
+        let pad = (radius - 0.5).ceil().max(0.0) as u32;
+        let bounds = Bounds {
+            x0: pixels.x0.saturating_sub(pad),
+            y0: pixels.y0.saturating_sub(pad),
+            x1: (pixels.x1 + pad).min(film.x1),
+            y1: (pixels.y1 + pad).min(film.y1),
+        };
+        FilmTile {
+            pixel_bounds: pixels,
+            bounds,
+            aovs: aovs.to_vec(),
+            pixels: vec![Pixel::new(aovs.len()); bounds.area()],
+            statistics: vec![PixelStatistics::default(); pixels.area()],
+            labelled: vec![false; pixels.area()],
+            rays: 0,
+        }
+    }
+
+    /// The pixels this tile is responsible for sampling.
+    pub fn pixel_bounds(&self) -> Bounds {
+        self.pixel_bounds
+    }
+
+    /// Writes the tile exactly, so that it can be rendered or merged elsewhere.
+    pub fn write_state(&self, out: &mut dyn Write) -> io::Result<()> {
+        write_bounds(out, &self.pixel_bounds)?;
+        write_bounds(out, &self.bounds)?;
+        write_aovs(out, &self.aovs)?;
+        for pixel in &self.pixels {
+            write_pixel(out, pixel)?;
+        }
+        for statistics in &self.statistics {
+            write_statistics(out, statistics)?;
+        }
+        for labelled in &self.labelled {
+            out.write_all(&[*labelled as u8])?;
+        }
+        out.write_all(&self.rays.to_le_bytes())
+    }
+
+    /// Reads a tile written by `write_state` of a film with the bounds `film`,
+    /// checking that the tile lies within it.
+    pub fn read_state(input: &mut dyn Read, film: &Bounds) -> io::Result<FilmTile> {
+        let pixel_bounds = read_bounds(input, film)?;
+        let bounds = read_bounds(input, film)?;
+        if !bounds.encloses(&pixel_bounds) {
+            return Err(invalid("a tile's pixels lie outside it".to_string()));
+        }
+        let aovs = read_aovs(input)?;
+        let mut pixels = vec![Pixel::new(aovs.len()); bounds.area()];
+        for pixel in &mut pixels {
+            read_pixel(input, pixel)?;
+        }
+        let statistics = (0..pixel_bounds.area())
+            .map(|_| read_statistics(input))
+            .collect::<io::Result<_>>()?;
+        let mut labelled = vec![0; pixel_bounds.area()];
+        input.read_exact(&mut labelled)?;
+        let rays = read_u64(input)?;
+        Ok(FilmTile {
+            pixel_bounds,
+            bounds,
+            aovs,
+            pixels,
+            statistics,
+            labelled: labelled.into_iter().map(|flag| flag != 0).collect(),
+            rays,
+        })
+    }
+
+    /// The statistics of the samples taken so far in pixel `(x, y)`, which
+    /// must be one of the pixels this tile is responsible for.
+    pub fn statistics_mut(&mut self, (x, y): (u32, u32)) -> &mut PixelStatistics {
+        let index = self.pixel_bounds.index((x, y));
+        &mut self.statistics[index]
+    }
+
+    /// Adds a sample at the continuous raster position `(x, y)` to every pixel
+    /// whose center is within the filter's radius.
+    pub fn add_sample(
+        &mut self,
+        filter: &dyn Filter,
+        (x, y): (f32, f32),
+        color: &Vec3,
+        aov: &AovSample,
+    ) {
+        let radius = filter.radius();
+        let x0 = ((x - 0.5 - radius).ceil().max(0.0) as u32).max(self.bounds.x0);
+        let y0 = ((y - 0.5 - radius).ceil().max(0.0) as u32).max(self.bounds.y0);
+        let x1 = ((x - 0.5 + radius).floor() as i64 + 1).min(self.bounds.x1 as i64);
+        let y1 = ((y - 0.5 + radius).floor() as i64 + 1).min(self.bounds.y1 as i64);
+        for j in y0..(y1.max(0) as u32) {
+            for i in x0..(x1.max(0) as u32) {
+                let weight = filter.evaluate(x - (i as f32 + 0.5), y - (j as f32 + 0.5));
+                if weight != 0.0 {
+                    let pixel = &mut self.pixels[self.bounds.index((i, j))];
+                    pixel.rgb += weight * color;
+                    pixel.weight += weight;
+                    for (index, kind) in self.aovs.iter().enumerate() {
+                        if !kind.is_id() {
+                            pixel.aovs[index] += weight * aov.value(*kind);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Records that `samples` more samples were taken within pixel `(x, y)`.
+    pub fn add_samples(&mut self, (x, y): (u32, u32), samples: usize) {
+        let index = self.bounds.index((x, y));
+        self.pixels[index].samples += samples;
+    }
+
+    /// The number of rays traced to render the tile.
+    pub fn rays(&self) -> u64 {
+        self.rays
+    }
+
+    pub fn add_rays(&mut self, rays: u64) {
+        self.rays += rays;
+    }
+
+    /// Labels pixel `(x, y)`, which must be one of the pixels this tile is
+    /// responsible for, in the ID AOVs with the IDs of a sample in it.
+    pub fn set_ids(&mut self, (x, y): (u32, u32), aov: &AovSample) {
+        let index = self.pixel_bounds.index((x, y));
+        self.labelled[index] = true;
+        let pixel = &mut self.pixels[self.bounds.index((x, y))];
+        for (index, kind) in self.aovs.iter().enumerate() {
+            if kind.is_id() {
+                pixel.aovs[index] = aov.value(*kind);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter::FilterKind;
+
+    const AOVS: &[Aov] = &[Aov::Albedo, Aov::ObjectId];
+
+    fn sample(albedo: f32, object_id: u32) -> AovSample {
+        AovSample {
+            albedo: Vec3::new(albedo, albedo, albedo),
+            object_id,
+            ..AovSample::default()
+        }
+    }
+
+    /// Renders one sample at the center of each of the tile's pixels, which
+    /// is the first of each pixel if `first`.
+    fn render(
+        tile: &mut FilmTile,
+        filter: &dyn Filter,
+        color: &Vec3,
+        aov: &AovSample,
+        first: bool,
+    ) {
+        for (x, y) in tile.pixel_bounds().pixels() {
+            tile.add_sample(filter, (x as f32 + 0.5, y as f32 + 0.5), color, aov);
+            tile.add_samples((x, y), 1);
+            if first {
+                tile.set_ids((x, y), aov);
+            }
+            tile.statistics_mut((x, y)).add(color[0]);
+        }
+        tile.add_rays(tile.pixel_bounds().area() as u64);
+    }
+
+    fn state<F: FnOnce(&mut Vec<u8>) -> io::Result<()>>(write: F) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        write(&mut bytes).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn state_round_trips_exactly() {
+        let filter = FilterKind::Tent.build(Some(1.5));
+        let mut film = Film::new(6, 4, AOVS);
+        let pixels = Bounds {
+            x0: 2,
+            y0: 1,
+            x1: 5,
+            y1: 3,
+        };
+        let mut tile = film.tile(pixels, &*filter);
+        let color = Vec3::new(0.25, 0.5, 1.0);
+        render(&mut tile, &*filter, &color, &sample(0.5, 3), true);
+
+        let bytes = state(|out| tile.write_state(out));
+        let read = FilmTile::read_state(&mut &bytes[..], &film.bounds()).unwrap();
+        assert_eq!(read.pixel_bounds(), pixels);
+        assert_eq!(read.rays(), 6);
+        assert_eq!(state(|out| read.write_state(out)), bytes);
+        let truncated = FilmTile::read_state(&mut &bytes[..bytes.len() - 1], &film.bounds());
+        assert_eq!(
+            truncated.err().map(|error| error.kind()),
+            Some(io::ErrorKind::UnexpectedEof)
+        );
+
+        film.merge(read);
+        let bytes = state(|out| film.write_state(out));
+        let read = Film::read_state(&mut &bytes[..]).unwrap();
+        assert_eq!((read.width(), read.height()), (6, 4));
+        assert_eq!(read.aovs(), AOVS);
+        assert_eq!(read.total_samples(), 6);
+        assert_eq!(read.statistics(3, 2).count, 1);
+        assert_eq!(read.aov(1, 3, 2), Vec3::new(3.0, 0.0, 0.0));
+        assert_eq!(state(|out| read.write_state(out)), bytes);
+        let truncated = Film::read_state(&mut &bytes[..bytes.len() - 1]);
+        assert_eq!(
+            truncated.err().map(|error| error.kind()),
+            Some(io::ErrorKind::UnexpectedEof)
+        );
+    }
+
+    #[test]
+    fn merging_accumulates_samples_and_keeps_the_first_ids() {
+        let filter = FilterKind::Box.build(None);
+        let mut film = Film::new(4, 3, AOVS);
+        let passes = [
+            (Vec3::new(1.0, 0.0, 0.0), sample(0.2, 3)),
+            (Vec3::new(0.0, 0.0, 1.0), sample(0.6, 5)),
+        ];
+        for (pass, (color, aov)) in passes.iter().enumerate() {
+            for bounds in film.tiles(2) {
+                let mut tile = film.tile(bounds, &*filter);
+                render(&mut tile, &*filter, color, aov, pass == 0);
+                film.merge(tile);
+            }
+        }
+
+        assert_eq!(film.total_samples(), 2 * 4 * 3);
+        for (x, y) in film.bounds().pixels() {
+            assert_eq!(film.pixel(x, y).samples, 2);
+            assert_eq!(film.pixel(x, y).color(), Vec3::new(0.5, 0.0, 0.5));
+            assert!((film.aov(0, x, y)[0] - 0.4).abs() < 1e-6);
+            // IDs label the first sample, rather than being averaged or
+            // wiped by the passes after it.
+            assert_eq!(film.aov(1, x, y), Vec3::new(3.0, 0.0, 0.0));
+            // The statistics continue from pass to pass.
+            assert_eq!(film.statistics(x, y).count, 2);
+        }
+    }
+}
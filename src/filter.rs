@@ -0,0 +1,372 @@
+use std::f32::consts::PI;
+use std::str::FromStr;
+
+/// A pixel reconstruction filter, which weights each sample's contribution to
+/// the pixels around it by the sample's offset from their centers. Filters
+/// integrate to one over their support, though the film divides by the sum of
+/// the weights regardless.
+pub trait Filter: Send + Sync {
+    /// The offset beyond which the filter is zero, in pixels.
+    fn radius(&self) -> f32;
+
+    fn evaluate(&self, x: f32, y: f32) -> f32;
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FilterKind {
+    Box,
+    Tent,
+    Gaussian,
+    Mitchell,
+    Lanczos,
+}
+
+impl FilterKind {
+    pub const NAMES: &'static [&'static str] = &["box", "tent", "gaussian", "mitchell", "lanczos"];
+
+    pub fn default_radius(self) -> f32 {
+        match self {
+            FilterKind::Box => 0.5,
+            FilterKind::Tent => 1.0,
+            FilterKind::Gaussian => 1.5,
+            FilterKind::Mitchell => 2.0,
+            FilterKind::Lanczos => 3.0,
+        }
+    }
+
+    /// Builds the filter with the given radius, or its default one. Panics
+    /// unless the radius is finite and positive.
+    pub fn build(self, radius: Option<f32>) -> Box<dyn Filter> {
+        let radius = radius.unwrap_or_else(|| self.default_radius());
+        match self {
+            FilterKind::Box => Box::new(BoxFilter::new(radius)),
+            FilterKind::Tent => Box::new(TentFilter::new(radius)),
+            FilterKind::Gaussian => Box::new(GaussianFilter::new(radius, radius / 3.0)),
+            FilterKind::Mitchell => Box::new(MitchellFilter::new(radius, 1.0 / 3.0, 1.0 / 3.0)),
+            FilterKind::Lanczos => Box::new(LanczosFilter::new(radius)),
+        }
+    }
+}
+
+impl FromStr for FilterKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<FilterKind, String> {
+        match s {
+            "box" => Ok(FilterKind::Box),
+            "tent" => Ok(FilterKind::Tent),
+            "gaussian" => Ok(FilterKind::Gaussian),
+            "mitchell" => Ok(FilterKind::Mitchell),
+            "lanczos" => Ok(FilterKind::Lanczos),
+            _ => Err(format!("unknown filter: {}", s)),
+        }
+    }
+}
+
+/// Panics unless `radius` is a finite, positive number of pixels, since
+/// anything else makes a filter that is zero everywhere or infinite.
+fn check_radius(radius: f32) {
+    assert!(
+        radius.is_finite() && radius > 0.0,
+        "invalid filter radius: {}",
+        radius
+    );
+}
+
+/// The integral of a filter's profile along one axis, by the midpoint rule,
+/// for filters with no closed form.
+fn integral(profile: impl Fn(f32) -> f32, radius: f32) -> f32 {
+    const STEPS: usize = 1024;
+    let step = 2.0 * radius / STEPS as f32;
+    (0..STEPS)
+        .map(|i| profile(-radius + (i as f32 + 0.5) * step))
+        .sum::<f32>()
+        * step
+}
+
+pub struct BoxFilter {
+    radius: f32,
+}
+
+impl BoxFilter {
+    pub fn new(radius: f32) -> BoxFilter {
+        check_radius(radius);
+        BoxFilter { radius }
+    }
+}
+
+impl Filter for BoxFilter {
+    fn radius(&self) -> f32 {
+        self.radius
+    }
+
+    fn evaluate(&self, x: f32, y: f32) -> f32 {
+        if x.abs() <= self.radius && y.abs() <= self.radius {
+            1.0 / (4.0 * self.radius * self.radius)
+        } else {
+            0.0
+        }
+    }
+}
+
+pub struct TentFilter {
+    radius: f32,
+}
+
+impl TentFilter {
+    pub fn new(radius: f32) -> TentFilter {
+        check_radius(radius);
+        TentFilter { radius }
+    }
+}
+
+impl Filter for TentFilter {
+    fn radius(&self) -> f32 {
+        self.radius
+    }
+
+    fn evaluate(&self, x: f32, y: f32) -> f32 {
+        let area = self.radius * self.radius;
+        (self.radius - x.abs()).max(0.0) * (self.radius - y.abs()).max(0.0) / (area * area)
+    }
+}
+
+pub struct GaussianFilter {
+    radius: f32,
+    sigma: f32,
+    /// The integral of `gaussian`.
+    integral: f32,
+}
+
+impl GaussianFilter {
+    pub fn new(radius: f32, sigma: f32) -> GaussianFilter {
+        check_radius(radius);
+        let mut filter = GaussianFilter {
+            radius,
+            sigma,
+            integral: 1.0,
+        };
+        filter.integral = integral(|x| filter.gaussian(x), radius);
+        filter
+    }
+
+    /// The Gaussian shifted down so that it falls to zero at the radius.
+    fn gaussian(&self, x: f32) -> f32 {
+        let g = |x: f32| (-x * x / (2.0 * self.sigma * self.sigma)).exp();
+        (g(x) - g(self.radius)).max(0.0)
+    }
+}
+
+impl Filter for GaussianFilter {
+    fn radius(&self) -> f32 {
+        self.radius
+    }
+
+    fn evaluate(&self, x: f32, y: f32) -> f32 {
+        self.gaussian(x) * self.gaussian(y) / (self.integral * self.integral)
+    }
+}
+
+/// The Mitchell-Netravali cubic, whose negative lobes sharpen edges.
+pub struct MitchellFilter {
+    radius: f32,
+    b: f32,
+    c: f32,
+}
+
+impl MitchellFilter {
+    pub fn new(radius: f32, b: f32, c: f32) -> MitchellFilter {
+        check_radius(radius);
+        MitchellFilter { radius, b, c }
+    }
+
+    /// The cubic over [-2, 2], which integrates to one.
+    fn mitchell(&self, x: f32) -> f32 {
+        let (b, c) = (self.b, self.c);
+        let x = x.abs();
+        let value = if x <= 1.0 {
+            (12.0 - 9.0 * b - 6.0 * c) * x.powi(3)
+                + (-18.0 + 12.0 * b + 6.0 * c) * x.powi(2)
+                + (6.0 - 2.0 * b)
+        } else if x <= 2.0 {
+            (-b - 6.0 * c) * x.powi(3)
+                + (6.0 * b + 30.0 * c) * x.powi(2)
+                + (-12.0 * b - 48.0 * c) * x
+                + (8.0 * b + 24.0 * c)
+        } else {
+            0.0
+        };
+        value / 6.0
+    }
+}
+
+impl Filter for MitchellFilter {
+    fn radius(&self) -> f32 {
+        self.radius
+    }
+
+    fn evaluate(&self, x: f32, y: f32) -> f32 {
+        let scale = 2.0 / self.radius;
+        self.mitchell(scale * x) * self.mitchell(scale * y) * scale * scale
+    }
+}
+
+/// The sinc function windowed by a wider sinc lobe out to the radius.
+pub struct LanczosFilter {
+    radius: f32,
+    /// The integral of `lanczos`.
+    integral: f32,
+}
+
+impl LanczosFilter {
+    pub fn new(radius: f32) -> LanczosFilter {
+        check_radius(radius);
+        let mut filter = LanczosFilter {
+            radius,
+            integral: 1.0,
+        };
+        filter.integral = integral(|x| filter.lanczos(x), radius);
+        filter
+    }
+
+    fn lanczos(&self, x: f32) -> f32 {
+        fn sinc(x: f32) -> f32 {
+            if x.abs() < 1e-5 {
+                1.0
+            } else {
+                (PI * x).sin() / (PI * x)
+            }
+        }
+        if x.abs() > self.radius {
+            0.0
+        } else {
+            sinc(x) * sinc(x / self.radius)
+        }
+    }
+}
+
+impl Filter for LanczosFilter {
+    fn radius(&self) -> f32 {
+        self.radius
+    }
+
+    fn evaluate(&self, x: f32, y: f32) -> f32 {
+        self.lanczos(x) * self.lanczos(y) / (self.integral * self.integral)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aov::{Aov, AovSample};
+    use crate::film::{Bounds, Film};
+    use crate::geometry::Vec3;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    fn filters() -> Vec<(FilterKind, Option<f32>)> {
+        let mut filters: Vec<_> = FilterKind::NAMES
+            .iter()
+            .map(|name| (name.parse().unwrap(), None))
+            .collect();
+        filters.extend(&[
+            (FilterKind::Box, Some(1.5)),
+            (FilterKind::Tent, Some(2.5)),
+            (FilterKind::Mitchell, Some(1.0)),
+        ]);
+        filters
+    }
+
+    #[test]
+    fn filters_integrate_to_one() {
+        const STEPS: usize = 400;
+        for (kind, radius) in filters() {
+            let filter = kind.build(radius);
+            let r = filter.radius();
+            let step = 2.0 * r / STEPS as f32;
+            let offset = |i: usize| -r + (i as f32 + 0.5) * step;
+            let mut sum = 0.0;
+            for i in 0..STEPS {
+                for j in 0..STEPS {
+                    sum += filter.evaluate(offset(i), offset(j)) as f64;
+                }
+            }
+            let integral = sum * (step * step) as f64;
+            assert!((integral - 1.0).abs() < 1e-3, "{:?}: {}", kind, integral);
+            assert_eq!(filter.evaluate(r + 0.01, 0.0), 0.0, "{:?}", kind);
+            assert_eq!(filter.evaluate(0.0, -r - 0.01), 0.0, "{:?}", kind);
+        }
+    }
+
+    #[test]
+    fn rejects_radii_that_are_not_finite_and_positive() {
+        for kind in FilterKind::NAMES
+            .iter()
+            .map(|name| name.parse::<FilterKind>().unwrap())
+        {
+            for radius in &[0.0, -1.0, f32::NAN, f32::INFINITY] {
+                let built = std::panic::catch_unwind(|| kind.build(Some(*radius)));
+                assert!(built.is_err(), "{:?} with radius {}", kind, radius);
+            }
+        }
+    }
+
+    /// Splats random samples over the film, each into the tile holding the
+    /// pixel it falls in, and merges the tiles.
+    fn splat(filter: &dyn Filter, film: &mut Film, tiles: &[Bounds]) {
+        let mut rng = StdRng::seed_from_u64(0x5eed);
+        let mut tiles: Vec<_> = tiles.iter().map(|t| film.tile(*t, filter)).collect();
+        let aov = AovSample {
+            albedo: Vec3::new(0.5, 0.25, 1.0),
+            ..AovSample::default()
+        };
+        for _ in 0..2000 {
+            let (x, y) = (rng.gen_range(0.0, 12.0), rng.gen_range(0.0, 9.0));
+            let color = Vec3::new(rng.gen(), rng.gen(), rng.gen());
+            let tile = tiles
+                .iter_mut()
+                .find(|tile| {
+                    let b = tile.pixel_bounds();
+                    (b.x0 as f32..b.x1 as f32).contains(&x)
+                        && (b.y0 as f32..b.y1 as f32).contains(&y)
+                })
+                .unwrap();
+            tile.add_sample(filter, (x, y), &color, &aov);
+        }
+        for tile in tiles {
+            film.merge(tile);
+        }
+    }
+
+    #[test]
+    fn tiles_merge_into_the_film_splatting_would_make() {
+        for (kind, radius) in filters() {
+            let filter = kind.build(radius);
+            let mut whole = Film::new(12, 9, &[Aov::Albedo]);
+            let bounds = whole.bounds();
+            splat(&*filter, &mut whole, &[bounds]);
+            let mut split = Film::new(12, 9, &[Aov::Albedo]);
+            let tiles = split.tiles(5);
+            assert!(tiles.len() > 4);
+            splat(&*filter, &mut split, &tiles);
+
+            for (x, y) in whole.bounds().pixels() {
+                let (a, b) = (whole.pixel(x, y), split.pixel(x, y));
+                let close = |a: f32, b: f32| (a - b).abs() <= 1e-4 * a.abs().max(1.0);
+                assert!(
+                    close(a.weight, b.weight)
+                        && close(a.rgb.r(), b.rgb.r())
+                        && close(a.rgb.g(), b.rgb.g())
+                        && close(a.rgb.b(), b.rgb.b()),
+                    "{:?} differs at ({}, {}): {:?} and {:?}",
+                    kind,
+                    x,
+                    y,
+                    a,
+                    b
+                );
+                assert!(close(a.aovs[0].b(), b.aovs[0].b()));
+            }
+        }
+    }
+}
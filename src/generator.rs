@@ -0,0 +1,267 @@
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::geometry::Vec3;
+use crate::material::{Dielectric, Lambertian, Metal};
+use crate::object::{Hittable, Sphere, World};
+use crate::texture::{Checkered, Uniform};
+
+/// The relative frequencies of the small spheres' materials.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MaterialMix {
+    pub diffuse: f32,
+    pub metal: f32,
+    pub glass: f32,
+}
+
+/// A region kept clear of small spheres, such as around a hero sphere.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Exclusion {
+    pub center: Vec3,
+    pub radius: f32,
+}
+
+/// The largest grid extent, which already makes 40,000 small spheres.
+pub const MAX_EXTENT: u32 = 100;
+
+/// The parameters of the random scene: the cover scene of *Ray Tracing in One
+/// Weekend*, with small spheres scattered over a grid around three large
+/// hero spheres.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Generator {
+    /// Small spheres are placed in the cells from `-extent` to `extent` along
+    /// both x and z.
+    pub extent: u32,
+    /// The smallest and largest radius of the small spheres.
+    pub radius: (f32, f32),
+    pub mix: MaterialMix,
+    /// Small spheres are left out where their centers fall in these regions.
+    pub exclusions: Vec<Exclusion>,
+}
+
+impl Default for Generator {
+    fn default() -> Generator {
+        Generator {
+            extent: 11,
+            radius: (0.2, 0.2),
+            mix: MaterialMix {
+                diffuse: 0.8,
+                metal: 0.1,
+                glass: 0.1,
+            },
+            // Clear of the metal hero sphere, though it overlaps the others.
+            exclusions: vec![Exclusion {
+                center: Vec3::new(4.0, 0.2, 0.0),
+                radius: 0.9,
+            }],
+        }
+    }
+}
+
+impl Generator {
+    /// Checks that the parameters describe a scene.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.extent > MAX_EXTENT {
+            return Err(format!(
+                "the grid extent must be at most {}, not {}",
+                MAX_EXTENT, self.extent
+            ));
+        }
+        let (min, max) = self.radius;
+        if !(0.0 < min && min <= max) {
+            return Err(format!("invalid radius range: {} to {}", min, max));
+        }
+        let mix = &self.mix;
+        let weights = [mix.diffuse, mix.metal, mix.glass];
+        if !weights.iter().all(|weight| *weight >= 0.0) || weights.iter().sum::<f32>() <= 0.0 {
+            return Err("the material mix must be non-negative, and not all zero".to_string());
+        }
+        if !self.exclusions.iter().all(|zone| zone.radius >= 0.0) {
+            return Err("the radius of an exclusion must be non-negative".to_string());
+        }
+        Ok(())
+    }
+
+    /// Generates the world, drawing from a random number generator seeded by
+    /// `seed`.
+    pub fn generate(&self, seed: u64) -> World {
+        let checker = Checkered::new(
+            Box::new(Uniform::new(Vec3::new(0.2, 0.3, 0.1))),
+            Box::new(Uniform::new(Vec3::new(0.9, 0.9, 0.9))),
+        );
+        let earth = Sphere::new(
+            Vec3::new(0.0, -1000.0, 0.0),
+            1000.0,
+            Lambertian::new(Box::new(checker)),
+        );
+        let mut objects: Vec<Box<dyn Hittable + Send + Sync>> = vec![Box::new(earth)];
+        let mix = &self.mix;
+        let total = mix.diffuse + mix.metal + mix.glass;
+        let (diffuse, metal) = (mix.diffuse / total, (mix.diffuse + mix.metal) / total);
+        let (min_radius, max_radius) = self.radius;
+        let extent = self.extent as i32;
+        let mut rng = StdRng::seed_from_u64(seed);
+        for x in -extent..extent {
+            for z in -extent..extent {
+                let (jitter_x, jitter_z) = (rng.gen::<f32>(), rng.gen::<f32>());
+                // Only drawn for a range, so that the default scene stays as
+                // it always was.
+                let radius = if min_radius < max_radius {
+                    rng.gen_range(min_radius, max_radius)
+                } else {
+                    min_radius
+                };
+                let center = Vec3::new(
+                    (x as f32) + 0.9 * jitter_x,
+                    radius,
+                    (z as f32) + 0.9 * jitter_z,
+                );
+                if self
+                    .exclusions
+                    .iter()
+                    .any(|zone| (&center - &zone.center).length() < zone.radius)
+                {
+                    continue;
+                }
+                let r: f32 = rng.gen::<f32>();
+                if r < diffuse {
+                    objects.push(Box::new(Sphere::new(
+                        center,
+                        radius,
+                        Lambertian::new(Box::new(Uniform::new(Vec3::new(
+                            rng.gen::<f32>() * rng.gen::<f32>(),
+                            rng.gen::<f32>() * rng.gen::<f32>(),
+                            rng.gen::<f32>() * rng.gen::<f32>(),
+                        )))),
+                    )));
+                } else if r < metal {
+                    objects.push(Box::new(Sphere::new(
+                        center,
+                        radius,
+                        Metal::new(
+                            Vec3::new(
+                                0.5 * (1.0 + rng.gen::<f32>()),
+                                0.5 * (1.0 + rng.gen::<f32>()),
+                                0.5 * (1.0 + rng.gen::<f32>()),
+                            ),
+                            0.5 * rng.gen::<f32>(),
+                        ),
+                    )));
+                } else {
+                    // Hollow glass, with a thin shell.
+                    objects.push(Box::new(Sphere::new(
+                        center.clone(),
+                        radius,
+                        Dielectric::new(1.5),
+                    )));
+                    objects.push(Box::new(Sphere::new(
+                        center,
+                        -0.975 * radius,
+                        Dielectric::new(1.5),
+                    )));
+                };
+            }
+        }
+        objects.push(Box::new(Sphere::new(
+            Vec3::new(0.0, 1.0, 0.0),
+            1.0,
+            Dielectric::new(1.5),
+        )));
+        objects.push(Box::new(Sphere::new(
+            Vec3::new(-3.0, 1.0, 0.5),
+            1.0,
+            Lambertian::new(Box::new(Uniform::new(Vec3::new(0.2, 0.2, 0.6)))),
+        )));
+        objects.push(Box::new(Sphere::new(
+            Vec3::new(4.0, 1.0, 0.0),
+            1.0,
+            Metal::new(Vec3::new(0.8, 0.6, 0.7), 0.0),
+        )));
+        World::new(objects)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generates_the_same_world_from_the_same_seed() {
+        let generator = Generator {
+            extent: 4,
+            radius: (0.1, 0.3),
+            ..Generator::default()
+        };
+        let bounds = |world: World| {
+            world
+                .objects()
+                .iter()
+                .map(|object| format!("{:?}", object.bounding_box()))
+                .collect::<Vec<_>>()
+        };
+        let world = bounds(generator.generate(5));
+        assert_eq!(world, bounds(generator.generate(5)));
+        assert_ne!(world, bounds(generator.generate(6)));
+    }
+
+    #[test]
+    fn generates_only_what_is_asked_for() {
+        let heroes = 3;
+        let empty = Generator {
+            extent: 0,
+            ..Generator::default()
+        };
+        assert_eq!(empty.generate(0).objects().len(), 1 + heroes);
+
+        // Without glass, which takes two spheres, there is one sphere per cell
+        // outside the exclusions.
+        let generator = Generator {
+            extent: 3,
+            mix: MaterialMix {
+                diffuse: 1.0,
+                metal: 1.0,
+                glass: 0.0,
+            },
+            exclusions: vec![Exclusion {
+                center: Vec3::new(0.0, 0.0, 0.0),
+                radius: 1000.0,
+            }],
+            ..Generator::default()
+        };
+        assert_eq!(generator.generate(0).objects().len(), 1 + heroes);
+        let generator = Generator {
+            exclusions: Vec::new(),
+            ..generator
+        };
+        assert_eq!(generator.generate(0).objects().len(), 1 + 36 + heroes);
+    }
+
+    #[test]
+    fn rejects_invalid_parameters() {
+        assert!(Generator::default().validate().is_ok());
+        for generator in &[
+            Generator {
+                extent: MAX_EXTENT + 1,
+                ..Generator::default()
+            },
+            Generator {
+                radius: (0.3, 0.2),
+                ..Generator::default()
+            },
+            Generator {
+                radius: (0.0, 0.2),
+                ..Generator::default()
+            },
+            Generator {
+                mix: MaterialMix {
+                    diffuse: 0.0,
+                    metal: 0.0,
+                    glass: 0.0,
+                },
+                ..Generator::default()
+            },
+        ] {
+            assert!(generator.validate().is_err());
+        }
+    }
+}
@@ -1,8 +1,6 @@
 use std::ops;
 
-use rand::Rng;
-
-#[derive(Clone, Debug, Default)]
+#[derive(Clone, Debug, Default, PartialEq)]
 pub struct Vec3 {
     e: [f32; 3],
 }
@@ -12,19 +10,27 @@ impl Vec3 {
         Vec3 { e: [e0, e1, e2] }
     }
 
-    pub fn sample_in_unit_sphere() -> Vec3 {
-        let mut rng = rand::thread_rng();
-        let radius: f32 = rng.gen();
-        let azimuth: f32 = 2.0 * std::f32::consts::PI * rng.gen::<f32>();
-        let polar: f32 = std::f32::consts::PI * rng.gen::<f32>();
-        let x = radius * polar.sin() * azimuth.cos();
-        let y = radius * polar.sin() * azimuth.sin();
-        let z = radius * polar.cos();
-        Vec3 { e: [x, y, z] }
+    /// Returns two unit vectors that form an orthonormal basis together with
+    /// this one, which must be normalized (Duff et al. 2017).
+    pub fn orthonormal_basis(&self) -> (Vec3, Vec3) {
+        let sign = 1.0f32.copysign(self.e[2]);
+        let a = -1.0 / (sign + self.e[2]);
+        let b = self.e[0] * self.e[1] * a;
+        (
+            Vec3::new(
+                1.0 + sign * self.e[0] * self.e[0] * a,
+                sign * b,
+                -sign * self.e[0],
+            ),
+            Vec3::new(b, sign + self.e[1] * self.e[1] * a, -self.e[1]),
+        )
     }
 
-    pub fn gamma2_corrected(&self) -> Vec3 {
-        Vec3::new(self.e[0].sqrt(), self.e[1].sqrt(), self.e[2].sqrt())
+    /// Transforms a vector from the local frame whose z axis is `normal` into
+    /// world space.
+    pub fn from_local(local: &Vec3, normal: &Vec3) -> Vec3 {
+        let (s, t) = normal.orthonormal_basis();
+        local.x() * s + local.y() * t + local.z() * normal
     }
 
     pub fn reflect(v: &Vec3, normal: &Vec3) -> Vec3 {
@@ -69,6 +75,15 @@ impl Vec3 {
         self.e[2]
     }
 
+    /// The relative luminance of a linear Rec. 709 color.
+    pub fn luminance(&self) -> f32 {
+        0.2126 * self.e[0] + 0.7152 * self.e[1] + 0.0722 * self.e[2]
+    }
+
+    pub fn max_component(&self) -> f32 {
+        self.e[0].max(self.e[1]).max(self.e[2])
+    }
+
     pub fn length(&self) -> f32 {
         (self.e[0] * self.e[0] + self.e[1] * self.e[1] + self.e[2] * self.e[2]).sqrt()
     }
@@ -102,6 +117,14 @@ impl Vec3 {
     }
 }
 
+impl ops::Index<usize> for Vec3 {
+    type Output = f32;
+
+    fn index(&self, axis: usize) -> &f32 {
+        &self.e[axis]
+    }
+}
+
 impl ops::Neg for Vec3 {
     type Output = Vec3;
 
@@ -292,11 +315,12 @@ impl ops::DivAssign<f32> for Vec3 {
 pub struct Ray {
     a: Vec3,
     b: Vec3,
+    time: f32,
 }
 
 impl Ray {
-    pub fn new(a: Vec3, b: Vec3) -> Ray {
-        Ray { a, b }
+    pub fn new(a: Vec3, b: Vec3, time: f32) -> Ray {
+        Ray { a, b, time }
     }
 
     pub fn origin(&self) -> &Vec3 {
@@ -307,7 +331,49 @@ impl Ray {
         &self.b
     }
 
+    /// The time within the shutter interval at which the ray was cast.
+    pub fn time(&self) -> f32 {
+        self.time
+    }
+
     pub fn at_time(&self, t: f32) -> Vec3 {
         &self.a + t * &self.b
     }
 }
+
+/// An axis-aligned bounding box.
+#[derive(Clone, Debug)]
+pub struct Aabb {
+    min: Vec3,
+    max: Vec3,
+}
+
+impl Aabb {
+    pub fn new(min: Vec3, max: Vec3) -> Aabb {
+        Aabb { min, max }
+    }
+
+    pub fn min(&self) -> &Vec3 {
+        &self.min
+    }
+
+    pub fn max(&self) -> &Vec3 {
+        &self.max
+    }
+
+    /// The smallest box enclosing both boxes.
+    pub fn union(&self, other: &Aabb) -> Aabb {
+        Aabb {
+            min: Vec3::new(
+                self.min.x().min(other.min.x()),
+                self.min.y().min(other.min.y()),
+                self.min.z().min(other.min.z()),
+            ),
+            max: Vec3::new(
+                self.max.x().max(other.max.x()),
+                self.max.y().max(other.max.y()),
+                self.max.z().max(other.max.z()),
+            ),
+        }
+    }
+}
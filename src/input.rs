@@ -0,0 +1,220 @@
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+use std::path::Path;
+
+use crate::exr::read_exr;
+use crate::film::Film;
+use crate::geometry::Vec3;
+use crate::output::Format;
+use crate::png::read_png;
+use crate::tonemap::srgb_decode;
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Reads the whitespace-separated fields of a PPM or PFM header, skipping
+/// comments, and the single whitespace byte after the last of them.
+fn read_header(input: &mut dyn Read, fields: usize) -> io::Result<Vec<String>> {
+    let mut header = Vec::new();
+    let mut field = String::new();
+    let mut comment = false;
+    let mut byte = [0];
+    while header.len() < fields {
+        if input.read(&mut byte)? == 0 {
+            return Err(invalid("the image header is truncated".to_string()));
+        }
+        let c = byte[0] as char;
+        if comment {
+            comment = c != '\n';
+        } else if c == '#' && field.is_empty() {
+            comment = true;
+        } else if c.is_ascii_whitespace() {
+            if !field.is_empty() {
+                header.push(std::mem::take(&mut field));
+            }
+        } else {
+            field.push(c);
+        }
+    }
+    Ok(header)
+}
+
+fn parse<T: std::str::FromStr>(field: &str) -> io::Result<T> {
+    field
+        .parse()
+        .map_err(|_| invalid(format!("invalid image header field: {}", field)))
+}
+
+/// Reads a PPM image, in plain-text (P3) or binary (P6) form, decoding its
+/// sRGB values to linear radiance.
+pub fn read_ppm(input: &mut dyn Read) -> io::Result<Film> {
+    let header = read_header(input, 4)?;
+    let (width, height, max): (u32, u32, u32) =
+        (parse(&header[1])?, parse(&header[2])?, parse(&header[3])?);
+    if max == 0 || max > 65535 {
+        return Err(invalid(format!("invalid maximum value: {}", max)));
+    }
+    let count = 3 * (width * height) as usize;
+    let values: Vec<u32> = match header[0].as_str() {
+        "P3" => {
+            let mut text = String::new();
+            input.read_to_string(&mut text)?;
+            text.split_ascii_whitespace()
+                .take(count)
+                .map(parse)
+                .collect::<io::Result<_>>()?
+        }
+        "P6" => {
+            let size = if max < 256 { 1 } else { 2 };
+            let mut bytes = vec![0; count * size];
+            input.read_exact(&mut bytes)?;
+            bytes
+                .chunks(size)
+                .map(|value| value.iter().fold(0, |sum, byte| sum << 8 | *byte as u32))
+                .collect()
+        }
+        magic => return Err(invalid(format!("not a PPM image: {}", magic))),
+    };
+    if values.len() < count {
+        return Err(invalid("the image is truncated".to_string()));
+    }
+
+    let mut film = Film::new(width, height, &[]);
+    let decode = |value: u32| srgb_decode(value.min(max) as f32 / max as f32);
+    for ((x, y), rgb) in film.bounds().pixels().zip(values.chunks(3)) {
+        film.set_color(
+            x,
+            y,
+            &Vec3::new(decode(rgb[0]), decode(rgb[1]), decode(rgb[2])),
+        );
+    }
+    Ok(film)
+}
+
+/// Reads a PFM image, in color (PF) or grayscale (Pf) and either byte order.
+pub fn read_pfm(input: &mut dyn Read) -> io::Result<Film> {
+    let header = read_header(input, 4)?;
+    let channels = match header[0].as_str() {
+        "PF" => 3,
+        "Pf" => 1,
+        magic => return Err(invalid(format!("not a PFM image: {}", magic))),
+    };
+    let (width, height, scale): (u32, u32, f32) =
+        (parse(&header[1])?, parse(&header[2])?, parse(&header[3])?);
+    let mut bytes = vec![0; 4 * channels * (width * height) as usize];
+    input.read_exact(&mut bytes)?;
+    let values: Vec<f32> = bytes
+        .chunks(4)
+        .map(|value| {
+            let value = [value[0], value[1], value[2], value[3]];
+            if scale < 0.0 {
+                f32::from_le_bytes(value)
+            } else {
+                f32::from_be_bytes(value)
+            }
+        })
+        .collect();
+
+    // The rows are stored from the bottom of the image up.
+    let mut film = Film::new(width, height, &[]);
+    for (x, y) in film.bounds().pixels() {
+        let index = channels * ((height - 1 - y) * width + x) as usize;
+        let color = if channels == 3 {
+            Vec3::new(values[index], values[index + 1], values[index + 2])
+        } else {
+            Vec3::new(values[index], values[index], values[index])
+        };
+        film.set_color(x, y, &color);
+    }
+    Ok(film)
+}
+
+/// Loads the image at `path` in the format given by its extension.
+pub fn load(path: &Path) -> io::Result<Film> {
+    let format = Format::from_path(path)?;
+    let mut input = BufReader::new(File::open(path)?);
+    match format {
+        Format::Ppm => read_ppm(&mut input),
+        Format::Png => read_png(&mut input),
+        Format::Pfm => read_pfm(&mut input),
+        Format::Exr => read_exr(&mut input),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aov::Aov;
+    use crate::exr::{write_exr, Compression, ExrOptions, PixelType};
+    use crate::output::{write_pfm, write_ppm};
+    use crate::png::write_png;
+    use crate::tonemap::{ToneCurve, ToneMapper};
+
+    /// A film with a gradient of colors and normals.
+    fn gradient() -> Film {
+        let mut film = Film::new(7, 20, &[Aov::Normal, Aov::ObjectId]);
+        for (x, y) in film.bounds().pixels() {
+            let (u, v) = (x as f32 / 7.0, y as f32 / 20.0);
+            film.set_color(x, y, &Vec3::new(u, v, 4.0 * u * v));
+            film.set_aov(0, x, y, &Vec3::new(u, -v, 1.0));
+            film.set_aov(1, x, y, &Vec3::new((x + y) as f32, 0.0, 0.0));
+        }
+        film
+    }
+
+    fn assert_close(a: &Vec3, b: &Vec3, tolerance: f32) {
+        assert!((a - b).length() <= tolerance, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn reads_what_is_written() {
+        let film = gradient();
+        let mut pfm = Vec::new();
+        write_pfm(&film, &mut pfm).unwrap();
+        let mut ppm = Vec::new();
+        let tone_mapper = ToneMapper::new(0.0, None, ToneCurve::Clamp);
+        write_ppm(&film.without_aovs(), &tone_mapper, &mut ppm).unwrap();
+        let read_pfm = read_pfm(&mut pfm.as_slice()).unwrap();
+        let read_ppm = read_ppm(&mut ppm.as_slice()).unwrap();
+        let mut png = Vec::new();
+        write_png(&film, &tone_mapper, &mut png).unwrap();
+        let read_png = read_png(&mut png.as_slice()).unwrap();
+        for (x, y) in film.bounds().pixels() {
+            let color = film.pixel(x, y).color();
+            assert_close(&read_pfm.pixel(x, y).color(), &color, 0.0);
+            let clamped = Vec3::new(color.x(), color.y(), color.z().min(1.0));
+            assert_close(&read_ppm.pixel(x, y).color(), &clamped, 0.01);
+            assert_close(&read_png.pixel(x, y).color(), &clamped, 0.01);
+        }
+
+        for &pixel_type in &[PixelType::Half, PixelType::Float] {
+            for &compression in &[Compression::None, Compression::Zip] {
+                let options = ExrOptions {
+                    pixel_type,
+                    compression,
+                    attributes: Vec::new(),
+                };
+                let mut exr = Vec::new();
+                write_exr(&film, &options, &mut exr).unwrap();
+                let read = read_exr(&mut exr.as_slice()).unwrap();
+                assert_eq!(read.aovs(), film.aovs());
+                let tolerance = if pixel_type == PixelType::Half {
+                    2e-3
+                } else {
+                    0.0
+                };
+                for (x, y) in film.bounds().pixels() {
+                    assert_close(
+                        &read.pixel(x, y).color(),
+                        &film.pixel(x, y).color(),
+                        tolerance,
+                    );
+                    for index in 0..2 {
+                        assert_close(&read.aov(index, x, y), &film.aov(index, x, y), tolerance);
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,38 @@
+//! A path tracer after *Ray Tracing in One Weekend*.
+//!
+//! Build a [`Scene`], configure a [`Renderer`] with [`RenderSettings`] and
+//! render the scene into a [`Film`], which the `output` module can tone map
+//! and save.
+
+pub mod aov;
+pub mod bench;
+pub mod builtin;
+pub mod camera;
+pub mod checkpoint;
+pub mod compare;
+pub mod denoise;
+pub mod distributed;
+pub mod exr;
+pub mod film;
+pub mod filter;
+pub mod generator;
+pub mod geometry;
+pub mod input;
+pub mod material;
+pub mod object;
+pub mod output;
+pub mod png;
+pub mod progress;
+pub mod renderer;
+pub mod sampler;
+pub mod sampling;
+pub mod scene;
+pub mod server;
+pub mod texture;
+pub mod tonemap;
+
+pub use camera::Camera;
+pub use film::Film;
+pub use progress::{CancellationToken, RenderObserver};
+pub use renderer::{RenderOutput, RenderSettings, Renderer, StopReason};
+pub use scene::Scene;
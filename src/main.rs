@@ -1,157 +1,1219 @@
-use futures::future;
-use std::iter;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::thread;
+use std::time::Duration;
 
+use clap::{value_t, values_t, App, AppSettings, Arg, ArgMatches, SubCommand};
 use indicatif::ProgressBar;
-use itertools::Itertools;
-use rand::Rng;
-
-mod camera;
-mod geometry;
-mod material;
-mod object;
-mod texture;
-
-use camera::Camera;
-use geometry::{Ray, Vec3};
-use object::{Hittable, World};
-
-fn bounce(config: &Config, ray: &Ray, world: &World, depth: u32) -> Vec3 {
-    if let Some(hit) = world.hit(ray, 0.001, std::f32::MAX) {
-        if depth < config.max_depth {
-            if let Some((attenuation, scattered)) = hit.material.scatter(&ray, &hit) {
-                attenuation * bounce(config, &scattered, world, depth + 1)
-            } else {
-                Vec3::default()
-            }
-        } else {
-            Vec3::default()
+use serde_json::json;
+
+use ray_tracer::aov::Aov;
+use ray_tracer::bench;
+use ray_tracer::builtin::{Builtin, SceneDescription};
+use ray_tracer::checkpoint;
+use ray_tracer::compare::{self, Metrics};
+use ray_tracer::denoise::Denoiser;
+use ray_tracer::distributed;
+use ray_tracer::exr::{Attribute, Compression, ExrOptions, PixelType};
+use ray_tracer::filter::FilterKind;
+use ray_tracer::generator::{Exclusion, MaterialMix};
+use ray_tracer::geometry::Vec3;
+use ray_tracer::input;
+use ray_tracer::object::Hittable;
+use ray_tracer::output;
+use ray_tracer::renderer::UNLIMITED_SAMPLES;
+use ray_tracer::sampler::SamplerKind;
+use ray_tracer::server;
+use ray_tracer::tonemap::{ToneCurve, ToneMapper};
+use ray_tracer::{CancellationToken, RenderSettings, Renderer, StopReason};
+
+const EXIT_STATUS: &str = "EXIT STATUS:
+    0      Success.
+    1      The command failed, such as on an I/O error or an invalid job file.
+    2      The command line is invalid.
+    130    The render was interrupted. What was rendered so far is still saved.";
+
+/// Why a command failed, which decides its exit status.
+enum Failure {
+    /// The command line is invalid.
+    Usage(clap::Error),
+    /// The command failed with an error.
+    Error(io::Error),
+    /// The command failed, having reported why itself.
+    Reported,
+    /// The command was interrupted.
+    Interrupted,
+}
+
+impl From<clap::Error> for Failure {
+    fn from(error: clap::Error) -> Failure {
+        Failure::Usage(error)
+    }
+}
+
+impl From<io::Error> for Failure {
+    fn from(error: io::Error) -> Failure {
+        Failure::Error(error)
+    }
+}
+
+fn runtime() -> io::Result<tokio::runtime::Runtime> {
+    tokio::runtime::Builder::new()
+        .threaded_scheduler()
+        .enable_all()
+        .build()
+}
+
+/// The options choosing the scene.
+fn scene_args() -> Vec<Arg<'static, 'static>> {
+    vec![
+        Arg::with_name("builtin")
+            .help("The built-in scene to render.")
+            .long("builtin")
+            .takes_value(true)
+            .possible_values(Builtin::NAMES)
+            .default_value("random"),
+        Arg::with_name("list_scenes")
+            .help("Lists the built-in scenes and exits.")
+            .long("list_scenes")
+            .alias("list-scenes"),
+        Arg::with_name("scene")
+            .help(
+                "A JSON scene file, as checked by the validate command, to use instead of the \
+                 options describing the scene.",
+            )
+            .long("scene")
+            .takes_value(true),
+        Arg::with_name("scene_seed")
+            .help("The seed of the scene's randomness, if not the seed of the render.")
+            .long("scene_seed")
+            .takes_value(true),
+        Arg::with_name("grid_extent")
+            .help(
+                "The random scene's small spheres fill the cells from minus this to this \
+                 along x and z, at most 100.",
+            )
+            .long("grid_extent")
+            .takes_value(true)
+            .default_value("11"),
+        Arg::with_name("sphere_radius")
+            .help("The smallest and largest radius of the random scene's small spheres.")
+            .long("sphere_radius")
+            .takes_value(true)
+            .number_of_values(2)
+            .use_delimiter(true)
+            .value_names(&["min", "max"]),
+        Arg::with_name("material_mix")
+            .help(
+                "The relative frequencies of diffuse, metal and glass small spheres in the \
+                 random scene.",
+            )
+            .long("material_mix")
+            .takes_value(true)
+            .number_of_values(3)
+            .use_delimiter(true)
+            .value_names(&["diffuse", "metal", "glass"]),
+        Arg::with_name("exclusion")
+            .help(
+                "A sphere to keep clear of the random scene's small spheres, instead of the \
+                 one around the metal sphere. May be given more than once, or with a radius \
+                 of 0 to keep nothing clear.",
+            )
+            .long("exclusion")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(4)
+            .use_delimiter(true)
+            .value_names(&["x", "y", "z", "radius"]),
+    ]
+}
+
+/// Reads a scene file: a JSON object naming a built-in scene and its
+/// parameters, with the fields of a job's scene. The scene's seed defaults to
+/// `seed`.
+fn read_scene(path: &Path, seed: u64) -> Result<SceneDescription, String> {
+    let contents = fs::read(path).map_err(|error| error.to_string())?;
+    let json = serde_json::from_slice(&contents).map_err(|error| error.to_string())?;
+    SceneDescription::from_json(&json, seed)
+}
+
+/// The scene chosen on the command line, seeded by `--scene_seed` or else
+/// `seed`.
+fn parse_scene(matches: &ArgMatches, seed: u64) -> Result<SceneDescription, clap::Error> {
+    let seed = if matches.is_present("scene_seed") {
+        value_t!(matches, "scene_seed", u64)?
+    } else {
+        seed
+    };
+    if let Some(path) = matches.value_of("scene") {
+        return read_scene(Path::new(path), seed)
+            .map_err(|error| clap::Error::value_validation_auto(format!("{}: {}", path, error)));
+    }
+    let mut description = SceneDescription::new(value_t!(matches, "builtin", Builtin)?, seed);
+    let generator = &mut description.generator;
+    generator.extent = value_t!(matches, "grid_extent", u32)?;
+    if matches.is_present("sphere_radius") {
+        let radius = values_t!(matches, "sphere_radius", f32)?;
+        generator.radius = (radius[0], radius[1]);
+    }
+    if matches.is_present("material_mix") {
+        let mix = values_t!(matches, "material_mix", f32)?;
+        generator.mix = MaterialMix {
+            diffuse: mix[0],
+            metal: mix[1],
+            glass: mix[2],
+        };
+    }
+    if matches.is_present("exclusion") {
+        generator.exclusions = values_t!(matches, "exclusion", f32)?
+            .chunks(4)
+            .map(|zone| Exclusion {
+                center: Vec3::new(zone[0], zone[1], zone[2]),
+                radius: zone[3],
+            })
+            .collect();
+    }
+    generator
+        .validate()
+        .map_err(clap::Error::value_validation_auto)?;
+    Ok(description)
+}
+
+/// Prints the built-in scenes if asked to, returning whether it did.
+fn list_scenes(matches: &ArgMatches) -> bool {
+    if !matches.is_present("list_scenes") {
+        return false;
+    }
+    for builtin in Builtin::ALL {
+        println!("{:<18}{}", builtin.name(), builtin.about());
+    }
+    true
+}
+
+/// Parses a duration such as `90s`, `5m` or `2h`, or a plain number of seconds.
+fn parse_duration(value: &str) -> Result<Duration, String> {
+    let (number, unit) = match value.find(|c: char| c.is_ascii_alphabetic()) {
+        Some(index) => value.split_at(index),
+        None => (value, "s"),
+    };
+    let seconds = match unit {
+        "s" => 1.0,
+        "m" => 60.0,
+        "h" => 3600.0,
+        _ => return Err(format!("unknown unit of time: {}", unit)),
+    };
+    match number.parse::<f64>() {
+        Ok(number) if number >= 0.0 => Ok(Duration::from_secs_f64(number * seconds)),
+        _ => Err(format!("invalid duration: {}", value)),
+    }
+}
+
+/// The options of the settings that determine what a render computes.
+fn settings_args() -> Vec<Arg<'static, 'static>> {
+    vec![
+        Arg::with_name("width")
+            .help("The width of the output image in pixels.")
+            .short("w")
+            .long("width")
+            .takes_value(true)
+            .default_value("200"),
+        Arg::with_name("height")
+            .help("The height of the output image in pixels.")
+            .short("H")
+            .long("height")
+            .takes_value(true)
+            .default_value("150"),
+        Arg::with_name("samples")
+            .help("The number of samples per pixel.")
+            .short("s")
+            .long("samples")
+            .takes_value(true)
+            .default_value("10"),
+        Arg::with_name("time_limit")
+            .help(
+                "Stop rendering once another pass would overrun this much time, such as \
+                 90s, 5m or 2h. Without --samples, the sample budget is unlimited.",
+            )
+            .long("time_limit")
+            .alias("time-limit")
+            .takes_value(true),
+        Arg::with_name("target_noise")
+            .help(
+                "Stop rendering once the estimated relative error of the image falls below \
+                 this threshold. Without --samples, the sample budget is unlimited.",
+            )
+            .long("target_noise")
+            .alias("target-noise")
+            .takes_value(true),
+        Arg::with_name("pass_samples")
+            .help("The number of samples per pixel to take over the image in each pass.")
+            .long("pass_samples")
+            .takes_value(true)
+            .default_value("16"),
+        Arg::with_name("max_depth")
+            .help(
+                "The number of bounces at which paths are cut off if Russian roulette hasn't \
+                 ended them. Cutting paths off darkens the image, so keep this deep.",
+            )
+            .short("d")
+            .long("max_depth")
+            .takes_value(true)
+            .default_value("1000"),
+        Arg::with_name("roulette_depth")
+            .help("The number of bounces after which paths may be terminated by Russian roulette.")
+            .long("roulette_depth")
+            .takes_value(true)
+            .default_value("3"),
+        Arg::with_name("sampler")
+            .help("The sampler that generates sample values for each pixel.")
+            .long("sampler")
+            .takes_value(true)
+            .possible_values(SamplerKind::NAMES)
+            .default_value("sobol"),
+        Arg::with_name("seed")
+            .help("The seed of the scene's randomness and of the sampler's scrambling.")
+            .long("seed")
+            .takes_value(true)
+            .default_value("0"),
+        Arg::with_name("adaptive")
+            .help(
+                "Stop sampling a pixel once the relative standard error of its mean falls \
+                 below this threshold, treating --samples as the budget.",
+            )
+            .long("adaptive")
+            .takes_value(true),
+        Arg::with_name("min_samples")
+            .help("The number of samples per pixel before adaptive sampling may stop.")
+            .long("min_samples")
+            .takes_value(true)
+            .default_value("8"),
+        Arg::with_name("filter")
+            .help("The filter that reconstructs pixels from the samples around them.")
+            .long("filter")
+            .takes_value(true)
+            .possible_values(FilterKind::NAMES)
+            .default_value("box"),
+        Arg::with_name("filter_radius")
+            .help("The radius of the reconstruction filter in pixels.")
+            .long("filter_radius")
+            .takes_value(true),
+    ]
+}
+
+fn parse_settings(matches: &ArgMatches) -> Result<RenderSettings, clap::Error> {
+    let time_limit = match matches.value_of("time_limit") {
+        Some(value) => Some(parse_duration(value).map_err(clap::Error::value_validation_auto)?),
+        None => None,
+    };
+    let target_noise = if matches.is_present("target_noise") {
+        Some(value_t!(matches, "target_noise", f32)?)
+    } else {
+        None
+    };
+    let samples = if matches.occurrences_of("samples") == 0
+        && (time_limit.is_some() || target_noise.is_some())
+    {
+        UNLIMITED_SAMPLES
+    } else {
+        value_t!(matches, "samples", usize)?
+    };
+    let pass_samples = value_t!(matches, "pass_samples", usize)?;
+    if pass_samples == 0 {
+        return Err(clap::Error::value_validation_auto(
+            "The number of samples per pass must be positive.".to_string(),
+        ));
+    }
+    let adaptive_threshold = if matches.is_present("adaptive") {
+        Some(value_t!(matches, "adaptive", f32)?)
+    } else {
+        None
+    };
+    let filter_radius = if matches.is_present("filter_radius") {
+        let radius = value_t!(matches, "filter_radius", f32)?;
+        if !(radius.is_finite() && radius > 0.0) {
+            return Err(clap::Error::value_validation_auto(
+                "The filter radius must be a positive number of pixels.".to_string(),
+            ));
         }
+        Some(radius)
+    } else {
+        None
+    };
+    Ok(RenderSettings {
+        width: value_t!(matches, "width", u32)?,
+        height: value_t!(matches, "height", u32)?,
+        samples,
+        pass_samples,
+        time_limit,
+        target_noise,
+        max_depth: value_t!(matches, "max_depth", u32)?,
+        roulette_depth: value_t!(matches, "roulette_depth", u32)?,
+        sampler: value_t!(matches, "sampler", SamplerKind)?,
+        seed: value_t!(matches, "seed", u64)?,
+        adaptive_threshold,
+        min_samples: value_t!(matches, "min_samples", usize)?,
+        aovs: Vec::new(),
+        filter: value_t!(matches, "filter", FilterKind)?,
+        filter_radius,
+    })
+}
+
+/// The options of how radiance is mapped to display values.
+fn tone_args() -> Vec<Arg<'static, 'static>> {
+    vec![
+        Arg::with_name("exposure")
+            .help("The exposure adjustment in stops.")
+            .long("exposure")
+            .takes_value(true)
+            .allow_hyphen_values(true)
+            .default_value("0"),
+        Arg::with_name("white_balance")
+            .help("The color temperature in kelvin of the light to balance to white.")
+            .long("white_balance")
+            .takes_value(true),
+        Arg::with_name("tonemap")
+            .help("The tone curve that maps radiance to display values.")
+            .long("tonemap")
+            .takes_value(true)
+            .possible_values(ToneCurve::NAMES)
+            .default_value("clamp"),
+    ]
+}
+
+fn parse_tone_mapper(matches: &ArgMatches) -> Result<ToneMapper, clap::Error> {
+    let white_balance = if matches.is_present("white_balance") {
+        Some(value_t!(matches, "white_balance", f32)?)
     } else {
-        let direction = ray.direction().normalized();
-        let t = 0.5 * (direction.y() + 1.0);
-        (1.0 - t) * Vec3::new(1.0, 1.0, 1.0) + t * Vec3::new(0.5, 0.7, 1.0)
-    }
-}
-
-async fn pixel_color(
-    config: &Config,
-    camera: &Camera,
-    world: &World,
-    i: u32,
-    j: u32,
-) -> (u32, u32, u32) {
-    let rays = iter::repeat_with(|| {
-        let mut rng = rand::thread_rng();
-        let u = (i as f32 + rng.gen::<f32>()) / config.width as f32;
-        let v = (j as f32 + rng.gen::<f32>()) / config.height as f32;
-        (u, v)
+        None
+    };
+    Ok(ToneMapper::new(
+        value_t!(matches, "exposure", f32)?,
+        white_balance,
+        value_t!(matches, "tonemap", ToneCurve)?,
+    ))
+}
+
+/// The options of OpenEXR output.
+fn exr_args() -> Vec<Arg<'static, 'static>> {
+    vec![
+        Arg::with_name("exr_pixel_type")
+            .help("The precision of the channels of OpenEXR output.")
+            .long("exr_pixel_type")
+            .takes_value(true)
+            .possible_values(PixelType::NAMES)
+            .default_value("half"),
+        Arg::with_name("exr_compression")
+            .help("The compression of OpenEXR output.")
+            .long("exr_compression")
+            .takes_value(true)
+            .possible_values(Compression::NAMES)
+            .default_value("zip"),
+    ]
+}
+
+fn parse_exr_options(matches: &ArgMatches) -> Result<ExrOptions, clap::Error> {
+    Ok(ExrOptions {
+        pixel_type: value_t!(matches, "exr_pixel_type", PixelType)?,
+        compression: value_t!(matches, "exr_compression", Compression)?,
+        attributes: Vec::new(),
     })
-    .map(|(u, v)| camera.ray(u, v))
-    .take(config.samples);
+}
 
-    let colors =
-        future::join_all(rays.map(|ray| async move { bounce(&config, &ray, &world, 0) })).await;
+/// The options of the render command: the render settings, and what to do
+/// with the render.
+struct Options {
+    settings: RenderSettings,
+    scene: SceneDescription,
+    heatmap: Option<PathBuf>,
+    checkpoint: Option<PathBuf>,
+    resume: Option<PathBuf>,
+    preview: Option<PathBuf>,
+    output: Option<PathBuf>,
+    tone_mapper: ToneMapper,
+    exr_pixel_type: PixelType,
+    exr_compression: Compression,
+    denoise: bool,
+    workers: Vec<String>,
+    worker_timeout: Duration,
+}
 
-    let color: Vec3 = colors.into_iter().sum::<Vec3>() / config.samples as f32;
-    let color = color.gamma2_corrected();
+fn render_command() -> App<'static, 'static> {
+    SubCommand::with_name("render")
+        .about("Renders a scene.")
+        .args(&scene_args())
+        .args(&settings_args())
+        .arg(
+            Arg::with_name("output")
+                .help(
+                    "A path to write the image to, as PPM, PNG, PFM or OpenEXR by its extension, \
+                     instead of writing PPM to stdout.",
+                )
+                .short("o")
+                .long("output")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("aov")
+                .help(
+                    "Auxiliary images to render alongside the output, written as layers of \
+                     OpenEXR output or otherwise beside it as PFM.",
+                )
+                .long("aov")
+                .takes_value(true)
+                .multiple(true)
+                .use_delimiter(true)
+                .possible_values(Aov::NAMES)
+                .requires("output"),
+        )
+        .arg(
+            Arg::with_name("preview")
+                .help(
+                    "A path to write the image so far to after each pass, as PPM, PNG, PFM \
+                     or OpenEXR by its extension.",
+                )
+                .long("preview")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("checkpoint")
+                .help("A path to save the render's progress to after each pass.")
+                .long("checkpoint")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("resume")
+                .help(
+                    "A checkpoint to continue the render from, which is updated as the render \
+                     goes on unless --checkpoint is given.",
+                )
+                .long("resume")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("heatmap")
+                .help("A path to write a PPM image of the number of samples per pixel.")
+                .long("heatmap")
+                .takes_value(true),
+        )
+        .args(&tone_args())
+        .arg(
+            Arg::with_name("denoise")
+                .help(
+                    "Denoise the output, guided by albedo and normal AOVs, and write the noisy \
+                     image beside it.",
+                )
+                .long("denoise")
+                .requires("output"),
+        )
+        .args(&exr_args())
+        .arg(
+            Arg::with_name("workers")
+                .help("Addresses of worker processes to render the tiles on instead of locally.")
+                .long("workers")
+                .takes_value(true)
+                .multiple(true)
+                .use_delimiter(true),
+        )
+        .arg(
+            Arg::with_name("worker_timeout")
+                .help(
+                    "Give up on workers that take longer than this to render a tile, such as \
+                     90s or 5m.",
+                )
+                .long("worker_timeout")
+                .takes_value(true)
+                .default_value("5m"),
+        )
+}
 
-    const RGB_SCALAR: f32 = 255.99;
-    let ir = (RGB_SCALAR * color.r()) as u32;
-    let ig = (RGB_SCALAR * color.g()) as u32;
-    let ib = (RGB_SCALAR * color.b()) as u32;
-    (ir, ig, ib)
+fn render(matches: &ArgMatches) -> Result<(), Failure> {
+    if list_scenes(matches) {
+        return Ok(());
+    }
+    let mut settings = parse_settings(matches)?;
+    settings.aovs = if matches.is_present("aov") {
+        values_t!(matches, "aov", Aov)?
+    } else {
+        Vec::new()
+    };
+    let denoise = matches.is_present("denoise");
+    if denoise {
+        for feature in &[Aov::Albedo, Aov::Normal] {
+            if !settings.aovs.contains(feature) {
+                settings.aovs.push(*feature);
+            }
+        }
+    }
+    let exr = parse_exr_options(matches)?;
+    let options = Options {
+        scene: parse_scene(matches, settings.seed)?,
+        settings,
+        heatmap: matches.value_of("heatmap").map(PathBuf::from),
+        checkpoint: matches.value_of("checkpoint").map(PathBuf::from),
+        resume: matches.value_of("resume").map(PathBuf::from),
+        preview: matches.value_of("preview").map(PathBuf::from),
+        output: matches.value_of("output").map(PathBuf::from),
+        tone_mapper: parse_tone_mapper(matches)?,
+        exr_pixel_type: exr.pixel_type,
+        exr_compression: exr.compression,
+        denoise,
+        workers: matches
+            .values_of("workers")
+            .map(|values| values.map(String::from).collect())
+            .unwrap_or_default(),
+        worker_timeout: parse_duration(matches.value_of("worker_timeout").unwrap())
+            .map_err(clap::Error::value_validation_auto)?,
+    };
+
+    match runtime()?.block_on(render_async(options))? {
+        Some(StopReason::Cancelled) => Err(Failure::Interrupted),
+        _ => Ok(()),
+    }
 }
 
-async fn async_main(config: Config) {
+async fn render_async(options: Options) -> io::Result<Option<StopReason>> {
+    let settings = &options.settings;
+    let scene = options
+        .scene
+        .build(settings.width as f32 / settings.height as f32);
+    let tone_mapper = &options.tone_mapper;
+    let checkpoint_key = settings.checkpoint_key();
+    let checkpoint_path = options.checkpoint.as_ref().or(options.resume.as_ref());
+
+    let cancellation = CancellationToken::new();
+    let interrupt = cancellation.clone();
+    tokio::spawn(async move {
+        if tokio::signal::ctrl_c().await.is_ok() {
+            eprintln!("\nFinishing up after the last complete pass, or interrupt again to quit...");
+            interrupt.cancel();
+            if tokio::signal::ctrl_c().await.is_ok() {
+                process::exit(130);
+            }
+        }
+    });
+
+    let mut renderer = Renderer::new(settings.clone())
+        .observer(ProgressBar::new(0))
+        .cancellation(cancellation)
+        .workers(options.workers.clone())
+        .worker_timeout(options.worker_timeout)
+        .after_pass(|film, passes| {
+            if let Some(path) = &options.preview {
+                let exr = ExrOptions {
+                    pixel_type: options.exr_pixel_type,
+                    compression: options.exr_compression,
+                    attributes: Vec::new(),
+                };
+                output::save_preview(film, tone_mapper, &exr, path)?;
+            }
+            if let Some(path) = checkpoint_path {
+                checkpoint::save(&checkpoint_key, passes, film, path)?;
+            }
+            Ok(())
+        });
+    if let Some(path) = &options.resume {
+        let checkpoint = checkpoint::load(path)?;
+        eprintln!(
+            "Resuming from {} at pass {}...",
+            path.display(),
+            checkpoint.passes + 1
+        );
+        renderer = renderer.resume(checkpoint)?;
+    }
+
     eprintln!("Ray tracing...");
+    let rendered = renderer.render(&scene).await?;
+    let mut film = rendered.film;
+    let render_time = rendered.elapsed;
+
+    let average_samples = film.average_samples();
+    match rendered.stopped {
+        Some(StopReason::TargetNoise) => eprintln!("Stopped early as the target noise was met."),
+        Some(StopReason::TimeLimit) => eprintln!("Stopped early as the time limit was reached."),
+        Some(StopReason::Cancelled) => eprintln!("Stopped early as the render was interrupted."),
+        None => {}
+    }
+    eprintln!(
+        "Rendered {:.2} samples per pixel on average in {:.1}s, with an estimated error of {:.4}.",
+        average_samples,
+        render_time.as_secs_f32(),
+        film.estimated_error()
+    );
+    if let Some(path) = &options.heatmap {
+        eprintln!("Writing out sample count heatmap to {}...", path.display());
+        let mut out = BufWriter::new(File::create(path)?);
+        output::write_heatmap(&film, &mut out)?;
+        out.flush()?;
+    }
+
+    match &options.output {
+        Some(path) => {
+            eprintln!("Writing out image to {}...", path.display());
+            let exr = ExrOptions {
+                pixel_type: options.exr_pixel_type,
+                compression: options.exr_compression,
+                attributes: vec![
+                    (
+                        "cameraPosition".into(),
+                        Attribute::V3f(scene.camera().origin().clone()),
+                    ),
+                    (
+                        "worldToCamera".into(),
+                        Attribute::M44f(scene.camera().world_to_camera()),
+                    ),
+                    (
+                        "verticalFov".into(),
+                        Attribute::Float(scene.camera().vertical_fov()),
+                    ),
+                    (
+                        "lensRadius".into(),
+                        Attribute::Float(scene.camera().lens_radius()),
+                    ),
+                    (
+                        "samplesPerPixel".into(),
+                        Attribute::Int(options.settings.samples as i32),
+                    ),
+                    (
+                        "averageSamplesPerPixel".into(),
+                        Attribute::Float(average_samples),
+                    ),
+                    (
+                        "renderTime".into(),
+                        Attribute::Float(render_time.as_secs_f32()),
+                    ),
+                    (
+                        "software".into(),
+                        Attribute::String(format!("ray-tracer {}", env!("CARGO_PKG_VERSION"))),
+                    ),
+                ],
+            };
+            if options.denoise {
+                let noisy_path = output::noisy_path(path);
+                eprintln!("Writing out noisy image to {}...", noisy_path.display());
+                output::save(&film.without_aovs(), tone_mapper, &exr, &noisy_path)?;
+                eprintln!("Denoising...");
+                film = Denoiser::default().apply(&film);
+            }
+            let written = output::save(&film, tone_mapper, &exr, path)?;
+            for aov_path in &written[1..] {
+                eprintln!("Wrote out AOV to {}.", aov_path.display());
+            }
+        }
+        None => {
+            eprintln!("Writing out pixel RGB values...");
+            let stdout = io::stdout();
+            let mut out = BufWriter::new(stdout.lock());
+            output::write_ppm(&film, tone_mapper, &mut out)?;
+            out.flush()?;
+        }
+    }
+    Ok(rendered.stopped)
+}
+
+fn info_command() -> App<'static, 'static> {
+    SubCommand::with_name("info")
+        .about("Describes a scene: its primitives, their bounds and the camera.")
+        .args(&scene_args())
+        .arg(
+            Arg::with_name("seed")
+                .help("The seed of the scene's randomness.")
+                .long("seed")
+                .takes_value(true)
+                .default_value("0"),
+        )
+}
+
+fn format_point(point: &Vec3) -> String {
+    format!("({:.2}, {:.2}, {:.2})", point.x(), point.y(), point.z())
+}
+
+fn info(matches: &ArgMatches) -> Result<(), Failure> {
+    if list_scenes(matches) {
+        return Ok(());
+    }
+    let settings = RenderSettings::default();
+    let description = parse_scene(matches, value_t!(matches, "seed", u64)?)?;
+    let scene = description.build(settings.width as f32 / settings.height as f32);
+    let objects = scene.world().objects();
+    let mut kinds = std::collections::BTreeMap::new();
+    for object in objects {
+        *kinds.entry(object.kind()).or_insert(0) += 1;
+    }
 
-    let world = World::random();
-    let camera = Camera::from_fov(
-        Vec3::new(4.0, 1.5, -3.0),
-        Vec3::new(0.0, -0.5, 1.0),
-        Vec3::new(0.0, 1.0, 0.0),
-        90.0,
-        config.width as f32 / config.height as f32,
+    println!(
+        "Scene: {}, seed {}: {}",
+        description.builtin,
+        description.seed,
+        description.builtin.about()
     );
+    println!("Primitives: {}", objects.len());
+    for (kind, count) in &kinds {
+        println!("  {}: {}", kind, count);
+    }
+    match scene.world().bounding_box() {
+        Some(bounds) => println!(
+            "Bounds: {} to {}",
+            format_point(bounds.min()),
+            format_point(bounds.max())
+        ),
+        None => println!("Bounds: none"),
+    }
+    // The world is a flat list, so there is no hierarchy to describe.
+    println!("Acceleration: none, every ray is tested against every primitive");
+    let camera = scene.camera();
+    println!(
+        "Camera: at {}, vertical field of view {:.1} degrees, lens radius {:.3}",
+        format_point(camera.origin()),
+        camera.vertical_fov(),
+        camera.lens_radius()
+    );
+    Ok(())
+}
 
-    let futures = (0..config.height)
-        .rev()
-        .cartesian_product(0..config.width)
-        .map(|(j, i)| pixel_color(&config, &camera, &world, i, j));
+fn bench_command() -> App<'static, 'static> {
+    SubCommand::with_name("bench")
+        .about(
+            "Times the arithmetic, intersections and scattering that renders spend their time \
+             on, and whole renders of a scene, and prints the timings as JSON.",
+        )
+        .args(&scene_args())
+        .args(&settings_args())
+        .arg(
+            Arg::with_name("iterations")
+                .help("The number of times to run each benchmark, after one run to warm up.")
+                .long("iterations")
+                .takes_value(true)
+                .default_value("3"),
+        )
+        .arg(
+            Arg::with_name("render_only")
+                .help("Times only the renders of the scene.")
+                .long("render_only"),
+        )
+}
 
-    let progress = ProgressBar::new((config.width * config.height).into());
-    let colors = future::join_all(futures.map(|future| async {
-        let result = future.await;
-        progress.inc(1);
-        result
-    }))
-    .await;
-    progress.finish();
+fn bench(matches: &ArgMatches) -> Result<(), Failure> {
+    if list_scenes(matches) {
+        return Ok(());
+    }
+    let settings = parse_settings(matches)?;
+    let iterations = value_t!(matches, "iterations", usize)?;
+    if iterations == 0 {
+        return Err(Failure::Usage(clap::Error::value_validation_auto(
+            "The number of iterations must be positive.".to_string(),
+        )));
+    }
+    let description = parse_scene(matches, settings.seed)?;
+    let scene = description.build(settings.width as f32 / settings.height as f32);
+    let report = |measurement: &bench::Measurement| {
+        eprintln!(
+            "{}: {:.3e} {} per second, best {:.3}s, mean {:.3}s",
+            measurement.name,
+            measurement.rate(),
+            measurement.unit,
+            measurement.best(),
+            measurement.mean()
+        );
+    };
 
-    eprintln!("Writing out pixel RGB values...");
-    println!("P3\n{} {}\n255", config.width, config.height);
-    for (r, g, b) in colors.iter() {
-        println!("{} {} {}", r, g, b);
+    let mut measurements = Vec::new();
+    if !matches.is_present("render_only") {
+        for measurement in bench::micro(iterations) {
+            report(&measurement);
+            measurements.push(measurement);
+        }
     }
+    let render = bench::render(&scene, &settings, iterations)?;
+    report(&render);
+    measurements.push(render);
+
+    let results = json!({
+        "version": env!("CARGO_PKG_VERSION"),
+        "iterations": iterations,
+        "render": {
+            "scene": description.to_json(),
+            "width": settings.width,
+            "height": settings.height,
+            "samples": settings.samples,
+            "seed": settings.seed,
+        },
+        "benchmarks": measurements.iter().map(|measurement| measurement.to_json()).collect::<Vec<_>>(),
+    });
+    println!("{}", serde_json::to_string_pretty(&results).unwrap());
+    Ok(())
 }
 
-struct Config {
-    width: u32,
-    height: u32,
-    samples: usize,
-    max_depth: u32,
+fn convert_command() -> App<'static, 'static> {
+    SubCommand::with_name("convert")
+        .about("Converts an image between the PPM, PNG, PFM and OpenEXR formats.")
+        .arg(
+            Arg::with_name("input")
+                .help("The image to convert, in the format given by its extension.")
+                .required(true),
+        )
+        .arg(
+            Arg::with_name("output")
+                .help(
+                    "The path to write the image to, in the format given by its extension. \
+                     AOV layers of OpenEXR images are written beside it as PFM unless it is \
+                     OpenEXR itself.",
+                )
+                .required(true),
+        )
+        .args(&tone_args())
+        .args(&exr_args())
+}
+
+fn convert(matches: &ArgMatches) -> Result<(), Failure> {
+    let tone_mapper = parse_tone_mapper(matches)?;
+    let exr = parse_exr_options(matches)?;
+    let (input, output) = (
+        Path::new(matches.value_of("input").unwrap()),
+        Path::new(matches.value_of("output").unwrap()),
+    );
+    // Check the output format before reading the input.
+    output::Format::from_path(output)?;
+    let film = input::load(input)?;
+    for path in output::save(&film, &tone_mapper, &exr, output)? {
+        eprintln!("Wrote out {}.", path.display());
+    }
+    Ok(())
 }
 
-fn main() -> Result<(), clap::Error> {
-    use clap::{value_t, App, Arg};
-    let matches = App::new("Rust Ray Tracer")
+fn compare_command() -> App<'static, 'static> {
+    SubCommand::with_name("compare")
+        .about("Measures how an image differs from a reference image.")
+        .arg(
+            Arg::with_name("image")
+                .help("The image to measure, in the format given by its extension.")
+                .required(true),
+        )
+        .arg(
+            Arg::with_name("reference")
+                .help("The reference image, in the format given by its extension.")
+                .required(true),
+        )
+        .arg(
+            Arg::with_name("diff")
+                .help(
+                    "A path to write a false-color image of each pixel's difference in \
+                     luminance to, going from black through red and yellow to white, in the \
+                     format given by its extension.",
+                )
+                .long("diff")
+                .takes_value(true),
+        )
         .arg(
-            Arg::with_name("width")
-                .help("The width of the output image in pixels.")
-                .short("w")
-                .long("width")
+            Arg::with_name("diff_max")
+                .help(
+                    "The difference in luminance shown as white in the difference image, \
+                     instead of the largest difference.",
+                )
+                .long("diff_max")
                 .takes_value(true)
-                .default_value("200"),
+                .requires("diff"),
+        )
+        .args(&exr_args())
+}
+
+fn compare(matches: &ArgMatches) -> Result<(), Failure> {
+    let diff_max = if matches.is_present("diff_max") {
+        let max = value_t!(matches, "diff_max", f32)?;
+        if max.is_nan() || max <= 0.0 {
+            return Err(Failure::Usage(clap::Error::value_validation_auto(
+                "The difference shown as white must be positive.".to_string(),
+            )));
+        }
+        Some(max)
+    } else {
+        None
+    };
+    let exr = parse_exr_options(matches)?;
+    let image = input::load(Path::new(matches.value_of("image").unwrap()))?;
+    let reference = input::load(Path::new(matches.value_of("reference").unwrap()))?;
+    let metrics = Metrics::new(&image, &reference)?;
+    println!("MSE     {:.6e}", metrics.mse);
+    println!("PSNR    {:.2} dB", metrics.psnr);
+    println!("relMSE  {:.6e}", metrics.relative_mse);
+    println!("SSIM    {:.4}", metrics.ssim);
+
+    if let Some(path) = matches.value_of("diff") {
+        // Identical images have no largest difference to scale by.
+        let max = match diff_max {
+            Some(max) => max,
+            None if metrics.max_difference > 0.0 => metrics.max_difference,
+            None => 1.0,
+        };
+        let diff = compare::difference_image(&image, &reference, max);
+        let tone_mapper = ToneMapper::new(0.0, None, ToneCurve::Clamp);
+        output::save(&diff, &tone_mapper, &exr, Path::new(path))?;
+        eprintln!(
+            "Wrote out {}, in which white is a difference of {:e}.",
+            path, max
+        );
+    }
+    Ok(())
+}
+
+fn validate_command() -> App<'static, 'static> {
+    SubCommand::with_name("validate")
+        .about(
+            "Checks scene files, as given to --scene, such as \
+             {\"name\": \"random\", \"seed\": 3, \"extent\": 5}.",
         )
         .arg(
-            Arg::with_name("height")
-                .help("The height of the output image in pixels.")
-                .short("h")
-                .long("height")
+            Arg::with_name("files")
+                .help("The scene files to check.")
+                .required(true)
+                .multiple(true),
+        )
+}
+
+/// Checks a scene file, returning a summary of it.
+fn validate_file(path: &Path) -> Result<String, String> {
+    let description = read_scene(path, 0)?;
+    let settings = RenderSettings::default();
+    let scene = description.build(settings.width as f32 / settings.height as f32);
+    let bounds = scene.world().bounding_box().ok_or("the scene is empty")?;
+    let finite =
+        |point: &Vec3| point.x().is_finite() && point.y().is_finite() && point.z().is_finite();
+    if !finite(bounds.min()) || !finite(bounds.max()) {
+        return Err("the scene isn't bounded".to_string());
+    }
+    Ok(format!(
+        "the {} scene with seed {}, {} primitives",
+        description.builtin,
+        description.seed,
+        scene.world().objects().len()
+    ))
+}
+
+fn validate(matches: &ArgMatches) -> Result<(), Failure> {
+    let mut valid = true;
+    for file in matches.values_of("files").unwrap() {
+        match validate_file(Path::new(file)) {
+            Ok(summary) => println!("{}: ok, {}", file, summary),
+            Err(error) => {
+                println!("{}: {}", file, error);
+                valid = false;
+            }
+        }
+    }
+    if valid {
+        Ok(())
+    } else {
+        Err(Failure::Reported)
+    }
+}
+
+fn serve_command() -> App<'static, 'static> {
+    SubCommand::with_name("serve")
+        .about("Serves an HTTP API for starting, watching and cancelling renders.")
+        .arg(
+            Arg::with_name("listen")
+                .help("The address to listen for HTTP requests on.")
+                .long("listen")
                 .takes_value(true)
-                .default_value("150"),
+                .default_value("127.0.0.1:8080"),
         )
+}
+
+fn serve(matches: &ArgMatches) -> Result<(), Failure> {
+    let listener = TcpListener::bind(matches.value_of("listen").unwrap())?;
+    eprintln!("Listening on http://{}...", listener.local_addr()?);
+    runtime()?.block_on(server::serve(listener))?;
+    Ok(())
+}
+
+fn worker_command() -> App<'static, 'static> {
+    SubCommand::with_name("worker")
+        .about("Renders tiles for coordinators that connect to it.")
         .arg(
-            Arg::with_name("samples")
-                .help("The number of samples per pixel.")
-                .short("s")
-                .long("samples")
+            Arg::with_name("listen")
+                .help("The address to listen for coordinators on.")
+                .long("listen")
                 .takes_value(true)
-                .default_value("10"),
+                .default_value("127.0.0.1:7878"),
         )
         .arg(
-            Arg::with_name("max_depth")
-                .help("The maximum number of bounces per ray.")
-                .short("d")
-                .long("max_depth")
+            Arg::with_name("idle_timeout")
+                .help(
+                    "Hang up on coordinators that send nothing for this long, such as 90s \
+                     or 5m.",
+                )
+                .long("idle_timeout")
                 .takes_value(true)
-                .default_value("50"),
+                .default_value("5m"),
         )
-        .get_matches();
+}
 
-    let width = value_t!(matches, "width", u32)?;
-    let height = value_t!(matches, "height", u32)?;
-    let samples = value_t!(matches, "samples", usize)?;
-    let max_depth = value_t!(matches, "max_depth", u32)?;
+/// Renders tiles for coordinators that connect, each on a thread of its own.
+fn worker(matches: &ArgMatches) -> Result<(), Failure> {
+    let idle_timeout = parse_duration(matches.value_of("idle_timeout").unwrap())
+        .map_err(clap::Error::value_validation_auto)?;
+    let listener = TcpListener::bind(matches.value_of("listen").unwrap())?;
+    eprintln!("Listening on {}...", listener.local_addr()?);
+    for stream in listener.incoming() {
+        let stream = stream?;
+        let peer = stream.peer_addr()?;
+        eprintln!("Rendering for {}...", peer);
+        thread::spawn(move || match distributed::serve(stream, idle_timeout) {
+            Ok(()) => eprintln!("Done rendering for {}.", peer),
+            Err(error) => eprintln!("Lost {}: {}", peer, error),
+        });
+    }
+    Ok(())
+}
 
-    let config = Config {
-        width,
-        height,
-        samples,
-        max_depth,
+fn app() -> App<'static, 'static> {
+    App::new("Rust Ray Tracer")
+        .bin_name("ray-tracer")
+        .version(env!("CARGO_PKG_VERSION"))
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .setting(AppSettings::VersionlessSubcommands)
+        .after_help(EXIT_STATUS)
+        .subcommand(render_command())
+        .subcommand(info_command())
+        .subcommand(bench_command())
+        .subcommand(convert_command())
+        .subcommand(compare_command())
+        .subcommand(validate_command())
+        .subcommand(serve_command())
+        .subcommand(worker_command())
+}
+
+fn main() {
+    let matches = match app().get_matches_safe() {
+        Ok(matches) => matches,
+        // Help and version information aren't errors.
+        Err(error) if !error.use_stderr() => {
+            println!("{}", error.message);
+            process::exit(0);
+        }
+        Err(error) => {
+            eprintln!("{}", error.message);
+            process::exit(2);
+        }
+    };
+    let result = match matches.subcommand() {
+        ("render", Some(matches)) => render(matches),
+        ("info", Some(matches)) => info(matches),
+        ("bench", Some(matches)) => bench(matches),
+        ("convert", Some(matches)) => convert(matches),
+        ("compare", Some(matches)) => compare(matches),
+        ("validate", Some(matches)) => validate(matches),
+        ("serve", Some(matches)) => serve(matches),
+        ("worker", Some(matches)) => worker(matches),
+        _ => unreachable!("clap requires a subcommand"),
     };
+    let status = match result {
+        Ok(()) => 0,
+        Err(Failure::Usage(error)) => {
+            eprintln!("{}", error.message);
+            2
+        }
+        Err(Failure::Error(error)) => {
+            eprintln!("error: {}", error);
+            1
+        }
+        Err(Failure::Reported) => 1,
+        Err(Failure::Interrupted) => 130,
+    };
+    process::exit(status);
+}
 
-    let mut runtime = dbg!(tokio::runtime::Builder::new().threaded_scheduler()).build()?;
-    runtime.block_on(async_main(config));
-    Ok(())
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_durations() {
+        assert_eq!(parse_duration("90"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_duration("1.5s"), Ok(Duration::from_millis(1500)));
+        assert_eq!(parse_duration("5m"), Ok(Duration::from_secs(300)));
+        assert_eq!(parse_duration("2h"), Ok(Duration::from_secs(7200)));
+        assert!(parse_duration("5d").is_err());
+        assert!(parse_duration("m").is_err());
+        assert!(parse_duration("-1s").is_err());
+    }
+
+    #[test]
+    fn short_h_is_help_and_not_height() {
+        let matches = app()
+            .get_matches_from_safe(["ray-tracer", "render", "-w", "32", "-H", "24"])
+            .unwrap();
+        let settings = parse_settings(matches.subcommand_matches("render").unwrap()).unwrap();
+        assert_eq!((settings.width, settings.height), (32, 24));
+
+        let help = app()
+            .get_matches_from_safe(["ray-tracer", "render", "-h"])
+            .unwrap_err();
+        assert_eq!(help.kind, clap::ErrorKind::HelpDisplayed);
+        assert!(app().get_matches_from_safe(["ray-tracer"]).is_err());
+    }
+
+    #[test]
+    fn filter_radii_must_be_finite_and_positive() {
+        let parse = |radius: &str| {
+            let option = format!("--filter_radius={}", radius);
+            let matches = app()
+                .get_matches_from_safe(["ray-tracer", "render", &option])
+                .unwrap();
+            parse_settings(matches.subcommand_matches("render").unwrap())
+                .map(|settings| settings.filter_radius)
+        };
+        assert_eq!(parse("1.5").unwrap(), Some(1.5));
+        for radius in &["0", "-1", "NaN", "inf"] {
+            assert!(parse(radius).is_err(), "{}", radius);
+        }
+    }
+
+    #[test]
+    fn checks_and_reads_scene_files() {
+        let directory = std::env::temp_dir().join(format!("scenes-{}", process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let write = |name: &str, contents: &str| {
+            let path = directory.join(name);
+            fs::write(&path, contents).unwrap();
+            path
+        };
+        let valid = write(
+            "valid.json",
+            r#"{"name": "random", "seed": 3, "extent": 2}"#,
+        );
+        assert!(validate_file(&valid).unwrap().contains("seed 3"));
+        for contents in &["not json", r#"{"name": "teapot"}"#, r#"{"extent": 1000}"#] {
+            let invalid = write("invalid.json", contents);
+            assert!(validate_file(&invalid).is_err(), "{}", contents);
+        }
+
+        let scene = format!("--scene={}", valid.display());
+        let matches = app()
+            .get_matches_from_safe(["ray-tracer", "info", &scene])
+            .unwrap();
+        let description = parse_scene(matches.subcommand_matches("info").unwrap(), 0).unwrap();
+        assert_eq!(description.seed, 3);
+        assert_eq!(description.generator.extent, 2);
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn stopping_options_take_hyphens_too() {
+        let matches = app()
+            .get_matches_from_safe([
+                "ray-tracer",
+                "render",
+                "--time-limit",
+                "5m",
+                "--target-noise",
+                "0.1",
+            ])
+            .unwrap();
+        let settings = parse_settings(matches.subcommand_matches("render").unwrap()).unwrap();
+        assert_eq!(settings.time_limit, Some(Duration::from_secs(300)));
+        assert_eq!(settings.target_noise, Some(0.1));
+    }
 }
@@ -1,9 +1,52 @@
+use std::f32::consts::PI;
+
 use crate::geometry::{Ray, Vec3};
 use crate::object::Hit;
+use crate::sampling;
 use crate::texture::Texture;
 
+/// The broad kinds of material, used to label and split render passes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MaterialKind {
+    Lambertian,
+    Metal,
+    Dielectric,
+    Emissive,
+    Isotropic,
+}
+
+impl MaterialKind {
+    pub fn is_specular(self) -> bool {
+        self == MaterialKind::Metal || self == MaterialKind::Dielectric
+    }
+}
+
 pub trait Material {
-    fn scatter(&self, ray: &Ray, hit: &Hit) -> Option<(Vec3, Ray)>;
+    fn kind(&self) -> MaterialKind;
+
+    /// Samples an outgoing ray using the sample values `uc` and `u`, which the
+    /// caller draws from its sampler for each bounce.
+    fn scatter(&self, ray: &Ray, hit: &Hit, uc: f32, u: (f32, f32)) -> Option<(Vec3, Ray)>;
+
+    /// The BSDF times the cosine of the angle `direction` makes with the
+    /// normal: how much of the light arriving from `direction` is scattered
+    /// back along `ray`, per unit solid angle. `scatter` weights the rays it
+    /// samples by this over `pdf`. Zero for materials that only scatter into
+    /// discrete directions.
+    fn eval(&self, _ray: &Ray, _hit: &Hit, _direction: &Vec3) -> Vec3 {
+        Vec3::default()
+    }
+
+    /// The density over solid angle with which `scatter` samples `direction`,
+    /// or zero for materials that only scatter into discrete directions.
+    fn pdf(&self, _ray: &Ray, _hit: &Hit, _direction: &Vec3) -> f32 {
+        0.0
+    }
+
+    /// The radiance the material emits at the hit.
+    fn emitted(&self, _hit: &Hit) -> Vec3 {
+        Vec3::default()
+    }
 }
 
 pub struct Lambertian {
@@ -17,12 +60,28 @@ impl Lambertian {
 }
 
 impl Material for Lambertian {
-    fn scatter(&self, _ray: &Ray, hit: &Hit) -> Option<(Vec3, Ray)> {
-        let target = &hit.p + &hit.normal + Vec3::sample_in_unit_sphere();
-        let scattered = Ray::new(hit.p.clone(), target - &hit.p);
-        let attenuation = self.albedo.value(0.0, 0.0, &hit.p);
+    fn kind(&self) -> MaterialKind {
+        MaterialKind::Lambertian
+    }
+
+    fn scatter(&self, ray: &Ray, hit: &Hit, _uc: f32, u: (f32, f32)) -> Option<(Vec3, Ray)> {
+        let (local, _pdf) = sampling::cosine_hemisphere(u);
+        let scattered = Ray::new(
+            hit.p.clone(),
+            Vec3::from_local(&local, &hit.normal),
+            ray.time(),
+        );
+        let attenuation = self.albedo.value(hit.u, hit.v, &hit.p);
         Some((attenuation, scattered))
     }
+
+    fn eval(&self, ray: &Ray, hit: &Hit, direction: &Vec3) -> Vec3 {
+        self.pdf(ray, hit, direction) * self.albedo.value(hit.u, hit.v, &hit.p)
+    }
+
+    fn pdf(&self, _ray: &Ray, hit: &Hit, direction: &Vec3) -> f32 {
+        sampling::cosine_hemisphere_pdf(direction.normalized().dot(&hit.normal))
+    }
 }
 
 pub struct Metal {
@@ -37,12 +96,14 @@ impl Metal {
 }
 
 impl Material for Metal {
-    fn scatter(&self, ray: &Ray, hit: &Hit) -> Option<(Vec3, Ray)> {
+    fn kind(&self) -> MaterialKind {
+        MaterialKind::Metal
+    }
+
+    fn scatter(&self, ray: &Ray, hit: &Hit, uc: f32, u: (f32, f32)) -> Option<(Vec3, Ray)> {
         let reflected = Vec3::reflect(&ray.direction().normalized(), &hit.normal);
-        let scattered = Ray::new(
-            hit.p.clone(),
-            reflected + self.fuzz * Vec3::sample_in_unit_sphere(),
-        );
+        let (fuzz, _pdf) = sampling::uniform_ball(u, uc);
+        let scattered = Ray::new(hit.p.clone(), reflected + self.fuzz * fuzz, ray.time());
         let attenuation = self.albedo.clone();
         if scattered.direction().dot(&hit.normal) > 0.0 {
             Some((attenuation, scattered))
@@ -50,6 +111,30 @@ impl Material for Metal {
             None
         }
     }
+
+    fn eval(&self, ray: &Ray, hit: &Hit, direction: &Vec3) -> Vec3 {
+        self.pdf(ray, hit, direction) * self.albedo.clone()
+    }
+
+    /// Scattered rays point from the hit to a uniform point in a ball of
+    /// radius `fuzz` about the tip of the mirror direction, so the density of
+    /// a direction is that of the points along it within the ball. Rays into
+    /// the surface are absorbed.
+    fn pdf(&self, ray: &Ray, hit: &Hit, direction: &Vec3) -> f32 {
+        let direction = direction.normalized();
+        if self.fuzz <= 0.0 || direction.dot(&hit.normal) <= 0.0 {
+            return 0.0;
+        }
+        let reflected = Vec3::reflect(&ray.direction().normalized(), &hit.normal);
+        let projection = direction.dot(&reflected);
+        let discriminant = projection * projection - 1.0 + self.fuzz * self.fuzz;
+        if discriminant <= 0.0 {
+            return 0.0;
+        }
+        let near = (projection - discriminant.sqrt()).max(0.0);
+        let far = (projection + discriminant.sqrt()).max(0.0);
+        (far.powi(3) - near.powi(3)) / (4.0 * PI * self.fuzz.powi(3))
+    }
 }
 
 pub struct Dielectric {
@@ -68,9 +153,13 @@ impl Dielectric {
 }
 
 impl Material for Dielectric {
-    fn scatter(&self, ray: &Ray, hit: &Hit) -> Option<(Vec3, Ray)> {
+    fn kind(&self) -> MaterialKind {
+        MaterialKind::Dielectric
+    }
+
+    fn scatter(&self, ray: &Ray, hit: &Hit, uc: f32, _u: (f32, f32)) -> Option<(Vec3, Ray)> {
         let attenuation = Vec3::new(1.0, 1.0, 1.0);
-        let reflected = Vec3::reflect(&ray.direction(), &hit.normal);
+        let reflected = Vec3::reflect(ray.direction(), &hit.normal);
 
         let (outward_normal, ni, nt, cosine) = if ray.direction().dot(&hit.normal) > 0.0 {
             let cosine =
@@ -82,16 +171,320 @@ impl Material for Dielectric {
         };
 
         let scattered =
-            if let Some(refracted) = Vec3::refract(&ray.direction(), &outward_normal, ni, nt) {
+            if let Some(refracted) = Vec3::refract(ray.direction(), &outward_normal, ni, nt) {
                 let reflection_probability = Dielectric::schlick(cosine, self.refractive_index);
-                if rand::random::<f32>() < reflection_probability {
-                    Ray::new(hit.p.clone(), reflected)
+                if uc < reflection_probability {
+                    Ray::new(hit.p.clone(), reflected, ray.time())
                 } else {
-                    Ray::new(hit.p.clone(), refracted)
+                    Ray::new(hit.p.clone(), refracted, ray.time())
                 }
             } else {
-                Ray::new(hit.p.clone(), reflected)
+                Ray::new(hit.p.clone(), reflected, ray.time())
             };
         Some((attenuation, scattered))
     }
 }
+
+/// A light that emits the same radiance in every direction, from both sides,
+/// and reflects nothing.
+pub struct DiffuseLight {
+    emit: Box<dyn Texture>,
+}
+
+impl DiffuseLight {
+    pub fn new(emit: Box<dyn Texture>) -> DiffuseLight {
+        DiffuseLight { emit }
+    }
+}
+
+impl Material for DiffuseLight {
+    fn kind(&self) -> MaterialKind {
+        MaterialKind::Emissive
+    }
+
+    fn scatter(&self, _ray: &Ray, _hit: &Hit, _uc: f32, _u: (f32, f32)) -> Option<(Vec3, Ray)> {
+        None
+    }
+
+    fn emitted(&self, hit: &Hit) -> Vec3 {
+        self.emit.value(hit.u, hit.v, &hit.p)
+    }
+}
+
+/// The phase function of participating media, which scatters light equally
+/// in every direction.
+pub struct Isotropic {
+    albedo: Box<dyn Texture>,
+}
+
+impl Isotropic {
+    pub fn new(albedo: Box<dyn Texture>) -> Isotropic {
+        Isotropic { albedo }
+    }
+}
+
+impl Material for Isotropic {
+    fn kind(&self) -> MaterialKind {
+        MaterialKind::Isotropic
+    }
+
+    fn scatter(&self, ray: &Ray, hit: &Hit, _uc: f32, u: (f32, f32)) -> Option<(Vec3, Ray)> {
+        let (direction, _pdf) = sampling::uniform_sphere(u);
+        let scattered = Ray::new(hit.p.clone(), direction, ray.time());
+        let attenuation = self.albedo.value(hit.u, hit.v, &hit.p);
+        Some((attenuation, scattered))
+    }
+
+    fn eval(&self, ray: &Ray, hit: &Hit, direction: &Vec3) -> Vec3 {
+        self.pdf(ray, hit, direction) * self.albedo.value(hit.u, hit.v, &hit.p)
+    }
+
+    fn pdf(&self, _ray: &Ray, _hit: &Hit, _direction: &Vec3) -> f32 {
+        sampling::uniform_sphere_pdf()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sampling::tests::{chi_square, critical_value};
+    use crate::texture::Uniform;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    const SAMPLES: usize = 200_000;
+
+    /// The angles from the normal that rays arrive at.
+    const ANGLES: [f32; 4] = [0.0, 0.7, 1.3, 1.55];
+
+    fn rng() -> StdRng {
+        StdRng::seed_from_u64(0x5eed)
+    }
+
+    fn uniform(r: f32, g: f32, b: f32) -> Box<dyn Texture> {
+        Box::new(Uniform::new(Vec3::new(r, g, b)))
+    }
+
+    /// A hit at the origin of a surface facing +z.
+    fn hit(material: &dyn Material) -> Hit<'_> {
+        Hit {
+            t: 1.0,
+            p: Vec3::default(),
+            normal: Vec3::new(0.0, 0.0, 1.0),
+            u: 0.5,
+            v: 0.5,
+            object_id: 0,
+            material,
+        }
+    }
+
+    /// A ray arriving at the origin at `angle` from the normal, from above the
+    /// surface or, for `inside`, from below it.
+    fn incoming(angle: f32, inside: bool) -> Ray {
+        let z = if inside { angle.cos() } else { -angle.cos() };
+        let direction = Vec3::new(angle.sin(), 0.0, z);
+        Ray::new(-direction.clone(), direction, 0.0)
+    }
+
+    /// The materials that scatter into a continuum of directions.
+    fn glossy_materials() -> Vec<(&'static str, Box<dyn Material>)> {
+        vec![
+            (
+                "lambertian",
+                Box::new(Lambertian::new(uniform(0.8, 0.5, 0.2))),
+            ),
+            ("metal", Box::new(Metal::new(Vec3::new(0.9, 0.6, 0.3), 0.3))),
+            // Fuzzier than the mirror direction is long, so that the ball of
+            // scattered points holds the hit itself.
+            (
+                "rough metal",
+                Box::new(Metal::new(Vec3::new(0.9, 0.6, 0.3), 1.5)),
+            ),
+            (
+                "isotropic",
+                Box::new(Isotropic::new(uniform(0.7, 0.7, 0.7))),
+            ),
+        ]
+    }
+
+    /// Integrates `f` over the directions with z in [z0, z1] and azimuth in
+    /// [phi0, phi1] by the midpoint rule on a grid of `n` by `n` cells, in
+    /// which equal steps in z subtend equal solid angle.
+    fn integrate<F>(f: F, (z0, z1): (f64, f64), (phi0, phi1): (f64, f64), n: usize) -> f64
+    where
+        F: Fn(&Vec3) -> f64,
+    {
+        let (d_z, d_phi) = ((z1 - z0) / n as f64, (phi1 - phi0) / n as f64);
+        let mut sum = 0.0;
+        for i in 0..n {
+            let z = z0 + (i as f64 + 0.5) * d_z;
+            let r = (1.0 - z * z).max(0.0).sqrt();
+            for j in 0..n {
+                let phi = phi0 + (j as f64 + 0.5) * d_phi;
+                let direction = Vec3::new((r * phi.cos()) as f32, (r * phi.sin()) as f32, z as f32);
+                sum += f(&direction);
+            }
+        }
+        sum * d_z * d_phi
+    }
+
+    #[test]
+    fn samples_directions_by_their_pdf() {
+        const Z_BINS: usize = 20;
+        const PHI_BINS: usize = 20;
+        let d_z = 2.0 / Z_BINS as f64;
+        let d_phi = 2.0 * std::f64::consts::PI / PHI_BINS as f64;
+        for (name, material) in glossy_materials() {
+            for &angle in &ANGLES {
+                let (ray, hit) = (incoming(angle, false), hit(&*material));
+                let mut rng = rng();
+                let mut observed = vec![0.0; Z_BINS * PHI_BINS];
+                for _ in 0..SAMPLES {
+                    let scatter = material.scatter(&ray, &hit, rng.gen(), (rng.gen(), rng.gen()));
+                    if let Some((_, scattered)) = scatter {
+                        let v = scattered.direction().normalized();
+                        let z = (((v.z() + 1.0) / 2.0 * Z_BINS as f32) as usize).min(Z_BINS - 1);
+                        let phi = v.y().atan2(v.x()).rem_euclid(2.0 * PI);
+                        let phi = ((phi / (2.0 * PI) * PHI_BINS as f32) as usize).min(PHI_BINS - 1);
+                        observed[z * PHI_BINS + phi] += 1.0;
+                    }
+                }
+                // Samples absorbed by the surface fall in no bin, so the
+                // expected counts can sum to less than the samples taken.
+                let expected: Vec<f64> = (0..Z_BINS * PHI_BINS)
+                    .map(|bin| {
+                        let z0 = -1.0 + (bin / PHI_BINS) as f64 * d_z;
+                        let phi0 = (bin % PHI_BINS) as f64 * d_phi;
+                        let pdf = |v: &Vec3| f64::from(material.pdf(&ray, &hit, v));
+                        SAMPLES as f64 * integrate(pdf, (z0, z0 + d_z), (phi0, phi0 + d_phi), 16)
+                    })
+                    .collect();
+                let (statistic, dof) = chi_square(&observed, &expected);
+                let critical = critical_value(dof);
+                assert!(
+                    statistic < critical,
+                    "{} at {}: chi-square statistic {} exceeds {} with {} degrees of freedom",
+                    name,
+                    angle,
+                    statistic,
+                    critical,
+                    dof
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn weights_samples_by_eval_over_pdf() {
+        for (name, material) in glossy_materials() {
+            for &angle in &ANGLES {
+                let (ray, hit) = (incoming(angle, false), hit(&*material));
+                let mut rng = rng();
+                for _ in 0..1000 {
+                    let scatter = material.scatter(&ray, &hit, rng.gen(), (rng.gen(), rng.gen()));
+                    let (attenuation, scattered) = match scatter {
+                        Some(scatter) => scatter,
+                        None => continue,
+                    };
+                    let pdf = material.pdf(&ray, &hit, scattered.direction());
+                    assert!(pdf > 0.0, "{}: sampled a direction of zero density", name);
+                    let weight = (1.0 / pdf) * material.eval(&ray, &hit, scattered.direction());
+                    assert!(
+                        (&weight - &attenuation).length() <= 1e-3 * attenuation.length(),
+                        "{} at {}: weighted {:?} rather than {:?}",
+                        name,
+                        angle,
+                        attenuation,
+                        weight
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn conserves_energy() {
+        // White materials, with whether they lose no energy at all.
+        let materials: Vec<(&str, Box<dyn Material>, bool)> = vec![
+            (
+                "lambertian",
+                Box::new(Lambertian::new(uniform(1.0, 1.0, 1.0))),
+                true,
+            ),
+            (
+                "mirror",
+                Box::new(Metal::new(Vec3::new(1.0, 1.0, 1.0), 0.0)),
+                true,
+            ),
+            // Fuzz loses the rays it scatters into the surface.
+            (
+                "metal",
+                Box::new(Metal::new(Vec3::new(1.0, 1.0, 1.0), 0.3)),
+                false,
+            ),
+            (
+                "rough metal",
+                Box::new(Metal::new(Vec3::new(1.0, 1.0, 1.0), 1.5)),
+                false,
+            ),
+            ("glass", Box::new(Dielectric::new(1.5)), true),
+            (
+                "isotropic",
+                Box::new(Isotropic::new(uniform(1.0, 1.0, 1.0))),
+                true,
+            ),
+        ];
+        for (name, material, lossless) in materials {
+            // Opaque surfaces are only ever hit from outside.
+            let sides: &[bool] = match material.kind() {
+                MaterialKind::Dielectric | MaterialKind::Isotropic => &[false, true],
+                _ => &[false],
+            };
+            for &angle in &ANGLES {
+                for &inside in sides {
+                    let (ray, hit) = (incoming(angle, inside), hit(&*material));
+                    let mut rng = rng();
+                    let mut total = Vec3::default();
+                    for _ in 0..SAMPLES / 10 {
+                        let scatter =
+                            material.scatter(&ray, &hit, rng.gen(), (rng.gen(), rng.gen()));
+                        if let Some((attenuation, _)) = scatter {
+                            assert!(
+                                attenuation.max_component() <= 1.0,
+                                "{}: {:?}",
+                                name,
+                                attenuation
+                            );
+                            total += attenuation;
+                        }
+                    }
+                    // The directional albedo: the fraction of the light arriving
+                    // along the ray that is scattered.
+                    let albedo = (10.0 / SAMPLES as f32) * total;
+                    let context = format!("{} at {}, inside: {}", name, angle, inside);
+                    assert!(albedo.max_component() <= 1.0, "{}: {:?}", context, albedo);
+                    if lossless {
+                        assert!(
+                            (albedo.luminance() - 1.0).abs() < 1e-4,
+                            "{}: {:?}",
+                            context,
+                            albedo
+                        );
+                    }
+                    if material.pdf(&ray, &hit, &Vec3::new(0.0, 0.0, 1.0)) > 0.0 {
+                        let eval = |v: &Vec3| f64::from(material.eval(&ray, &hit, v).luminance());
+                        let pi = std::f64::consts::PI;
+                        let integral = integrate(eval, (-1.0, 1.0), (0.0, 2.0 * pi), 400) as f32;
+                        assert!(
+                            (integral - albedo.luminance()).abs() < 0.01,
+                            "{}: the BSDF integrates to {} but scatters {}",
+                            context,
+                            integral,
+                            albedo.luminance()
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
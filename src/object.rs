@@ -1,18 +1,28 @@
 use std::cmp::Ordering;
 
-use crate::geometry::{Ray, Vec3};
-use crate::material::{Dielectric, Lambertian, Material, Metal};
-use crate::texture::{Checkered, Uniform};
+use crate::geometry::{Aabb, Ray, Vec3};
+use crate::material::{Dielectric, Isotropic, Lambertian, Material, Metal};
+use crate::sampler::hash;
+use crate::texture::{Texture, Uniform};
 
 pub struct Hit<'a> {
     pub t: f32,
     pub p: Vec3,
     pub normal: Vec3,
+    pub u: f32,
+    pub v: f32,
+    pub object_id: usize,
     pub material: &'a dyn Material,
 }
 
 pub trait Hittable {
-    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<Hit>;
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<Hit<'_>>;
+
+    /// A box enclosing the object, or `None` if it is unbounded or empty.
+    fn bounding_box(&self) -> Option<Aabb>;
+
+    /// What kind of primitive the object is, for scene statistics.
+    fn kind(&self) -> &'static str;
 }
 
 pub struct Sphere<M: Material> {
@@ -31,8 +41,26 @@ impl<M: Material> Sphere<M> {
     }
 }
 
+impl<M: Material> Sphere<M> {
+    fn hit_at(&self, ray: &Ray, t: f32) -> Hit<'_> {
+        let p = ray.at_time(t);
+        let outward = (&p - &self.center) / self.radius.abs();
+        let u = 0.5 + outward.z().atan2(outward.x()) / (2.0 * std::f32::consts::PI);
+        let v = 0.5 + outward.y().clamp(-1.0, 1.0).asin() / std::f32::consts::PI;
+        Hit {
+            t,
+            normal: (&p - &self.center) / self.radius,
+            p,
+            u,
+            v,
+            object_id: 0,
+            material: &self.material,
+        }
+    }
+}
+
 impl<M: Material> Hittable for Sphere<M> {
-    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<Hit> {
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<Hit<'_>> {
         let oc = ray.origin() - &self.center;
         let a = ray.direction().dot(ray.direction());
         let b = oc.dot(ray.direction());
@@ -43,19 +71,9 @@ impl<M: Material> Hittable for Sphere<M> {
             let t2 = (-b + discriminant.sqrt()) / a;
 
             if t_min < t1 && t1 < t_max {
-                Some(Hit {
-                    t: t1,
-                    p: ray.at_time(t1),
-                    normal: (ray.at_time(t1) - &self.center) / self.radius,
-                    material: &self.material,
-                })
+                Some(self.hit_at(ray, t1))
             } else if t_min < t2 && t2 < t_max {
-                Some(Hit {
-                    t: t2,
-                    p: ray.at_time(t2),
-                    normal: (ray.at_time(t2) - &self.center) / self.radius,
-                    material: &self.material,
-                })
+                Some(self.hit_at(ray, t2))
             } else {
                 None
             }
@@ -63,6 +81,335 @@ impl<M: Material> Hittable for Sphere<M> {
             None
         }
     }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let radius = self.radius.abs();
+        let extent = Vec3::new(radius, radius, radius);
+        Some(Aabb::new(&self.center - &extent, &self.center + &extent))
+    }
+
+    fn kind(&self) -> &'static str {
+        "sphere"
+    }
+}
+
+/// An axis-aligned rectangle at `k` along the axis it faces, spanning `a` and
+/// `b` along the next two axes in turn. Its normal faces the ray that hits it.
+pub struct Rect<M: Material> {
+    axis: usize,
+    k: f32,
+    a: (f32, f32),
+    b: (f32, f32),
+    material: M,
+}
+
+impl<M: Material> Rect<M> {
+    pub fn xy(x: (f32, f32), y: (f32, f32), z: f32, material: M) -> Rect<M> {
+        Rect {
+            axis: 2,
+            k: z,
+            a: x,
+            b: y,
+            material,
+        }
+    }
+
+    pub fn xz(x: (f32, f32), z: (f32, f32), y: f32, material: M) -> Rect<M> {
+        Rect {
+            axis: 1,
+            k: y,
+            a: z,
+            b: x,
+            material,
+        }
+    }
+
+    pub fn yz(y: (f32, f32), z: (f32, f32), x: f32, material: M) -> Rect<M> {
+        Rect {
+            axis: 0,
+            k: x,
+            a: y,
+            b: z,
+            material,
+        }
+    }
+
+    fn point(&self, k: f32, a: f32, b: f32) -> Vec3 {
+        let mut e = [0.0; 3];
+        e[self.axis] = k;
+        e[(self.axis + 1) % 3] = a;
+        e[(self.axis + 2) % 3] = b;
+        Vec3::new(e[0], e[1], e[2])
+    }
+}
+
+impl<M: Material> Hittable for Rect<M> {
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<Hit<'_>> {
+        let (i, j) = ((self.axis + 1) % 3, (self.axis + 2) % 3);
+        let t = (self.k - ray.origin()[self.axis]) / ray.direction()[self.axis];
+        if !(t_min < t && t < t_max) {
+            return None;
+        }
+        let p = ray.at_time(t);
+        if p[i] < self.a.0 || p[i] > self.a.1 || p[j] < self.b.0 || p[j] > self.b.1 {
+            return None;
+        }
+        let facing = -ray.direction()[self.axis].signum();
+        Some(Hit {
+            t,
+            normal: self.point(facing, 0.0, 0.0),
+            u: (p[i] - self.a.0) / (self.a.1 - self.a.0),
+            v: (p[j] - self.b.0) / (self.b.1 - self.b.0),
+            p,
+            object_id: 0,
+            material: &self.material,
+        })
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        // Padded so that the box has some thickness along the axis.
+        Some(Aabb::new(
+            self.point(self.k - 1e-4, self.a.0, self.b.0),
+            self.point(self.k + 1e-4, self.a.1, self.b.1),
+        ))
+    }
+
+    fn kind(&self) -> &'static str {
+        "rect"
+    }
+}
+
+/// An axis-aligned box.
+pub struct Cuboid<M: Material> {
+    min: Vec3,
+    max: Vec3,
+    material: M,
+}
+
+impl<M: Material> Cuboid<M> {
+    pub fn new(min: Vec3, max: Vec3, material: M) -> Cuboid<M> {
+        Cuboid { min, max, material }
+    }
+}
+
+impl<M: Material> Hittable for Cuboid<M> {
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<Hit<'_>> {
+        // The ray is inside the slab between each pair of faces over an
+        // interval, and inside the box over their intersection.
+        let (mut near, mut far) = (f32::MIN, f32::MAX);
+        let (mut near_axis, mut far_axis) = (0, 0);
+        for axis in 0..3 {
+            let inverse = 1.0 / ray.direction()[axis];
+            let mut t0 = (self.min[axis] - ray.origin()[axis]) * inverse;
+            let mut t1 = (self.max[axis] - ray.origin()[axis]) * inverse;
+            if inverse < 0.0 {
+                std::mem::swap(&mut t0, &mut t1);
+            }
+            if t0 > near {
+                near = t0;
+                near_axis = axis;
+            }
+            if t1 < far {
+                far = t1;
+                far_axis = axis;
+            }
+        }
+        if near > far {
+            return None;
+        }
+        // Normals face out of the box, along the ray when it leaves.
+        let (t, axis, sign) = if t_min < near && near < t_max {
+            (near, near_axis, -1.0)
+        } else if t_min < far && far < t_max {
+            (far, far_axis, 1.0)
+        } else {
+            return None;
+        };
+        let mut normal = [0.0; 3];
+        normal[axis] = sign * ray.direction()[axis].signum();
+        let p = ray.at_time(t);
+        let (i, j) = ((axis + 1) % 3, (axis + 2) % 3);
+        Some(Hit {
+            t,
+            normal: Vec3::new(normal[0], normal[1], normal[2]),
+            u: (p[i] - self.min[i]) / (self.max[i] - self.min[i]),
+            v: (p[j] - self.min[j]) / (self.max[j] - self.min[j]),
+            p,
+            object_id: 0,
+            material: &self.material,
+        })
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(Aabb::new(self.min.clone(), self.max.clone()))
+    }
+
+    fn kind(&self) -> &'static str {
+        "cuboid"
+    }
+}
+
+/// An object moved by an offset.
+pub struct Translate {
+    object: Box<dyn Hittable + Send + Sync>,
+    offset: Vec3,
+}
+
+impl Translate {
+    pub fn new(object: Box<dyn Hittable + Send + Sync>, offset: Vec3) -> Translate {
+        Translate { object, offset }
+    }
+}
+
+impl Hittable for Translate {
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<Hit<'_>> {
+        let moved = Ray::new(
+            ray.origin() - &self.offset,
+            ray.direction().clone(),
+            ray.time(),
+        );
+        self.object.hit(&moved, t_min, t_max).map(|hit| Hit {
+            p: hit.p + self.offset.clone(),
+            ..hit
+        })
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.object
+            .bounding_box()
+            .map(|bounds| Aabb::new(bounds.min() + &self.offset, bounds.max() + &self.offset))
+    }
+
+    fn kind(&self) -> &'static str {
+        self.object.kind()
+    }
+}
+
+/// An object rotated about the y axis.
+pub struct RotateY {
+    object: Box<dyn Hittable + Send + Sync>,
+    sin: f32,
+    cos: f32,
+}
+
+impl RotateY {
+    pub fn new(object: Box<dyn Hittable + Send + Sync>, degrees: f32) -> RotateY {
+        let radians = degrees.to_radians();
+        RotateY {
+            object,
+            sin: radians.sin(),
+            cos: radians.cos(),
+        }
+    }
+
+    /// Rotates `v` by the object's angle, or back by it if `sin` is negated.
+    fn rotate(v: &Vec3, sin: f32, cos: f32) -> Vec3 {
+        Vec3::new(cos * v.x() + sin * v.z(), v.y(), -sin * v.x() + cos * v.z())
+    }
+}
+
+impl Hittable for RotateY {
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<Hit<'_>> {
+        let rotated = Ray::new(
+            RotateY::rotate(ray.origin(), -self.sin, self.cos),
+            RotateY::rotate(ray.direction(), -self.sin, self.cos),
+            ray.time(),
+        );
+        self.object.hit(&rotated, t_min, t_max).map(|hit| Hit {
+            p: RotateY::rotate(&hit.p, self.sin, self.cos),
+            normal: RotateY::rotate(&hit.normal, self.sin, self.cos),
+            ..hit
+        })
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let bounds = self.object.bounding_box()?;
+        (0..8)
+            .map(|corner| {
+                let pick = |axis: usize| {
+                    if corner >> axis & 1 == 0 {
+                        bounds.min()[axis]
+                    } else {
+                        bounds.max()[axis]
+                    }
+                };
+                let p = RotateY::rotate(&Vec3::new(pick(0), pick(1), pick(2)), self.sin, self.cos);
+                Aabb::new(p.clone(), p)
+            })
+            .fold(None, |union: Option<Aabb>, corner| match union {
+                Some(union) => Some(union.union(&corner)),
+                None => Some(corner),
+            })
+    }
+
+    fn kind(&self) -> &'static str {
+        self.object.kind()
+    }
+}
+
+/// A uniform participating medium filling a closed boundary, such as smoke or
+/// fog, through which rays travel an exponentially distributed distance
+/// before scattering.
+pub struct ConstantMedium {
+    boundary: Box<dyn Hittable + Send + Sync>,
+    density: f32,
+    phase: Isotropic,
+}
+
+impl ConstantMedium {
+    pub fn new(
+        boundary: Box<dyn Hittable + Send + Sync>,
+        density: f32,
+        albedo: Box<dyn Texture>,
+    ) -> ConstantMedium {
+        ConstantMedium {
+            boundary,
+            density,
+            phase: Isotropic::new(albedo),
+        }
+    }
+}
+
+impl Hittable for ConstantMedium {
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<Hit<'_>> {
+        let enter = self.boundary.hit(ray, f32::MIN, f32::MAX)?;
+        let exit = self.boundary.hit(ray, enter.t + 1e-4, f32::MAX)?;
+        let (t0, t1) = (enter.t.max(t_min), exit.t.min(t_max));
+        if t0 >= t1 {
+            return None;
+        }
+        // Hits take no sample values, so the distance is drawn from a hash of
+        // the ray instead, which keeps renders deterministic.
+        let bits = [ray.origin(), ray.direction()]
+            .iter()
+            .flat_map(|v| (0..3).map(move |axis| u64::from(v[axis].to_bits())))
+            .collect::<Vec<_>>();
+        let u = (hash(&bits) >> 40) as f32 / (1u64 << 24) as f32;
+        let length = ray.direction().length();
+        let distance = -(1.0 - u).ln() / self.density;
+        if distance > (t1 - t0) * length {
+            return None;
+        }
+        let t = t0 + distance / length;
+        Some(Hit {
+            t,
+            p: ray.at_time(t),
+            // Arbitrary, as the phase function doesn't use it.
+            normal: Vec3::new(1.0, 0.0, 0.0),
+            u: 0.0,
+            v: 0.0,
+            object_id: 0,
+            material: &self.phase,
+        })
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.boundary.bounding_box()
+    }
+
+    fn kind(&self) -> &'static str {
+        "medium"
+    }
 }
 
 #[derive(Default)]
@@ -75,6 +422,10 @@ impl World {
         World { objects }
     }
 
+    pub fn objects(&self) -> &[Box<dyn Hittable + Send + Sync>] {
+        &self.objects
+    }
+
     pub fn demo() -> World {
         let sphere = Sphere::new(
             Vec3::new(0.0, 0.0, -1.0),
@@ -106,88 +457,607 @@ impl World {
             )),
         ])
     }
+}
 
-    pub fn random() -> World {
-        use rand::Rng;
-        let checker = Checkered::new(
-            Box::new(Uniform::new(Vec3::new(0.2, 0.3, 0.1))),
-            Box::new(Uniform::new(Vec3::new(0.9, 0.9, 0.9))),
-        );
-        let earth = Sphere::new(
-            Vec3::new(0.0, -1000.0, 0.0),
-            1000.0,
-            Lambertian::new(Box::new(checker)),
-        );
-        let mut objects: Vec<Box<dyn Hittable + Send + Sync>> = vec![Box::new(earth)];
-        let mut rng = rand::thread_rng();
-        for x in (-11)..11 {
-            for z in (-11)..11 {
-                let center = Vec3::new(
-                    (x as f32) + 0.9 * rng.gen::<f32>(),
-                    0.2,
-                    (z as f32) + 0.9 * rng.gen::<f32>(),
-                );
-                if (&center - Vec3::new(4.0, 0.2, 0.0)).length() < 0.9 {
-                    continue;
+impl Hittable for World {
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<Hit<'_>> {
+        self.objects
+            .iter()
+            .enumerate()
+            .filter_map(|(id, obj)| {
+                obj.hit(ray, t_min, t_max).map(|hit| Hit {
+                    object_id: id,
+                    ..hit
+                })
+            })
+            .min_by(|hit1, hit2| hit1.t.partial_cmp(&hit2.t).unwrap_or(Ordering::Less))
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.objects
+            .iter()
+            .filter_map(|object| object.bounding_box())
+            .fold(None, |bounds, object| match bounds {
+                Some(bounds) => Some(object.union(&bounds)),
+                None => Some(object),
+            })
+    }
+
+    fn kind(&self) -> &'static str {
+        "world"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sampling;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    const RAYS: usize = 20_000;
+
+    type Point = [f64; 3];
+
+    fn point(v: &Vec3) -> Point {
+        [v.x().into(), v.y().into(), v.z().into()]
+    }
+
+    fn dot(a: &Point, b: &Point) -> f64 {
+        a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+    }
+
+    fn sub(a: &Point, b: &Point) -> Point {
+        [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+    }
+
+    /// Rotates `p` about the y axis as `RotateY` does.
+    fn rotate(p: &Point, degrees: f64) -> Point {
+        let (sin, cos) = degrees.to_radians().sin_cos();
+        [cos * p[0] + sin * p[2], p[1], -sin * p[0] + cos * p[2]]
+    }
+
+    /// A ray in double precision.
+    #[derive(Clone)]
+    struct Line {
+        origin: Point,
+        direction: Point,
+    }
+
+    impl Line {
+        fn of(ray: &Ray) -> Line {
+            Line {
+                origin: point(ray.origin()),
+                direction: point(ray.direction()),
+            }
+        }
+
+        fn at(&self, t: f64) -> Point {
+            let (o, d) = (&self.origin, &self.direction);
+            [o[0] + t * d[0], o[1] + t * d[1], o[2] + t * d[2]]
+        }
+    }
+
+    /// A primitive's surface in double precision, to check hits against.
+    #[derive(Clone)]
+    enum Surface {
+        Sphere {
+            center: Point,
+            radius: f64,
+        },
+        /// A rectangle or box between two corners, which for a rectangle are
+        /// equal along its axis.
+        Box {
+            min: Point,
+            max: Point,
+        },
+        /// A surface rotated about the y axis and then moved.
+        Moved {
+            surface: Box<Surface>,
+            degrees: f64,
+            offset: Point,
+        },
+    }
+
+    impl Surface {
+        /// The distances along the line at which it crosses the surface, in
+        /// order, and how close each comes to an edge of the surface, where a
+        /// ray may hit it or not.
+        fn crossings(&self, line: &Line) -> Vec<(f64, f64)> {
+            match self {
+                Surface::Sphere { center, radius } => {
+                    let oc = sub(&line.origin, center);
+                    let a = dot(&line.direction, &line.direction);
+                    let b = dot(&oc, &line.direction);
+                    let c = dot(&oc, &oc) - radius * radius;
+                    let discriminant = b * b - a * c;
+                    if discriminant < 0.0 {
+                        return Vec::new();
+                    }
+                    // The distance between the two crossings is how far the
+                    // line is from grazing the sphere.
+                    let root = discriminant.sqrt();
+                    let graze = 2.0 * root / a.sqrt();
+                    vec![((-b - root) / a, graze), ((-b + root) / a, graze)]
+                }
+                Surface::Box { min, max } => {
+                    let mut crossings = Vec::new();
+                    for axis in 0..3 {
+                        for &k in &[min[axis], max[axis]] {
+                            let t = (k - line.origin[axis]) / line.direction[axis];
+                            let p = line.at(t);
+                            // The distance from the edges of the face, which
+                            // is negative outside it.
+                            let edge = (0..3)
+                                .filter(|other| *other != axis)
+                                .map(|other| (p[other] - min[other]).min(max[other] - p[other]))
+                                .fold(f64::INFINITY, f64::min);
+                            if edge > -1e-3 * (1.0 + t.abs()) {
+                                crossings.push((t, edge));
+                            }
+                        }
+                    }
+                    crossings.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+                    crossings.dedup_by(|a, b| (a.0 - b.0).abs() < 1e-9);
+                    crossings
+                }
+                Surface::Moved {
+                    surface,
+                    degrees,
+                    offset,
+                } => surface.crossings(&Line {
+                    origin: rotate(&sub(&line.origin, offset), -degrees),
+                    direction: rotate(&line.direction, -degrees),
+                }),
+            }
+        }
+
+        /// The distance from `p` to the surface.
+        fn distance(&self, p: &Point) -> f64 {
+            match self {
+                Surface::Sphere { center, radius } => {
+                    (dot(&sub(p, center), &sub(p, center)).sqrt() - radius.abs()).abs()
+                }
+                Surface::Box { min, max } => {
+                    let outside = (0..3)
+                        .map(|axis| (min[axis] - p[axis]).max(p[axis] - max[axis]).max(0.0))
+                        .map(|d| d * d)
+                        .sum::<f64>()
+                        .sqrt();
+                    let inside = (0..3)
+                        .map(|axis| (p[axis] - min[axis]).min(max[axis] - p[axis]))
+                        .fold(f64::INFINITY, f64::min);
+                    outside.max(inside)
+                }
+                Surface::Moved {
+                    surface,
+                    degrees,
+                    offset,
+                } => surface.distance(&rotate(&sub(p, offset), -degrees)),
+            }
+        }
+
+        /// The normal the primitive should report at `p`: out of spheres, or
+        /// into them for a negative radius, out of boxes, and against the
+        /// ray for rectangles.
+        fn normal(&self, p: &Point, direction: &Point) -> Point {
+            match self {
+                Surface::Sphere { center, radius } => {
+                    let n = sub(p, center);
+                    [n[0] / radius, n[1] / radius, n[2] / radius]
+                }
+                Surface::Box { min, max } => {
+                    let mut normal = [0.0; 3];
+                    if let Some(axis) = (0..3).find(|axis| min[*axis] == max[*axis]) {
+                        normal[axis] = -direction[axis].signum();
+                        return normal;
+                    }
+                    // The face the point is nearest.
+                    let (axis, side) = (0..3)
+                        .flat_map(|axis| vec![(axis, -1.0), (axis, 1.0)])
+                        .min_by(|a, b| {
+                            let gap = |(axis, side): (usize, f64)| {
+                                if side < 0.0 {
+                                    (p[axis] - min[axis]).abs()
+                                } else {
+                                    (max[axis] - p[axis]).abs()
+                                }
+                            };
+                            gap(*a).partial_cmp(&gap(*b)).unwrap()
+                        })
+                        .unwrap();
+                    normal[axis] = side;
+                    normal
+                }
+                Surface::Moved {
+                    surface,
+                    degrees,
+                    offset,
+                } => {
+                    let local = rotate(&sub(p, offset), -degrees);
+                    rotate(
+                        &surface.normal(&local, &rotate(direction, -degrees)),
+                        *degrees,
+                    )
+                }
+            }
+        }
+
+        /// Whether `p` is within `tolerance` of the inside of a closed surface.
+        fn contains(&self, p: &Point, tolerance: f64) -> bool {
+            match self {
+                Surface::Sphere { center, radius } => {
+                    dot(&sub(p, center), &sub(p, center)).sqrt() <= radius.abs() + tolerance
+                }
+                Surface::Box { min, max } => (0..3).all(|axis| {
+                    min[axis] - tolerance <= p[axis] && p[axis] <= max[axis] + tolerance
+                }),
+                Surface::Moved {
+                    surface,
+                    degrees,
+                    offset,
+                } => surface.contains(&rotate(&sub(p, offset), -degrees), tolerance),
+            }
+        }
+
+        /// The size of the surface, which tolerances scale with.
+        fn size(&self) -> f64 {
+            match self {
+                Surface::Sphere { radius, .. } => radius.abs(),
+                Surface::Box { min, max } => dot(&sub(max, min), &sub(max, min)).sqrt(),
+                Surface::Moved { surface, .. } => surface.size(),
+            }
+        }
+
+        fn center(&self) -> Point {
+            match self {
+                Surface::Sphere { center, .. } => *center,
+                Surface::Box { min, max } => [
+                    0.5 * (min[0] + max[0]),
+                    0.5 * (min[1] + max[1]),
+                    0.5 * (min[2] + max[2]),
+                ],
+                Surface::Moved {
+                    surface,
+                    degrees,
+                    offset,
+                } => {
+                    let c = rotate(&surface.center(), *degrees);
+                    [c[0] + offset[0], c[1] + offset[1], c[2] + offset[2]]
                 }
-                let r: f32 = rng.gen::<f32>();
-                if r < 0.8 {
-                    objects.push(Box::new(Sphere::new(
-                        center,
-                        0.2,
-                        Lambertian::new(Box::new(Uniform::new(Vec3::new(
-                            rng.gen::<f32>() * rng.gen::<f32>(),
-                            rng.gen::<f32>() * rng.gen::<f32>(),
-                            rng.gen::<f32>() * rng.gen::<f32>(),
-                        )))),
-                    )));
-                } else if r < 0.9 {
-                    objects.push(Box::new(Sphere::new(
-                        center,
-                        0.2,
-                        Metal::new(
-                            Vec3::new(
-                                0.5 * (1.0 + rng.gen::<f32>()),
-                                0.5 * (1.0 + rng.gen::<f32>()),
-                                0.5 * (1.0 + rng.gen::<f32>()),
-                            ),
-                            0.5 * rng.gen::<f32>(),
-                        ),
-                    )));
-                } else {
-                    objects.push(Box::new(Sphere::new(
-                        center.clone(),
-                        0.2,
-                        Dielectric::new(1.5),
-                    )));
-                    objects.push(Box::new(Sphere::new(center, -0.195, Dielectric::new(1.5))));
+            }
+        }
+    }
+
+    fn vec3(p: &Point) -> Vec3 {
+        Vec3::new(p[0] as f32, p[1] as f32, p[2] as f32)
+    }
+
+    fn material() -> Lambertian {
+        Lambertian::new(Box::new(Uniform::new(Vec3::new(0.5, 0.5, 0.5))))
+    }
+
+    /// Sizes from a hundredth of a unit to the size of the ground sphere in
+    /// the random scene.
+    fn size(rng: &mut StdRng) -> f64 {
+        10f64.powf(rng.gen_range(-2.0, 3.0))
+    }
+
+    fn random_point(rng: &mut StdRng, center: &Point, extent: f64) -> Point {
+        let mut p = *center;
+        for value in &mut p {
+            *value += rng.gen_range(-extent, extent);
+        }
+        p
+    }
+
+    /// A random primitive, and its surface.
+    fn primitive(rng: &mut StdRng) -> (Box<dyn Hittable + Send + Sync>, Surface) {
+        let size = size(rng);
+        // Rounded to single precision, as the primitive's will be.
+        let center = point(&vec3(&random_point(rng, &[0.0; 3], 2.0 * size)));
+        match rng.gen_range(0, 4) {
+            0 => {
+                let radius = if rng.gen() { size } else { -size } as f32 as f64;
+                let sphere = Sphere::new(vec3(&center), radius as f32, material());
+                (Box::new(sphere), Surface::Sphere { center, radius })
+            }
+            1 => {
+                let axis = rng.gen_range(0, 3);
+                let corner = random_point(rng, &center, size);
+                let (mut min, mut max) = (center, center);
+                for other in (0..3).filter(|other| *other != axis) {
+                    min[other] = center[other].min(corner[other]) as f32 as f64;
+                    max[other] = center[other].max(corner[other]) as f32 as f64;
+                }
+                let (k, span) = (min[axis] as f32, |i: usize| (min[i] as f32, max[i] as f32));
+                let rect: Box<dyn Hittable + Send + Sync> = match axis {
+                    0 => Box::new(Rect::yz(span(1), span(2), k, material())),
+                    1 => Box::new(Rect::xz(span(0), span(2), k, material())),
+                    _ => Box::new(Rect::xy(span(0), span(1), k, material())),
                 };
+                (rect, Surface::Box { min, max })
+            }
+            kind => {
+                let corner = random_point(rng, &center, size);
+                let (mut min, mut max) = (center, corner);
+                for axis in 0..3 {
+                    let (a, b) = (center[axis] as f32, corner[axis] as f32);
+                    min[axis] = a.min(b).into();
+                    max[axis] = a.max(b).into();
+                }
+                let cuboid = Box::new(Cuboid::new(vec3(&min), vec3(&max), material()));
+                let surface = Surface::Box { min, max };
+                if kind == 2 {
+                    return (cuboid, surface);
+                }
+                let degrees = rng.gen_range(-180.0, 180.0);
+                let offset = random_point(rng, &[0.0; 3], size);
+                let moved = Translate::new(
+                    Box::new(RotateY::new(cuboid, degrees as f32)),
+                    vec3(&offset),
+                );
+                let surface = Surface::Moved {
+                    surface: Box::new(surface),
+                    degrees: degrees as f32 as f64,
+                    offset: point(&vec3(&offset)),
+                };
+                (Box::new(moved), surface)
             }
         }
-        objects.push(Box::new(Sphere::new(
-            Vec3::new(0.0, 1.0, 0.0),
-            1.0,
-            Dielectric::new(1.5),
-        )));
-        objects.push(Box::new(Sphere::new(
-            Vec3::new(-3.0, 1.0, 0.5),
-            1.0,
-            Lambertian::new(Box::new(Uniform::new(Vec3::new(0.2, 0.2, 0.6)))),
-        )));
-        objects.push(Box::new(Sphere::new(
-            Vec3::new(4.0, 1.0, 0.0),
-            1.0,
-            Metal::new(Vec3::new(0.8, 0.6, 0.7), 0.0),
-        )));
-        World { objects }
     }
-}
 
-impl Hittable for World {
-    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<Hit> {
-        self.objects
+    /// A ray from somewhere around the surface towards it, with a direction
+    /// that needn't be unit length, and the distance along it to a point by
+    /// the surface.
+    fn ray_towards(rng: &mut StdRng, surface: &Surface) -> (Ray, f32) {
+        let (center, size) = (surface.center(), surface.size());
+        let origin = random_point(rng, &center, 3.0 * size);
+        let target = random_point(rng, &center, 0.5 * size);
+        let scale = 10f64.powf(rng.gen_range(-1.0, 1.0));
+        let direction = sub(&target, &origin);
+        let direction = [
+            scale * direction[0],
+            scale * direction[1],
+            scale * direction[2],
+        ];
+        (
+            Ray::new(vec3(&origin), vec3(&direction), 0.0),
+            (1.0 / scale) as f32,
+        )
+    }
+
+    /// The range of distances to look for hits in, sometimes the renderer's
+    /// and sometimes a random part of the ray up to twice `reach`.
+    fn range(rng: &mut StdRng, reach: f32) -> (f32, f32) {
+        let t_min = rng.gen_range(0.0, reach);
+        match rng.gen_range(0, 3) {
+            0 => (0.001, f32::MAX),
+            1 => (t_min, f32::MAX),
+            _ => (t_min, t_min + rng.gen_range(0.0, reach)),
+        }
+    }
+
+    /// Checks a hit, or the lack of one, against the surface: the first
+    /// crossing of the surface within the range must be hit, at a point on
+    /// the surface with a unit normal facing the right way.
+    fn check(name: &str, object: &dyn Hittable, surface: &Surface, ray: &Ray, range: (f32, f32)) {
+        let line = Line::of(ray);
+        let length = dot(&line.direction, &line.direction).sqrt();
+        // Distances along the ray that are too close to tell apart in single
+        // precision.
+        let tolerance = 1e-4 * (surface.size() + dot(&line.origin, &line.origin).sqrt()) / length;
+        let (t_min, t_max) = (f64::from(range.0), f64::from(range.1));
+        let crossings = surface.crossings(&line);
+        // A ray that grazes an edge, or crosses the surface at the ends of the
+        // range, may or may not hit it.
+        if crossings.iter().any(|(t, edge)| {
+            *edge < tolerance * length
+                || (t - t_min).abs() < tolerance
+                || (t - t_max).abs() < tolerance
+        }) {
+            return;
+        }
+        let expected = crossings
             .iter()
-            .filter_map(|obj| obj.hit(ray, t_min, t_max))
-            .min_by(|hit1, hit2| hit1.t.partial_cmp(&hit2.t).unwrap_or(Ordering::Less))
+            .map(|(t, _)| *t)
+            .find(|t| t_min < *t && *t < t_max);
+        let hit = object.hit(ray, range.0, range.1);
+        let context = || format!("{}: {:?} in {:?}", name, ray, range);
+        let hit = match (hit, expected) {
+            (None, None) => return,
+            (Some(hit), Some(t)) => {
+                assert!(
+                    (f64::from(hit.t) - t).abs() < tolerance,
+                    "{}: hit at {} rather than {}",
+                    context(),
+                    hit.t,
+                    t
+                );
+                hit
+            }
+            (Some(hit), None) => panic!("{}: hit at {} but misses", context(), hit.t),
+            (None, Some(t)) => panic!("{}: missed the crossing at {}", context(), t),
+        };
+        assert!(
+            range.0 < hit.t && hit.t < range.1,
+            "{}: {} is out of range",
+            context(),
+            hit.t
+        );
+        let p = point(&hit.p);
+        let scale = tolerance * length;
+        assert!(
+            surface.distance(&p) < scale,
+            "{}: {:?} is {} off the surface",
+            context(),
+            hit.p,
+            surface.distance(&p)
+        );
+        assert!(
+            dot(
+                &sub(&p, &line.at(f64::from(hit.t))),
+                &sub(&p, &line.at(f64::from(hit.t)))
+            )
+            .sqrt()
+                < scale,
+            "{}: {:?} isn't on the ray at {}",
+            context(),
+            hit.p,
+            hit.t
+        );
+        assert!(
+            (hit.normal.length() - 1.0).abs() < 1e-4,
+            "{}: the normal {:?} isn't unit length",
+            context(),
+            hit.normal
+        );
+        let normal = surface.normal(&p, &line.direction);
+        assert!(
+            dot(&point(&hit.normal), &normal) > 0.999,
+            "{}: the normal {:?} should be {:?}",
+            context(),
+            hit.normal,
+            normal
+        );
+        let bounds = object.bounding_box().unwrap();
+        for (axis, value) in p.iter().enumerate() {
+            assert!(
+                f64::from(bounds.min()[axis]) - scale <= *value
+                    && *value <= f64::from(bounds.max()[axis]) + scale,
+                "{}: {:?} is outside the bounding box {:?}",
+                context(),
+                hit.p,
+                bounds
+            );
+        }
+    }
+
+    #[test]
+    fn primitives_hit_their_surfaces() {
+        let mut rng = StdRng::seed_from_u64(0x5eed);
+        for _ in 0..RAYS {
+            let (object, surface) = primitive(&mut rng);
+            let (ray, reach) = ray_towards(&mut rng, &surface);
+            check(
+                object.kind(),
+                &*object,
+                &surface,
+                &ray,
+                range(&mut rng, reach),
+            );
+        }
+    }
+
+    /// A random primitive enclosing a volume, and its surface.
+    fn closed_primitive(rng: &mut StdRng) -> (Box<dyn Hittable + Send + Sync>, Surface) {
+        loop {
+            let (object, surface) = primitive(rng);
+            if object.kind() != "rect" {
+                return (object, surface);
+            }
+        }
+    }
+
+    #[test]
+    fn media_scatter_inside_their_boundaries() {
+        let mut rng = StdRng::seed_from_u64(0x5eed);
+        for _ in 0..RAYS {
+            let (boundary, surface) = closed_primitive(&mut rng);
+            let (ray, reach) = ray_towards(&mut rng, &surface);
+            let range = range(&mut rng, reach);
+            // Dense enough to scatter most rays that pass through.
+            let density = 10f32.powf(rng.gen_range(-1.0, 1.0)) / surface.size() as f32;
+            let medium = ConstantMedium::new(
+                boundary,
+                density,
+                Box::new(Uniform::new(Vec3::new(0.5, 0.5, 0.5))),
+            );
+            let hit = match medium.hit(&ray, range.0, range.1) {
+                Some(hit) => hit,
+                None => continue,
+            };
+            let context = format!("medium: {:?} in {:?}", ray, range);
+            assert!(
+                range.0 < hit.t && hit.t < range.1,
+                "{}: {} is out of range",
+                context,
+                hit.t
+            );
+            let line = Line::of(&ray);
+            let tolerance = 1e-4 * (surface.size() + dot(&line.origin, &line.origin).sqrt());
+            assert!(
+                surface.contains(&point(&hit.p), tolerance),
+                "{}: scattered at {:?}, outside the boundary",
+                context,
+                hit.p
+            );
+            let on_ray = sub(&point(&hit.p), &line.at(f64::from(hit.t)));
+            assert!(dot(&on_ray, &on_ray).sqrt() < tolerance, "{}", context);
+            assert!((hit.normal.length() - 1.0).abs() < 1e-4, "{}", context);
+        }
+    }
+
+    /// `World` is the only aggregate of primitives, with no acceleration
+    /// structure, so it is checked against each of its objects in turn.
+    #[test]
+    fn world_hits_the_nearest_object() {
+        let mut rng = StdRng::seed_from_u64(0x5eed);
+        for _ in 0..RAYS / 10 {
+            let count = rng.gen_range(1, 9);
+            let (objects, surfaces): (Vec<_>, Vec<_>) =
+                (0..count).map(|_| primitive(&mut rng)).unzip();
+            let world = World::new(objects);
+            let target = &surfaces[rng.gen_range(0, count)];
+            let (ray, reach) = ray_towards(&mut rng, target);
+            let range = range(&mut rng, reach);
+            let nearest = world
+                .objects()
+                .iter()
+                .enumerate()
+                .filter_map(|(id, object)| object.hit(&ray, range.0, range.1).map(|hit| (id, hit)))
+                .min_by(|a, b| a.1.t.partial_cmp(&b.1.t).unwrap());
+            let hit = world.hit(&ray, range.0, range.1);
+            match (hit, nearest) {
+                (None, None) => {}
+                (Some(hit), Some((id, nearest))) => {
+                    assert_eq!(hit.t, nearest.t, "{:?} in {:?}", ray, range);
+                    assert_eq!(hit.object_id, id);
+                    assert_eq!(hit.p, nearest.p);
+                    assert_eq!(hit.normal, nearest.normal);
+                }
+                (hit, nearest) => panic!(
+                    "{:?} in {:?}: the world hit {:?} but its nearest object {:?}",
+                    ray,
+                    range,
+                    hit.map(|hit| hit.t),
+                    nearest.map(|(_, hit)| hit.t)
+                ),
+            }
+        }
+    }
+
+    /// Secondary rays start at a hit and rely on `t_min` to skip it. On large
+    /// primitives the hit is further off the surface than `t_min` can make up
+    /// for at grazing angles, and such rays are left unjudged.
+    #[test]
+    fn rays_leaving_a_surface_do_not_hit_it_again_at_once() {
+        let mut rng = StdRng::seed_from_u64(0x5eed);
+        let mut checked = 0;
+        while checked < RAYS {
+            let (object, surface) = primitive(&mut rng);
+            let (ray, _) = ray_towards(&mut rng, &surface);
+            let hit = match object.hit(&ray, 0.001, f32::MAX) {
+                Some(hit) => hit,
+                None => continue,
+            };
+            // Leave in any direction, as reflected, refracted and scattered
+            // rays do, from the hit point as the renderer does.
+            let (direction, _) = sampling::uniform_sphere((rng.gen(), rng.gen()));
+            let scale = 10f32.powf(rng.gen_range(-1.0, 1.0));
+            let ray = Ray::new(hit.p.clone(), scale * direction, 0.0);
+            check(object.kind(), &*object, &surface, &ray, (0.001, f32::MAX));
+            checked += 1;
+        }
     }
 }
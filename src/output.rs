@@ -0,0 +1,206 @@
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use crate::exr::{write_exr, ExrOptions};
+use crate::film::Film;
+use crate::geometry::Vec3;
+use crate::png::write_png;
+use crate::tonemap::ToneMapper;
+
+/// Writes the tone mapped film as a plain-text PPM image.
+pub fn write_ppm(film: &Film, tone_mapper: &ToneMapper, out: &mut dyn Write) -> io::Result<()> {
+    writeln!(out, "P3\n{} {}\n255", film.width(), film.height())?;
+    for (r, g, b) in tone_mapper.apply(film) {
+        writeln!(out, "{} {} {}", r, g, b)?;
+    }
+    Ok(())
+}
+
+/// Writes the film's linear radiance as a little-endian PFM image.
+pub fn write_pfm(film: &Film, out: &mut dyn Write) -> io::Result<()> {
+    write_pfm_with(film, |x, y| film.pixel(x, y).color(), out)
+}
+
+/// Writes one of the film's AOVs as a little-endian PFM image.
+pub fn write_aov_pfm(film: &Film, index: usize, out: &mut dyn Write) -> io::Result<()> {
+    write_pfm_with(film, |x, y| film.aov(index, x, y), out)
+}
+
+/// Writes a color for each pixel of the film as a PFM image, which stores its
+/// rows from the bottom of the image up.
+fn write_pfm_with<F>(film: &Film, color: F, out: &mut dyn Write) -> io::Result<()>
+where
+    F: Fn(u32, u32) -> Vec3,
+{
+    write!(out, "PF\n{} {}\n-1.0\n", film.width(), film.height())?;
+    for y in (0..film.height()).rev() {
+        for x in 0..film.width() {
+            let color = color(x, y);
+            for value in &[color.r(), color.g(), color.b()] {
+                out.write_all(&value.to_le_bytes())?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Writes the number of samples taken in each pixel as a PPM image, from blue
+/// for the fewest samples through green to red for the most taken in any
+/// pixel.
+pub fn write_heatmap(film: &Film, out: &mut dyn Write) -> io::Result<()> {
+    writeln!(out, "P3\n{} {}\n255", film.width(), film.height())?;
+    let max_samples = film.pixels().iter().map(|pixel| pixel.samples).max();
+    let max_samples = max_samples.unwrap_or(0).max(1);
+    for pixel in film.pixels() {
+        let t = (pixel.samples as f32 / max_samples as f32).min(1.0);
+        let (r, g, b) = if t < 0.5 {
+            (0.0, 2.0 * t, 1.0 - 2.0 * t)
+        } else {
+            (2.0 * t - 1.0, 2.0 - 2.0 * t, 0.0)
+        };
+        let quantize = |v: f32| (255.0 * v + 0.5) as u8;
+        writeln!(out, "{} {} {}", quantize(r), quantize(g), quantize(b))?;
+    }
+    Ok(())
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    Ppm,
+    Png,
+    Pfm,
+    Exr,
+}
+
+impl Format {
+    pub const NAMES: &'static [&'static str] = &["ppm", "png", "pfm", "exr"];
+
+    /// The format named by a path's extension.
+    pub fn from_path(path: &Path) -> io::Result<Format> {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("ppm") => Ok(Format::Ppm),
+            Some("png") => Ok(Format::Png),
+            Some("pfm") => Ok(Format::Pfm),
+            Some("exr") => Ok(Format::Exr),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("unsupported image format: {}", path.display()),
+            )),
+        }
+    }
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Format, String> {
+        match s {
+            "ppm" => Ok(Format::Ppm),
+            "png" => Ok(Format::Png),
+            "pfm" => Ok(Format::Pfm),
+            "exr" => Ok(Format::Exr),
+            _ => Err(format!("unknown image format: {}", s)),
+        }
+    }
+}
+
+/// Writes the film in the given format.
+pub fn write_image(
+    film: &Film,
+    tone_mapper: &ToneMapper,
+    exr: &ExrOptions,
+    format: Format,
+    out: &mut dyn Write,
+) -> io::Result<()> {
+    match format {
+        Format::Ppm => write_ppm(film, tone_mapper, out),
+        Format::Png => write_png(film, tone_mapper, out),
+        Format::Pfm => write_pfm(film, out),
+        Format::Exr => write_exr(film, exr, out),
+    }
+}
+
+/// Saves the film to `path` in the format given by its extension, returning
+/// the paths of all the images written. OpenEXR images hold the film's AOVs as
+/// layers, while for other formats they are saved beside `path`.
+pub fn save(
+    film: &Film,
+    tone_mapper: &ToneMapper,
+    exr: &ExrOptions,
+    path: &Path,
+) -> io::Result<Vec<PathBuf>> {
+    let format = Format::from_path(path)?;
+    let mut out = BufWriter::new(File::create(path)?);
+    write_image(film, tone_mapper, exr, format, &mut out)?;
+    out.flush()?;
+
+    let mut paths = vec![path.to_path_buf()];
+    if format != Format::Exr {
+        paths.extend(save_aovs(film, path)?);
+    }
+    Ok(paths)
+}
+
+/// Replaces the file at `path` with what `write` writes. The file is written
+/// to a temporary file beside it and renamed into place, so that readers never
+/// see it half written.
+pub fn replace_atomically<F>(path: &Path, write: F) -> io::Result<()>
+where
+    F: FnOnce(&mut dyn Write) -> io::Result<()>,
+{
+    let name = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or("output");
+    let temporary = path.with_file_name(format!(".{}.tmp", name));
+    let mut out = BufWriter::new(File::create(&temporary)?);
+    write(&mut out)?;
+    out.into_inner()?.sync_all()?;
+    fs::rename(&temporary, path)
+}
+
+/// Replaces the image at `path` with the film in the format given by its
+/// extension, atomically.
+pub fn save_preview(
+    film: &Film,
+    tone_mapper: &ToneMapper,
+    exr: &ExrOptions,
+    path: &Path,
+) -> io::Result<()> {
+    let format = Format::from_path(path)?;
+    replace_atomically(path, |out| write_image(film, tone_mapper, exr, format, out))
+}
+
+/// The path beside `path` to keep the noisy image at when denoising.
+pub fn noisy_path(path: &Path) -> PathBuf {
+    let stem = path
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .unwrap_or("image");
+    match path.extension().and_then(|extension| extension.to_str()) {
+        Some(extension) => path.with_file_name(format!("{}.noisy.{}", stem, extension)),
+        None => path.with_file_name(format!("{}.noisy", stem)),
+    }
+}
+
+/// Saves each of the film's AOVs as a PFM image beside `path`, named by
+/// inserting the AOV's name before the extension.
+fn save_aovs(film: &Film, path: &Path) -> io::Result<Vec<PathBuf>> {
+    let stem = path
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .unwrap_or("image");
+    film.aovs()
+        .iter()
+        .enumerate()
+        .map(|(index, aov)| {
+            let aov_path = path.with_file_name(format!("{}.{}.pfm", stem, aov.name()));
+            let mut out = BufWriter::new(File::create(&aov_path)?);
+            write_aov_pfm(film, index, &mut out)?;
+            out.flush()?;
+            Ok(aov_path)
+        })
+        .collect()
+}
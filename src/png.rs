@@ -0,0 +1,319 @@
+use std::io::{self, Read, Write};
+
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use flate2::Crc;
+
+use crate::film::Film;
+use crate::geometry::Vec3;
+use crate::tonemap::{srgb_decode, ToneMapper};
+
+// A writer and reader for PNG images. Images are written as 8-bit sRGB
+// without filtering, which suits the noisy images renders make. Any
+// non-interlaced image can be read: its values are decoded from sRGB to linear
+// radiance, and its alpha, if any, is ignored.
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+fn write_chunk(out: &mut dyn Write, kind: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    out.write_all(&(data.len() as u32).to_be_bytes())?;
+    out.write_all(kind)?;
+    out.write_all(data)?;
+    let mut crc = Crc::new();
+    crc.update(kind);
+    crc.update(data);
+    out.write_all(&crc.sum().to_be_bytes())
+}
+
+/// Writes the tone mapped film as an 8-bit RGB PNG image.
+pub fn write_png(film: &Film, tone_mapper: &ToneMapper, out: &mut dyn Write) -> io::Result<()> {
+    out.write_all(&SIGNATURE)?;
+    let mut header = Vec::new();
+    header.extend_from_slice(&film.width().to_be_bytes());
+    header.extend_from_slice(&film.height().to_be_bytes());
+    // 8 bits per sample of RGB, deflated, unfiltered and not interlaced.
+    header.extend_from_slice(&[8, 2, 0, 0, 0]);
+    write_chunk(out, b"IHDR", &header)?;
+
+    let colors = tone_mapper.apply(film);
+    let mut encoder = ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+    for row in colors.chunks(film.width() as usize) {
+        let mut line = vec![0];
+        for (r, g, b) in row {
+            line.extend_from_slice(&[*r, *g, *b]);
+        }
+        encoder.write_all(&line)?;
+    }
+    write_chunk(out, b"IDAT", &encoder.finish()?)?;
+    write_chunk(out, b"IEND", &[])
+}
+
+/// The layout of an image's samples, from its header.
+struct Header {
+    width: u32,
+    height: u32,
+    bit_depth: u8,
+    color_type: u8,
+}
+
+impl Header {
+    fn channels(&self) -> usize {
+        match self.color_type {
+            0 | 3 => 1,
+            4 => 2,
+            2 => 3,
+            _ => 4,
+        }
+    }
+
+    /// The number of bytes in a row of the image, without its filter byte.
+    fn row_size(&self) -> usize {
+        (self.width as usize * self.channels() * self.bit_depth as usize).div_ceil(8)
+    }
+
+    /// The number of bytes that a filter looks back to find the same sample of
+    /// the previous pixel.
+    fn pixel_size(&self) -> usize {
+        (self.channels() * self.bit_depth as usize).div_ceil(8)
+    }
+}
+
+/// Reads a chunk, returning its type and data.
+fn read_chunk(input: &mut dyn Read) -> io::Result<([u8; 4], Vec<u8>)> {
+    let mut length = [0; 4];
+    input.read_exact(&mut length)?;
+    let mut kind = [0; 4];
+    input.read_exact(&mut kind)?;
+    let mut data = vec![0; u32::from_be_bytes(length) as usize];
+    input.read_exact(&mut data)?;
+    let mut crc = [0; 4];
+    input.read_exact(&mut crc)?;
+    let mut expected = Crc::new();
+    expected.update(&kind);
+    expected.update(&data);
+    if expected.sum() != u32::from_be_bytes(crc) {
+        return Err(invalid("a chunk of the image is corrupt"));
+    }
+    Ok((kind, data))
+}
+
+fn paeth(left: u8, up: u8, up_left: u8) -> u8 {
+    let estimate = left as i16 + up as i16 - up_left as i16;
+    let (a, b, c) = (
+        (estimate - left as i16).abs(),
+        (estimate - up as i16).abs(),
+        (estimate - up_left as i16).abs(),
+    );
+    if a <= b && a <= c {
+        left
+    } else if b <= c {
+        up
+    } else {
+        up_left
+    }
+}
+
+/// Reverses the filter applied to each row, returning the rows without their
+/// filter bytes.
+fn unfilter(header: &Header, data: &[u8]) -> io::Result<Vec<u8>> {
+    let (row_size, pixel_size) = (header.row_size(), header.pixel_size());
+    if data.len() < (row_size + 1) * header.height as usize {
+        return Err(invalid("the image is truncated"));
+    }
+    let mut rows = vec![0; row_size * header.height as usize];
+    for (y, line) in data
+        .chunks(row_size + 1)
+        .take(header.height as usize)
+        .enumerate()
+    {
+        let start = y * row_size;
+        for i in 0..row_size {
+            let left = if i >= pixel_size {
+                rows[start + i - pixel_size]
+            } else {
+                0
+            };
+            let up = if y > 0 { rows[start + i - row_size] } else { 0 };
+            let up_left = if y > 0 && i >= pixel_size {
+                rows[start + i - row_size - pixel_size]
+            } else {
+                0
+            };
+            let predicted = match line[0] {
+                0 => 0,
+                1 => left,
+                2 => up,
+                3 => ((left as u16 + up as u16) / 2) as u8,
+                4 => paeth(left, up, up_left),
+                _ => return Err(invalid("unknown row filter")),
+            };
+            rows[start + i] = line[1 + i].wrapping_add(predicted);
+        }
+    }
+    Ok(rows)
+}
+
+/// Reads a non-interlaced PNG image of any color type and bit depth.
+pub fn read_png(input: &mut dyn Read) -> io::Result<Film> {
+    let mut signature = [0; 8];
+    input.read_exact(&mut signature)?;
+    if signature != SIGNATURE {
+        return Err(invalid("not a PNG image"));
+    }
+    let (kind, data) = read_chunk(input)?;
+    if &kind != b"IHDR" || data.len() != 13 {
+        return Err(invalid("the image header is missing"));
+    }
+    let header = Header {
+        width: u32::from_be_bytes([data[0], data[1], data[2], data[3]]),
+        height: u32::from_be_bytes([data[4], data[5], data[6], data[7]]),
+        bit_depth: data[8],
+        color_type: data[9],
+    };
+    let valid_depths: &[u8] = match header.color_type {
+        0 => &[1, 2, 4, 8, 16],
+        3 => &[1, 2, 4, 8],
+        2 | 4 | 6 => &[8, 16],
+        _ => return Err(invalid("unknown color type")),
+    };
+    if !valid_depths.contains(&header.bit_depth) {
+        return Err(invalid("invalid bit depth"));
+    }
+    if data[12] != 0 {
+        return Err(invalid("interlaced PNG images aren't supported"));
+    }
+
+    let mut palette = Vec::new();
+    let mut compressed = Vec::new();
+    loop {
+        let (kind, data) = read_chunk(input)?;
+        match &kind {
+            b"PLTE" => palette = data,
+            b"IDAT" => compressed.extend_from_slice(&data),
+            b"IEND" => break,
+            _ if kind[0].is_ascii_uppercase() => {
+                return Err(invalid("the image has an unknown critical chunk"))
+            }
+            _ => {}
+        }
+    }
+    let mut data = Vec::new();
+    ZlibDecoder::new(compressed.as_slice()).read_to_end(&mut data)?;
+    let rows = unfilter(&header, &data)?;
+
+    let depth = header.bit_depth as usize;
+    let max = ((1u32 << depth) - 1) as f32;
+    // Sample `index` of a row, counting across the pixels' channels.
+    let sample = |row: &[u8], index: usize| -> u32 {
+        match depth {
+            16 => u16::from_be_bytes([row[2 * index], row[2 * index + 1]]) as u32,
+            8 => row[index] as u32,
+            _ => {
+                let bit = index * depth;
+                (row[bit / 8] >> (8 - depth - bit % 8)) as u32 & ((1 << depth) - 1)
+            }
+        }
+    };
+    let channels = header.channels();
+    let mut film = Film::new(header.width, header.height, &[]);
+    for (x, y) in film.bounds().pixels() {
+        let row = &rows[y as usize * header.row_size()..][..header.row_size()];
+        let index = x as usize * channels;
+        let encoded = match header.color_type {
+            3 => {
+                let entry = 3 * sample(row, index) as usize;
+                let rgb = palette
+                    .get(entry..entry + 3)
+                    .ok_or_else(|| invalid("a color isn't in the palette"))?;
+                [
+                    rgb[0] as f32 / 255.0,
+                    rgb[1] as f32 / 255.0,
+                    rgb[2] as f32 / 255.0,
+                ]
+            }
+            0 | 4 => [sample(row, index) as f32 / max; 3],
+            _ => [
+                sample(row, index) as f32 / max,
+                sample(row, index + 1) as f32 / max,
+                sample(row, index + 2) as f32 / max,
+            ],
+        };
+        let color = Vec3::new(
+            srgb_decode(encoded[0]),
+            srgb_decode(encoded[1]),
+            srgb_decode(encoded[2]),
+        );
+        film.set_color(x, y, &color);
+    }
+    Ok(film)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Encodes rows, each starting with its filter byte, as a PNG image.
+    fn encode(header: &[u8], palette: &[u8], rows: &[u8]) -> Vec<u8> {
+        let mut png = SIGNATURE.to_vec();
+        write_chunk(&mut png, b"IHDR", header).unwrap();
+        if !palette.is_empty() {
+            write_chunk(&mut png, b"PLTE", palette).unwrap();
+        }
+        let mut encoder = ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(rows).unwrap();
+        write_chunk(&mut png, b"IDAT", &encoder.finish().unwrap()).unwrap();
+        write_chunk(&mut png, b"IEND", &[]).unwrap();
+        png
+    }
+
+    fn gray(film: &Film, x: u32, y: u32) -> f32 {
+        let color = film.pixel(x, y).color();
+        assert_eq!((color.r(), color.g()), (color.b(), color.b()));
+        color.r()
+    }
+
+    #[test]
+    fn reads_filtered_rows_of_any_layout() {
+        // A 16-bit grayscale image whose rows are filtered by Sub, Up, Average
+        // and Paeth, all encoding the values 0, 0x8000 and 0xffff.
+        let header = [0, 0, 0, 3, 0, 0, 0, 4, 16, 0, 0, 0, 0];
+        let rows = [
+            1, 0, 0, 0x80, 0, 0x7f, 0xff, //
+            2, 0, 0, 0, 0, 0, 0, //
+            3, 0, 0, 0x40, 0, 0x40, 0x80, //
+            4, 0, 0, 0, 0, 0, 0,
+        ];
+        let film = read_png(&mut encode(&header, &[], &rows).as_slice()).unwrap();
+        for y in 0..4 {
+            assert_eq!(gray(&film, 0, y), 0.0);
+            assert!((gray(&film, 1, y) - srgb_decode(0x8000 as f32 / 65535.0)).abs() < 1e-6);
+            assert_eq!(gray(&film, 2, y), 1.0);
+        }
+
+        // A 2-bit palette image of five pixels, which don't fill the last byte.
+        let header = [0, 0, 0, 5, 0, 0, 0, 1, 2, 3, 0, 0, 0];
+        let palette = [0, 0, 0, 255, 0, 0, 0, 255, 0, 0, 0, 255];
+        let film =
+            read_png(&mut encode(&header, &palette, &[0, 0b00_01_10_11, 0b0100_0000]).as_slice())
+                .unwrap();
+        let colors: Vec<Vec3> = (0..5).map(|x| film.pixel(x, 0).color()).collect();
+        assert_eq!(
+            colors,
+            vec![
+                Vec3::new(0.0, 0.0, 0.0),
+                Vec3::new(1.0, 0.0, 0.0),
+                Vec3::new(0.0, 1.0, 0.0),
+                Vec3::new(0.0, 0.0, 1.0),
+                Vec3::new(1.0, 0.0, 0.0),
+            ]
+        );
+
+        let mut corrupt = encode(&header, &palette, &[0, 0, 0]);
+        corrupt[20] ^= 1;
+        assert!(read_png(&mut corrupt.as_slice()).is_err());
+    }
+}
@@ -0,0 +1,84 @@
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use indicatif::ProgressBar;
+
+use crate::film::{Bounds, Film};
+use crate::renderer::StopReason;
+
+/// Watches a render as it goes. Every method does nothing by default.
+pub trait RenderObserver {
+    /// Called as the render starts, with the number of passes it will take at
+    /// most and the tiles that each pass renders.
+    fn on_start(&mut self, _passes: usize, _tiles: &[Bounds]) {}
+
+    /// Called as each tile of pass number `pass` completes.
+    fn on_tile(&mut self, _pass: usize, _tile: Bounds) {}
+
+    /// Called with the film and the number of passes it holds after each pass.
+    /// Returning an error aborts the render with it.
+    fn on_pass(&mut self, _film: &Film, _passes: usize) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Called when a worker of a distributed render can't be reached or fails,
+    /// and is dropped from the render.
+    fn on_worker_failed(&mut self, _worker: &str, _error: &io::Error) {}
+
+    /// Called as the render ends, with why it stopped early if it did.
+    fn on_finish(&mut self, _stopped: Option<StopReason>) {}
+}
+
+/// Counts the pixels rendered over all passes.
+impl RenderObserver for ProgressBar {
+    fn on_start(&mut self, passes: usize, tiles: &[Bounds]) {
+        let pixels: usize = tiles.iter().map(Bounds::area).sum();
+        self.set_length((passes * pixels) as u64);
+    }
+
+    fn on_tile(&mut self, _pass: usize, tile: Bounds) {
+        self.inc(tile.area() as u64);
+    }
+
+    fn on_worker_failed(&mut self, worker: &str, error: &io::Error) {
+        self.println(format!("Dropped worker {}: {}", worker, error));
+    }
+
+    fn on_finish(&mut self, _stopped: Option<StopReason>) {
+        self.finish_at_current_pos();
+    }
+}
+
+/// Calls a function after each pass.
+pub struct AfterPass<F>(pub F);
+
+impl<F> RenderObserver for AfterPass<F>
+where
+    F: FnMut(&Film, usize) -> io::Result<()>,
+{
+    fn on_pass(&mut self, film: &Film, passes: usize) -> io::Result<()> {
+        (self.0)(film, passes)
+    }
+}
+
+/// A flag for cancelling a render from elsewhere, such as another thread.
+/// Clones share the flag.
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+    pub fn new() -> CancellationToken {
+        CancellationToken::default()
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+}
@@ -0,0 +1,874 @@
+use std::io;
+use std::ops::Range;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use futures::channel::{mpsc, oneshot};
+use futures::stream::{FuturesOrdered, StreamExt};
+
+use crate::aov::{Aov, AovSample};
+use crate::camera::CameraSample;
+use crate::checkpoint::Checkpoint;
+use crate::distributed::{Cluster, ClusterEvent};
+use crate::film::{Film, FilmTile};
+use crate::filter::{Filter, FilterKind};
+use crate::geometry::{Ray, Vec3};
+use crate::object::Hittable;
+use crate::progress::{AfterPass, CancellationToken, RenderObserver};
+use crate::sampler::{Sampler, SamplerKind};
+use crate::scene::Scene;
+
+/// The settings that determine what a render computes.
+#[derive(Clone, Debug)]
+pub struct RenderSettings {
+    pub width: u32,
+    pub height: u32,
+    /// The number of samples per pixel to take, at most.
+    pub samples: usize,
+    /// The number of samples per pixel to take over the image in each pass.
+    pub pass_samples: usize,
+    /// Stop once another pass would overrun this much time.
+    pub time_limit: Option<Duration>,
+    /// Stop once the estimated relative error of the image falls below this.
+    pub target_noise: Option<f32>,
+    /// The number of bounces at which paths are cut off even if Russian
+    /// roulette hasn't ended them, which darkens the image if any reach it.
+    pub max_depth: u32,
+    /// The number of bounces after which paths may be terminated by Russian
+    /// roulette.
+    pub roulette_depth: u32,
+    pub sampler: SamplerKind,
+    /// The seed for the sampler's random scrambling.
+    pub seed: u64,
+    /// Stop sampling a pixel once the relative standard error of its mean falls
+    /// below this threshold.
+    pub adaptive_threshold: Option<f32>,
+    /// The number of samples per pixel before adaptive sampling may stop.
+    pub min_samples: usize,
+    /// Auxiliary images to render alongside the beauty image.
+    pub aovs: Vec<Aov>,
+    pub filter: FilterKind,
+    /// The radius of the reconstruction filter in pixels, if not the default.
+    pub filter_radius: Option<f32>,
+}
+
+/// The sample budget of renders that stop on time or noise instead.
+pub const UNLIMITED_SAMPLES: usize = 1 << 16;
+
+impl Default for RenderSettings {
+    fn default() -> RenderSettings {
+        RenderSettings {
+            width: 200,
+            height: 150,
+            samples: 10,
+            pass_samples: 16,
+            time_limit: None,
+            target_noise: None,
+            max_depth: 1000,
+            roulette_depth: 3,
+            sampler: SamplerKind::Sobol,
+            seed: 0,
+            adaptive_threshold: None,
+            min_samples: 8,
+            aovs: Vec::new(),
+            filter: FilterKind::Box,
+            filter_radius: None,
+        }
+    }
+}
+
+impl RenderSettings {
+    /// The number of passes it takes to spend the sample budget.
+    pub fn passes(&self) -> usize {
+        self.samples.div_ceil(self.pass_samples)
+    }
+
+    /// Checks that the settings describe a render.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.width == 0 || self.height == 0 {
+            return Err(format!(
+                "the image must not be empty, not {}x{}",
+                self.width, self.height
+            ));
+        }
+        if self.pass_samples == 0 {
+            return Err("each pass must take at least one sample".to_string());
+        }
+        if let Some(radius) = self.filter_radius {
+            if !(radius.is_finite() && radius > 0.0) {
+                return Err(format!("invalid filter radius: {}", radius));
+            }
+        }
+        Ok(())
+    }
+
+    /// Describes the settings that determine the samples of each pass, which a
+    /// render must keep to be resumed from a checkpoint. These include the
+    /// sample budget, since samplers may shuffle their samples across all of it.
+    pub fn checkpoint_key(&self) -> String {
+        let radius = self
+            .filter_radius
+            .unwrap_or_else(|| self.filter.default_radius());
+        format!(
+            "{}x{} seed {} sampler {:?} samples {} in passes of {} filter {:?} radius {} \
+             max depth {} roulette depth {} adaptive {:?} min samples {} aovs {:?}",
+            self.width,
+            self.height,
+            self.seed,
+            self.sampler,
+            self.samples,
+            self.pass_samples,
+            self.filter,
+            radius,
+            self.max_depth,
+            self.roulette_depth,
+            self.adaptive_threshold,
+            self.min_samples,
+            self.aovs,
+        )
+    }
+}
+
+/// Traces a path starting along `ray` and returns the radiance it carries
+/// back, along with the path's AOVs, counting the rays it traces in `rays`.
+/// Past `settings.roulette_depth` bounces, paths are randomly terminated with
+/// a probability that grows as their throughput falls, and survivors are
+/// reweighted to keep the estimate unbiased. Even paths that lose no energy
+/// are terminated with probability 0.05 per bounce, so roulette ends them
+/// all. `settings.max_depth` only guards against runaway paths: cutting a
+/// path off there drops the light it would have gathered, which biases the
+/// image darker, so it should be far deeper than paths go.
+fn bounce(
+    settings: &RenderSettings,
+    ray: Ray,
+    scene: &Scene,
+    sampler: &mut dyn Sampler,
+    rays: &mut u64,
+) -> (Vec3, AovSample) {
+    let mut ray = ray;
+    let mut throughput = Vec3::new(1.0, 1.0, 1.0);
+    let mut radiance = Vec3::default();
+    let mut aov = AovSample::default();
+    let mut specular = false;
+    for depth in 0..=settings.max_depth {
+        *rays += 1;
+        let hit = match scene.world().hit(&ray, 0.001, f32::MAX) {
+            Some(hit) => hit,
+            None => {
+                let background = scene.background().radiance(ray.direction());
+                if depth == 0 {
+                    aov.albedo = background.clone();
+                }
+                let light = throughput * background;
+                aov.add_light(depth, specular, &light);
+                return (radiance + light, aov);
+            }
+        };
+        if depth == 0 {
+            let kind = hit.material.kind();
+            aov.normal = hit.normal.normalized();
+            aov.depth = hit.t * ray.direction().length();
+            aov.position = hit.p.clone();
+            aov.uv = (hit.u, hit.v);
+            aov.object_id = hit.object_id as u32 + 1;
+            aov.material_id = kind as u32 + 1;
+            specular = kind.is_specular();
+        }
+        let emitted = hit.material.emitted(&hit);
+        if emitted.max_component() > 0.0 {
+            let light = throughput.clone() * emitted;
+            aov.add_light(depth, specular, &light);
+            radiance += light;
+        }
+        if depth == settings.max_depth {
+            break;
+        }
+
+        let uc = sampler.get_1d();
+        let u = sampler.get_2d();
+        let (attenuation, scattered) = match hit.material.scatter(&ray, &hit, uc, u) {
+            Some(scatter) => scatter,
+            None => break,
+        };
+        if depth == 0 {
+            aov.albedo = attenuation.clone();
+        }
+        throughput = throughput * attenuation;
+        ray = scattered;
+
+        if depth + 1 >= settings.roulette_depth {
+            let survival = throughput.max_component().min(0.95);
+            if sampler.get_1d() >= survival {
+                break;
+            }
+            throughput /= survival;
+        }
+    }
+    (radiance, aov)
+}
+
+const TILE_SIZE: u32 = 16;
+
+/// Takes the samples numbered `indices` in pixel `(x, y)`, or with adaptive
+/// sampling, as many of them as it takes for the pixel to converge. Returns
+/// the number of samples taken.
+fn sample_pixel(
+    settings: &RenderSettings,
+    scene: &Scene,
+    filter: &dyn Filter,
+    tile: &mut FilmTile,
+    (x, y): (u32, u32),
+    indices: Range<usize>,
+) -> usize {
+    let mut sampler = settings.sampler.build(settings.samples, settings.seed);
+    let mut statistics = tile.statistics_mut((x, y)).clone();
+    let mut samples = 0;
+    let mut rays = 0;
+    for index in indices {
+        if let Some(threshold) = settings.adaptive_threshold {
+            if statistics.count >= settings.min_samples && statistics.relative_error() < threshold {
+                break;
+            }
+        }
+
+        sampler.start_pixel_sample((x, y), index);
+        let (dx, dy) = sampler.get_pixel_2d();
+        let (film_x, film_y) = (x as f32 + dx, y as f32 + dy);
+        let sample = CameraSample {
+            film: (
+                film_x / settings.width as f32,
+                1.0 - film_y / settings.height as f32,
+            ),
+            lens: sampler.get_2d(),
+            time: sampler.get_1d(),
+        };
+        let ray = scene.camera().ray(&sample);
+        let (color, aov) = bounce(settings, ray, scene, &mut *sampler, &mut rays);
+        statistics.add(color.luminance());
+        tile.add_sample(filter, (film_x, film_y), &color, &aov);
+        if index == 0 {
+            tile.set_ids((x, y), &aov);
+        }
+        samples += 1;
+    }
+    *tile.statistics_mut((x, y)) = statistics;
+    tile.add_rays(rays);
+    samples
+}
+
+/// Takes the samples of pass number `pass` in each of the tile's pixels.
+pub fn render_pass_tile(
+    settings: &RenderSettings,
+    scene: &Scene,
+    filter: &dyn Filter,
+    mut tile: FilmTile,
+    pass: usize,
+) -> FilmTile {
+    let begin = pass * settings.pass_samples;
+    let indices = begin
+        ..begin
+            .saturating_add(settings.pass_samples)
+            .min(settings.samples);
+    for pixel in tile.pixel_bounds().pixels() {
+        let samples = sample_pixel(settings, scene, filter, &mut tile, pixel, indices.clone());
+        tile.add_samples(pixel, samples);
+    }
+    tile
+}
+
+/// Renders the tile, unless the render is cancelled before it starts.
+async fn render_tile(
+    settings: &RenderSettings,
+    scene: &Scene,
+    filter: &dyn Filter,
+    tile: FilmTile,
+    pass: usize,
+    cancellation: &CancellationToken,
+) -> Option<FilmTile> {
+    if cancellation.is_cancelled() {
+        return None;
+    }
+    Some(render_pass_tile(settings, scene, filter, tile, pass))
+}
+
+/// Runs `f` on a thread of its own, so that the executor running the render
+/// isn't stalled while it blocks.
+async fn unblocked<T, F>(f: F) -> io::Result<T>
+where
+    T: Send + 'static,
+    F: FnOnce() -> T + Send + 'static,
+{
+    let (done, result) = oneshot::channel();
+    thread::spawn(move || {
+        let _ = done.send(f());
+    });
+    result
+        .await
+        .map_err(|_| io::Error::other("a blocking thread panicked"))
+}
+
+/// Renders a pass on the cluster on a thread of its own, since the cluster
+/// blocks until its workers are done, and reports the cluster's events to
+/// `on_event` as they arrive. Returns the cluster for the next pass.
+async fn render_cluster_pass<F>(
+    mut cluster: Cluster,
+    pass: usize,
+    tiles: Vec<FilmTile>,
+    cancellation: CancellationToken,
+    mut on_event: F,
+) -> io::Result<(Cluster, Vec<FilmTile>)>
+where
+    F: FnMut(ClusterEvent),
+{
+    let (events, mut received) = mpsc::unbounded();
+    let (done, result) = oneshot::channel();
+    thread::spawn(move || {
+        let rendered = cluster.render_pass(pass, tiles, &cancellation, |event| {
+            let _ = events.unbounded_send(event);
+        });
+        let _ = done.send(rendered.map(|tiles| (cluster, tiles)));
+    });
+    while let Some(event) = received.next().await {
+        on_event(event);
+    }
+    result
+        .await
+        .unwrap_or_else(|_| Err(io::Error::other("the cluster's thread panicked")))
+}
+
+/// Why a render stopped before spending its sample budget.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StopReason {
+    TimeLimit,
+    TargetNoise,
+    /// The render's cancellation token was cancelled. The pass in progress is
+    /// dropped, leaving the film with the passes completed before it.
+    Cancelled,
+}
+
+/// The result of a render.
+pub struct RenderOutput {
+    /// The image, as accumulated over all the passes so far.
+    pub film: Film,
+    /// The number of passes in the film.
+    pub passes: usize,
+    /// The time spent rendering, not counting any render resumed from.
+    pub elapsed: Duration,
+    /// The number of rays traced, not counting any render resumed from.
+    pub rays: u64,
+    pub stopped: Option<StopReason>,
+}
+
+/// Renders scenes in passes. Each pass takes the next
+/// `settings.pass_samples` samples in every pixel, so that the whole image
+/// converges together and can be inspected along the way.
+pub struct Renderer<'a> {
+    settings: RenderSettings,
+    resume: Option<(Film, usize)>,
+    observers: Vec<Box<dyn RenderObserver + 'a>>,
+    cancellation: CancellationToken,
+    workers: Vec<String>,
+    worker_timeout: Duration,
+}
+
+impl<'a> Renderer<'a> {
+    pub fn new(settings: RenderSettings) -> Renderer<'a> {
+        Renderer {
+            settings,
+            resume: None,
+            observers: Vec::new(),
+            cancellation: CancellationToken::new(),
+            workers: Vec::new(),
+            worker_timeout: Duration::from_secs(300),
+        }
+    }
+
+    pub fn settings(&self) -> &RenderSettings {
+        &self.settings
+    }
+
+    /// Continues the render saved in `checkpoint`, which must have been made
+    /// with the same settings.
+    pub fn resume(mut self, checkpoint: Checkpoint) -> io::Result<Renderer<'a>> {
+        if checkpoint.settings != self.settings.checkpoint_key() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "the checkpoint was rendered with different settings: {}",
+                    checkpoint.settings
+                ),
+            ));
+        }
+        self.resume = Some((checkpoint.film, checkpoint.passes));
+        Ok(self)
+    }
+
+    /// Reports the render's progress to `observer`, after any observers added
+    /// before it.
+    pub fn observer<O>(mut self, observer: O) -> Renderer<'a>
+    where
+        O: RenderObserver + 'a,
+    {
+        self.observers.push(Box::new(observer));
+        self
+    }
+
+    /// Calls `after_pass` with the film and the number of passes it holds after
+    /// each pass, for example to save a preview or a checkpoint.
+    pub fn after_pass<F>(self, after_pass: F) -> Renderer<'a>
+    where
+        F: FnMut(&Film, usize) -> io::Result<()> + 'a,
+    {
+        self.observer(AfterPass(after_pass))
+    }
+
+    /// Stops the render before its next tile once `cancellation` is cancelled.
+    pub fn cancellation(mut self, cancellation: CancellationToken) -> Renderer<'a> {
+        self.cancellation = cancellation;
+        self
+    }
+
+    /// Renders the tiles on the `ray-tracer worker` processes listening at
+    /// `addresses` instead of locally. The workers must build the same scene
+    /// from the render settings as the one rendered.
+    pub fn workers(mut self, addresses: Vec<String>) -> Renderer<'a> {
+        self.workers = addresses;
+        self
+    }
+
+    /// Gives up on workers that take longer than `timeout` to render a tile,
+    /// handing their tiles to the other workers.
+    pub fn worker_timeout(mut self, timeout: Duration) -> Renderer<'a> {
+        self.worker_timeout = timeout;
+        self
+    }
+
+    /// Renders the scene until the sample budget is spent, one of the
+    /// settings' stopping criteria is met or the render is cancelled.
+    pub async fn render(mut self, scene: &Scene) -> io::Result<RenderOutput> {
+        let settings = &self.settings;
+        settings
+            .validate()
+            .map_err(|error| io::Error::new(io::ErrorKind::InvalidInput, error))?;
+        let filter = settings.filter.build(settings.filter_radius);
+        let (mut film, first_pass) = match self.resume.take() {
+            Some(resume) => resume,
+            None => (
+                Film::new(settings.width, settings.height, &settings.aovs),
+                0,
+            ),
+        };
+        let passes = settings.passes();
+        let tiles = film.tiles(TILE_SIZE);
+        let mut cluster = None;
+        if !self.workers.is_empty() {
+            let description = scene.description().ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "only built-in scenes can be rendered on workers",
+                )
+            })?;
+            let (addresses, timeout) = (self.workers.clone(), self.worker_timeout);
+            let (settings, description) = (settings.clone(), description.clone());
+            let (connected, failures) =
+                unblocked(move || Cluster::connect(&addresses, &settings, &description, timeout))
+                    .await?;
+            for (address, error) in failures {
+                for observer in &mut self.observers {
+                    observer.on_worker_failed(&address, &error);
+                }
+            }
+            if connected.is_empty() {
+                return Err(io::Error::other("no workers could be reached"));
+            }
+            cluster = Some(connected);
+        }
+        for observer in &mut self.observers {
+            observer.on_start(passes.saturating_sub(first_pass), &tiles);
+        }
+
+        let start = Instant::now();
+        let mut stopped = None;
+        let mut rendered_passes = first_pass;
+        let mut rays = 0;
+        for pass in first_pass..passes {
+            let pass_start = Instant::now();
+            let (observers, cancellation) = (&mut self.observers, &self.cancellation);
+            let completed = match cluster.take() {
+                Some(connected) => {
+                    let pass_tiles = tiles
+                        .iter()
+                        .map(|pixels| film.tile(*pixels, &*filter))
+                        .collect();
+                    let on_event = |event: ClusterEvent| {
+                        for observer in observers.iter_mut() {
+                            match &event {
+                                ClusterEvent::Tile(pixels) => observer.on_tile(pass, *pixels),
+                                ClusterEvent::WorkerFailed(address, error) => {
+                                    observer.on_worker_failed(address, error)
+                                }
+                            }
+                        }
+                    };
+                    let (connected, completed) = render_cluster_pass(
+                        connected,
+                        pass,
+                        pass_tiles,
+                        cancellation.clone(),
+                        on_event,
+                    )
+                    .await?;
+                    cluster = Some(connected);
+                    completed
+                }
+                None => {
+                    let mut rendered: FuturesOrdered<_> = tiles
+                        .iter()
+                        .map(|pixels| {
+                            let tile = film.tile(*pixels, &*filter);
+                            render_tile(settings, scene, &*filter, tile, pass, cancellation)
+                        })
+                        .collect();
+                    let mut completed = Vec::with_capacity(tiles.len());
+                    while let Some(Some(tile)) = rendered.next().await {
+                        for observer in observers.iter_mut() {
+                            observer.on_tile(pass, tile.pixel_bounds());
+                        }
+                        completed.push(tile);
+                    }
+                    completed
+                }
+            };
+            if self.cancellation.is_cancelled() {
+                stopped = Some(StopReason::Cancelled);
+                break;
+            }
+            // Tiles are merged in order, so that the sums in each pixel don't
+            // depend on when the tiles completed.
+            for tile in completed {
+                rays += tile.rays();
+                film.merge(tile);
+            }
+            rendered_passes = pass + 1;
+            for observer in &mut self.observers {
+                observer.on_pass(&film, rendered_passes)?;
+            }
+
+            if let Some(target) = settings.target_noise {
+                if film.estimated_error() < target {
+                    stopped = Some(StopReason::TargetNoise);
+                    break;
+                }
+            }
+            // Stop if the next pass, taking as long as this one, would overrun
+            // the time limit.
+            if let Some(limit) = settings.time_limit {
+                if start.elapsed() + pass_start.elapsed() > limit {
+                    stopped = Some(StopReason::TimeLimit);
+                    break;
+                }
+            }
+        }
+        for observer in &mut self.observers {
+            observer.on_finish(stopped);
+        }
+
+        Ok(RenderOutput {
+            film,
+            passes: rendered_passes,
+            elapsed: start.elapsed(),
+            rays,
+            stopped,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::checkpoint::{read_checkpoint, write_checkpoint};
+    use crate::exr::{Compression, ExrOptions, PixelType};
+    use crate::film::Bounds;
+    use crate::output;
+    use crate::tonemap::{ToneCurve, ToneMapper};
+    use std::fs;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    fn settings() -> RenderSettings {
+        RenderSettings {
+            width: 24,
+            height: 16,
+            samples: 12,
+            pass_samples: 3,
+            seed: 5,
+            adaptive_threshold: Some(0.2),
+            min_samples: 4,
+            aovs: vec![Aov::Albedo, Aov::ObjectId],
+            filter: FilterKind::Gaussian,
+            ..RenderSettings::default()
+        }
+    }
+
+    fn state(film: &Film) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        film.write_state(&mut bytes).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn resumed_render_matches_uninterrupted_render() {
+        let settings = settings();
+        let scene = Scene::random(settings.seed, 1.5);
+        let render = |renderer: Renderer| futures::executor::block_on(renderer.render(&scene));
+        let uninterrupted = render(Renderer::new(settings.clone())).unwrap();
+        assert_eq!(uninterrupted.passes, 4);
+
+        // Checkpoint after the second pass, and stop there.
+        let mut bytes = Vec::new();
+        let interrupted = Renderer::new(settings.clone()).after_pass(|film, passes| {
+            if passes == 2 {
+                write_checkpoint(&settings.checkpoint_key(), passes, film, &mut bytes)?;
+                return Err(io::Error::new(io::ErrorKind::Interrupted, "interrupted"));
+            }
+            Ok(())
+        });
+        assert!(render(interrupted).is_err());
+
+        let checkpoint = read_checkpoint(&mut bytes.as_slice()).unwrap();
+        assert_eq!(checkpoint.passes, 2);
+        let resumed = render(Renderer::new(settings.clone()).resume(checkpoint).unwrap()).unwrap();
+        assert_eq!(resumed.passes, 4);
+        assert_eq!(state(&resumed.film), state(&uninterrupted.film));
+    }
+
+    #[test]
+    fn resuming_requires_the_same_settings() {
+        let settings = settings();
+        let film = Film::new(settings.width, settings.height, &settings.aovs);
+        let mut bytes = Vec::new();
+        write_checkpoint(&settings.checkpoint_key(), 1, &film, &mut bytes).unwrap();
+        let checkpoint = read_checkpoint(&mut bytes.as_slice()).unwrap();
+        let changed = RenderSettings {
+            seed: 6,
+            ..settings
+        };
+        assert!(Renderer::new(changed).resume(checkpoint).is_err());
+    }
+
+    #[test]
+    fn russian_roulette_leaves_the_mean_unbiased() {
+        let settings = RenderSettings {
+            samples: 64,
+            pass_samples: 64,
+            adaptive_threshold: None,
+            ..settings()
+        };
+        let scene = Scene::random(settings.seed, 1.5);
+        let mean = |roulette_depth: u32| {
+            let settings = RenderSettings {
+                roulette_depth,
+                ..settings.clone()
+            };
+            let film = futures::executor::block_on(Renderer::new(settings).render(&scene))
+                .unwrap()
+                .film;
+            let pixels = film.pixels();
+            pixels
+                .iter()
+                .map(|pixel| pixel.color().luminance())
+                .sum::<f32>()
+                / pixels.len() as f32
+        };
+        // Roulette from the first bounce, against none before the far cutoff.
+        let (with, without) = (mean(1), mean(settings.max_depth));
+        assert!(
+            (with - without).abs() < 0.02 * without,
+            "{} with roulette, {} without",
+            with,
+            without
+        );
+    }
+
+    #[test]
+    fn adaptive_sampling_stops_converged_pixels_early() {
+        let settings = RenderSettings {
+            samples: 64,
+            pass_samples: 16,
+            adaptive_threshold: Some(0.05),
+            min_samples: 4,
+            ..settings()
+        };
+        let scene = Scene::random(settings.seed, 1.5);
+        let rendered =
+            futures::executor::block_on(Renderer::new(settings.clone()).render(&scene)).unwrap();
+        let film = rendered.film;
+
+        let mut counts = Vec::new();
+        for (x, y) in film.bounds().pixels() {
+            let samples = film.pixel(x, y).samples;
+            assert!(samples >= settings.min_samples);
+            // Pixels only stop short of the budget once they meet the target.
+            if samples < settings.samples {
+                assert!(film.statistics(x, y).relative_error() < 0.05);
+            }
+            counts.push(samples);
+        }
+        // The flat sky converges at once, while the noisy ground keeps going.
+        assert!(counts.contains(&settings.min_samples));
+        assert!(counts.contains(&settings.samples));
+    }
+
+    #[test]
+    fn ids_survive_later_passes() {
+        let settings = settings();
+        let scene = Scene::random(settings.seed, 1.5);
+        let render = |settings: RenderSettings| {
+            futures::executor::block_on(Renderer::new(settings).render(&scene))
+                .unwrap()
+                .film
+        };
+        let ids = |film: &Film| -> Vec<f32> {
+            film.bounds()
+                .pixels()
+                .map(|(x, y)| film.aov(1, x, y)[0])
+                .collect()
+        };
+        let passes = ids(&render(settings.clone()));
+        let single = ids(&render(RenderSettings {
+            pass_samples: settings.samples,
+            ..settings
+        }));
+        assert!(passes.iter().filter(|id| **id > 0.0).count() > passes.len() / 2);
+        assert_eq!(passes, single);
+    }
+
+    #[test]
+    fn previews_are_replaced_atomically() {
+        let settings = settings();
+        let scene = Scene::random(settings.seed, 1.5);
+        let directory = std::env::temp_dir().join(format!("preview-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("preview.pfm");
+        let mut complete = Vec::new();
+        output::write_pfm(
+            &Film::new(settings.width, settings.height, &[]),
+            &mut complete,
+        )
+        .unwrap();
+        let tone_mapper = ToneMapper::new(0.0, None, ToneCurve::Clamp);
+        let exr = ExrOptions {
+            pixel_type: PixelType::Half,
+            compression: Compression::None,
+            attributes: Vec::new(),
+        };
+
+        // Read the preview over and over while the render replaces it, and
+        // check that it is never seen half written.
+        let done = AtomicBool::new(false);
+        let (rendered, reads) = thread::scope(|scope| {
+            let reader = scope.spawn(|| {
+                let mut reads = 0;
+                while !done.load(Ordering::Relaxed) {
+                    if let Ok(bytes) = fs::read(&path) {
+                        assert_eq!(bytes.len(), complete.len());
+                        reads += 1;
+                    }
+                }
+                reads
+            });
+            let renderer = Renderer::new(settings.clone())
+                .after_pass(|film, _| output::save_preview(film, &tone_mapper, &exr, &path));
+            let rendered = futures::executor::block_on(renderer.render(&scene));
+            done.store(true, Ordering::Relaxed);
+            (rendered.unwrap(), reader.join().unwrap())
+        });
+        assert!(reads > 0);
+
+        // The last pass's preview is left, and nothing else.
+        let mut last = Vec::new();
+        output::write_pfm(&rendered.film, &mut last).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), last);
+        let names: Vec<_> = fs::read_dir(&directory)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        assert_eq!(names, vec!["preview.pfm"]);
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn rejects_invalid_settings() {
+        let scene = Scene::random(0, 1.5);
+        let invalid = [
+            RenderSettings {
+                pass_samples: 0,
+                ..settings()
+            },
+            RenderSettings {
+                width: 0,
+                ..settings()
+            },
+            RenderSettings {
+                height: 0,
+                ..settings()
+            },
+            RenderSettings {
+                filter_radius: Some(0.0),
+                ..settings()
+            },
+            RenderSettings {
+                filter_radius: Some(-1.0),
+                ..settings()
+            },
+            RenderSettings {
+                filter_radius: Some(f32::NAN),
+                ..settings()
+            },
+        ];
+        for settings in invalid.iter() {
+            assert!(settings.validate().is_err());
+            let rendered =
+                futures::executor::block_on(Renderer::new(settings.clone()).render(&scene));
+            assert_eq!(
+                rendered.err().map(|error| error.kind()),
+                Some(io::ErrorKind::InvalidInput)
+            );
+        }
+        assert_eq!(settings().validate(), Ok(()));
+    }
+
+    /// Cancels a render partway through its second pass.
+    struct CancelOnSecondPass(CancellationToken);
+
+    impl RenderObserver for CancelOnSecondPass {
+        fn on_tile(&mut self, pass: usize, _tile: Bounds) {
+            if pass == 1 {
+                self.0.cancel();
+            }
+        }
+    }
+
+    #[test]
+    fn cancelled_render_keeps_completed_passes() {
+        let settings = settings();
+        let scene = Scene::random(settings.seed, 1.5);
+
+        let mut first_pass = Vec::new();
+        let renderer = Renderer::new(settings.clone()).after_pass(|film, passes| {
+            if passes == 1 {
+                first_pass = state(film);
+            }
+            Ok(())
+        });
+        futures::executor::block_on(renderer.render(&scene)).unwrap();
+
+        let cancellation = CancellationToken::new();
+        let renderer = Renderer::new(settings)
+            .observer(CancelOnSecondPass(cancellation.clone()))
+            .cancellation(cancellation);
+        let cancelled = futures::executor::block_on(renderer.render(&scene)).unwrap();
+        assert_eq!(cancelled.stopped, Some(StopReason::Cancelled));
+        assert_eq!(cancelled.passes, 1);
+        assert_eq!(state(&cancelled.film), first_pass);
+    }
+}
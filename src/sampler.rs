@@ -0,0 +1,495 @@
+use std::str::FromStr;
+
+/// A source of sample values in [0, 1) for each dimension of the integrand.
+/// Every sample is identified by its pixel and index, so the values drawn for
+/// it are the same no matter the order in which samples are taken.
+pub trait Sampler: Send {
+    fn start_pixel_sample(&mut self, pixel: (u32, u32), index: usize);
+
+    fn get_1d(&mut self) -> f32;
+
+    fn get_2d(&mut self) -> (f32, f32);
+
+    /// The sample offset within the pixel, which samplers may stratify
+    /// differently from the other dimensions.
+    fn get_pixel_2d(&mut self) -> (f32, f32) {
+        self.get_2d()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SamplerKind {
+    Independent,
+    Stratified,
+    Halton,
+    Sobol,
+}
+
+impl SamplerKind {
+    pub const NAMES: &'static [&'static str] = &["independent", "stratified", "halton", "sobol"];
+
+    pub fn build(self, samples_per_pixel: usize, seed: u64) -> Box<dyn Sampler> {
+        match self {
+            SamplerKind::Independent => Box::new(IndependentSampler::new(seed)),
+            SamplerKind::Stratified => Box::new(StratifiedSampler::new(samples_per_pixel, seed)),
+            SamplerKind::Halton => Box::new(HaltonSampler::new(seed)),
+            SamplerKind::Sobol => Box::new(SobolSampler::new(samples_per_pixel, seed)),
+        }
+    }
+}
+
+impl FromStr for SamplerKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<SamplerKind, String> {
+        match s {
+            "independent" => Ok(SamplerKind::Independent),
+            "stratified" => Ok(SamplerKind::Stratified),
+            "halton" => Ok(SamplerKind::Halton),
+            "sobol" => Ok(SamplerKind::Sobol),
+            _ => Err(format!("unknown sampler: {}", s)),
+        }
+    }
+}
+
+const ONE_MINUS_EPSILON: f32 = 1.0 - f32::EPSILON / 2.0;
+
+/// The PCG32 generator of O'Neill, which unlike the generators in `rand` can
+/// jump ahead in its stream in logarithmic time.
+#[derive(Clone, Debug)]
+pub struct Pcg32 {
+    state: u64,
+    inc: u64,
+}
+
+impl Pcg32 {
+    const MULTIPLIER: u64 = 0x5851_f42d_4c95_7f2d;
+
+    pub fn new(sequence: u64, seed: u64) -> Pcg32 {
+        let mut rng = Pcg32 {
+            state: 0,
+            inc: (sequence << 1) | 1,
+        };
+        rng.next_u32();
+        rng.state = rng.state.wrapping_add(seed);
+        rng.next_u32();
+        rng
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        let old = self.state;
+        self.state = old.wrapping_mul(Pcg32::MULTIPLIER).wrapping_add(self.inc);
+        let xor_shifted = (((old >> 18) ^ old) >> 27) as u32;
+        let rotation = (old >> 59) as u32;
+        xor_shifted.rotate_right(rotation)
+    }
+
+    pub fn next_f32(&mut self) -> f32 {
+        (self.next_u32() as f32 * (1.0 / 4_294_967_296.0)).min(ONE_MINUS_EPSILON)
+    }
+
+    /// Skips the next `delta` values of the stream.
+    pub fn advance(&mut self, mut delta: u64) {
+        let (mut acc_mult, mut acc_plus) = (1u64, 0u64);
+        let (mut cur_mult, mut cur_plus) = (Pcg32::MULTIPLIER, self.inc);
+        while delta > 0 {
+            if delta & 1 == 1 {
+                acc_mult = acc_mult.wrapping_mul(cur_mult);
+                acc_plus = acc_plus.wrapping_mul(cur_mult).wrapping_add(cur_plus);
+            }
+            cur_plus = cur_mult.wrapping_add(1).wrapping_mul(cur_plus);
+            cur_mult = cur_mult.wrapping_mul(cur_mult);
+            delta /= 2;
+        }
+        self.state = acc_mult.wrapping_mul(self.state).wrapping_add(acc_plus);
+    }
+}
+
+fn mix_bits(mut v: u64) -> u64 {
+    v ^= v >> 31;
+    v = v.wrapping_mul(0x7fb5_d329_728e_a185);
+    v ^= v >> 27;
+    v = v.wrapping_mul(0x81da_def4_bc2d_d44d);
+    v ^= v >> 33;
+    v
+}
+
+/// Hashes the values into 64 well-mixed bits.
+pub fn hash(values: &[u64]) -> u64 {
+    values.iter().fold(0x9e37_79b9_7f4a_7c15, |h, v| {
+        mix_bits(h ^ v.wrapping_add(h << 6))
+    })
+}
+
+fn pixel_key(pixel: (u32, u32)) -> u64 {
+    (u64::from(pixel.1) << 32) | u64::from(pixel.0)
+}
+
+/// Returns the `i`th element of a pseudo-random permutation of `0..n` chosen
+/// by `seed`, without materializing the permutation (Kensler 2013).
+fn permutation_element(mut i: u32, n: u32, seed: u32) -> u32 {
+    let mut w = n - 1;
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+    loop {
+        i ^= seed;
+        i = i.wrapping_mul(0xe170_893d);
+        i ^= seed >> 16;
+        i ^= (i & w) >> 4;
+        i ^= seed >> 8;
+        i = i.wrapping_mul(0x0929_eb3f);
+        i ^= seed >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | seed >> 27);
+        i = i.wrapping_mul(0x6935_fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dc_b303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e50_1cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860_a3df);
+        i &= w;
+        i ^= i >> 5;
+        if i < n {
+            break;
+        }
+    }
+    (i.wrapping_add(seed)) % n
+}
+
+/// Uniform random samples with no correlation between dimensions.
+pub struct IndependentSampler {
+    seed: u64,
+    rng: Pcg32,
+}
+
+impl IndependentSampler {
+    pub fn new(seed: u64) -> IndependentSampler {
+        IndependentSampler {
+            seed,
+            rng: Pcg32::new(0, seed),
+        }
+    }
+}
+
+impl Sampler for IndependentSampler {
+    fn start_pixel_sample(&mut self, pixel: (u32, u32), index: usize) {
+        self.rng = Pcg32::new(hash(&[pixel_key(pixel), self.seed]), self.seed);
+        self.rng.advance(index as u64 * 65536);
+    }
+
+    fn get_1d(&mut self) -> f32 {
+        self.rng.next_f32()
+    }
+
+    fn get_2d(&mut self) -> (f32, f32) {
+        (self.rng.next_f32(), self.rng.next_f32())
+    }
+}
+
+/// Jittered samples over a grid of strata in each dimension, with the strata
+/// of different dimensions randomly shuffled against each other.
+pub struct StratifiedSampler {
+    x_strata: u32,
+    y_strata: u32,
+    seed: u64,
+    pixel: (u32, u32),
+    index: u32,
+    dimension: u64,
+    rng: Pcg32,
+}
+
+impl StratifiedSampler {
+    pub fn new(samples_per_pixel: usize, seed: u64) -> StratifiedSampler {
+        // Choose the most square grid with exactly one sample per stratum.
+        let samples = samples_per_pixel.max(1) as u32;
+        let mut x_strata = (samples as f32).sqrt() as u32;
+        while !samples.is_multiple_of(x_strata) {
+            x_strata -= 1;
+        }
+        StratifiedSampler {
+            x_strata,
+            y_strata: samples / x_strata,
+            seed,
+            pixel: (0, 0),
+            index: 0,
+            dimension: 0,
+            rng: Pcg32::new(0, seed),
+        }
+    }
+
+    fn stratum(&mut self) -> Option<u32> {
+        let strata = self.x_strata * self.y_strata;
+        let key = hash(&[pixel_key(self.pixel), self.dimension, self.seed]);
+        self.dimension += 1;
+        if self.index < strata {
+            Some(permutation_element(self.index, strata, key as u32))
+        } else {
+            None
+        }
+    }
+}
+
+impl Sampler for StratifiedSampler {
+    fn start_pixel_sample(&mut self, pixel: (u32, u32), index: usize) {
+        self.pixel = pixel;
+        self.index = index as u32;
+        self.dimension = 0;
+        self.rng = Pcg32::new(hash(&[pixel_key(pixel), self.seed]), self.seed);
+        self.rng.advance(index as u64 * 65536);
+    }
+
+    fn get_1d(&mut self) -> f32 {
+        let strata = (self.x_strata * self.y_strata) as f32;
+        let jitter = self.rng.next_f32();
+        match self.stratum() {
+            Some(stratum) => ((stratum as f32 + jitter) / strata).min(ONE_MINUS_EPSILON),
+            None => jitter,
+        }
+    }
+
+    fn get_2d(&mut self) -> (f32, f32) {
+        let jitter = (self.rng.next_f32(), self.rng.next_f32());
+        match self.stratum() {
+            Some(stratum) => {
+                let x = stratum % self.x_strata;
+                let y = stratum / self.x_strata;
+                (
+                    ((x as f32 + jitter.0) / self.x_strata as f32).min(ONE_MINUS_EPSILON),
+                    ((y as f32 + jitter.1) / self.y_strata as f32).min(ONE_MINUS_EPSILON),
+                )
+            }
+            None => jitter,
+        }
+    }
+}
+
+const PRIMES: &[u64] = &[
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53, 59, 61, 67, 71, 73, 79, 83, 89, 97,
+    101, 103, 107, 109, 113, 127, 131, 137, 139, 149, 151, 157, 163, 167, 173, 179, 181, 191, 193,
+    197, 199, 211, 223, 227, 229, 233, 239, 241, 251, 257, 263, 269, 271, 277, 281, 283, 293, 307,
+    311,
+];
+
+/// The radical inverse of `a` in the given prime base, with the digits
+/// scrambled by a nested random permutation derived from `seed` (Owen
+/// scrambling), which keeps the stratification of the Halton sequence.
+fn owen_scrambled_radical_inverse(base: u64, mut a: u64, seed: u64) -> f32 {
+    let inv_base = 1.0 / base as f64;
+    let mut inv_base_m = 1.0;
+    let mut reversed_digits: u64 = 0;
+    let limit = u64::MAX / base - base;
+    while 1.0 - (base - 1) as f64 * inv_base_m < 1.0 && reversed_digits < limit {
+        let next = a / base;
+        let digit = a - next * base;
+        let digit_seed = mix_bits(seed ^ reversed_digits) as u32;
+        let digit = u64::from(permutation_element(digit as u32, base as u32, digit_seed));
+        reversed_digits = reversed_digits * base + digit;
+        inv_base_m *= inv_base;
+        a = next;
+    }
+    ((inv_base_m * reversed_digits as f64) as f32).min(ONE_MINUS_EPSILON)
+}
+
+/// The Halton sequence with each dimension in successive prime bases. Each
+/// pixel runs through the same sequence under its own scramble, falling back
+/// to independent samples once the prime table runs out.
+pub struct HaltonSampler {
+    seed: u64,
+    pixel: (u32, u32),
+    index: u64,
+    dimension: usize,
+    rng: Pcg32,
+}
+
+impl HaltonSampler {
+    pub fn new(seed: u64) -> HaltonSampler {
+        HaltonSampler {
+            seed,
+            pixel: (0, 0),
+            index: 0,
+            dimension: 0,
+            rng: Pcg32::new(0, seed),
+        }
+    }
+
+    fn next(&mut self) -> f32 {
+        let dimension = self.dimension;
+        self.dimension += 1;
+        match PRIMES.get(dimension) {
+            Some(&base) => {
+                let scramble = hash(&[pixel_key(self.pixel), dimension as u64, self.seed]);
+                owen_scrambled_radical_inverse(base, self.index, scramble)
+            }
+            None => self.rng.next_f32(),
+        }
+    }
+}
+
+impl Sampler for HaltonSampler {
+    fn start_pixel_sample(&mut self, pixel: (u32, u32), index: usize) {
+        self.pixel = pixel;
+        self.index = index as u64;
+        self.dimension = 0;
+        self.rng = Pcg32::new(hash(&[pixel_key(pixel), self.seed]), self.seed);
+        self.rng.advance(index as u64 * 65536);
+    }
+
+    fn get_1d(&mut self) -> f32 {
+        self.next()
+    }
+
+    fn get_2d(&mut self) -> (f32, f32) {
+        (self.next(), self.next())
+    }
+}
+
+/// Scrambles the bits of a sample in [0, 2^32) as if by a random nested
+/// permutation of its binary digits (Laine and Karras 2011).
+fn fast_owen_scramble(v: u32, seed: u32) -> u32 {
+    let mut v = v.reverse_bits();
+    v ^= v.wrapping_mul(0x3d20_adea);
+    v = v.wrapping_add(seed);
+    v = v.wrapping_mul((seed >> 16) | 1);
+    v ^= v.wrapping_mul(0x0552_6c56);
+    v ^= v.wrapping_mul(0x53a2_2864);
+    v.reverse_bits()
+}
+
+/// The first two dimensions of the Sobol sequence. The first is the van der
+/// Corput sequence; the second uses the direction numbers of the primitive
+/// polynomial x + 1.
+fn sobol_02(index: u32) -> (u32, u32) {
+    let (mut x, mut y) = (0u32, 0u32);
+    let mut direction = 1u32 << 31;
+    let mut i = index;
+    let mut bit = 0;
+    while i != 0 {
+        if i & 1 == 1 {
+            x ^= 1 << (31 - bit);
+            y ^= direction;
+        }
+        direction ^= direction >> 1;
+        i >>= 1;
+        bit += 1;
+    }
+    (x, y)
+}
+
+fn to_unit(v: u32) -> f32 {
+    (v as f32 * (1.0 / 4_294_967_296.0)).min(ONE_MINUS_EPSILON)
+}
+
+/// Padded Owen-scrambled Sobol samples: each pair of dimensions is an
+/// independently scrambled (0, 2)-sequence, with the sample order shuffled per
+/// dimension so that pairs are decorrelated from each other.
+pub struct SobolSampler {
+    samples_per_pixel: u32,
+    seed: u64,
+    pixel: (u32, u32),
+    index: u32,
+    dimension: u64,
+}
+
+impl SobolSampler {
+    pub fn new(samples_per_pixel: usize, seed: u64) -> SobolSampler {
+        SobolSampler {
+            samples_per_pixel: samples_per_pixel.max(1) as u32,
+            seed,
+            pixel: (0, 0),
+            index: 0,
+            dimension: 0,
+        }
+    }
+
+    fn shuffled_index(&mut self) -> (u32, u64) {
+        let key = hash(&[pixel_key(self.pixel), self.dimension, self.seed]);
+        self.dimension += 1;
+        let index = if self.index < self.samples_per_pixel {
+            permutation_element(self.index, self.samples_per_pixel, key as u32)
+        } else {
+            self.index
+        };
+        (index, key)
+    }
+}
+
+impl Sampler for SobolSampler {
+    fn start_pixel_sample(&mut self, pixel: (u32, u32), index: usize) {
+        self.pixel = pixel;
+        self.index = index as u32;
+        self.dimension = 0;
+    }
+
+    fn get_1d(&mut self) -> f32 {
+        let (index, key) = self.shuffled_index();
+        let (x, _) = sobol_02(index);
+        to_unit(fast_owen_scramble(x, key as u32))
+    }
+
+    fn get_2d(&mut self) -> (f32, f32) {
+        let (index, key) = self.shuffled_index();
+        let (x, y) = sobol_02(index);
+        (
+            to_unit(fast_owen_scramble(x, key as u32)),
+            to_unit(fast_owen_scramble(y, (key >> 32) as u32)),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KINDS: &[SamplerKind] = &[
+        SamplerKind::Independent,
+        SamplerKind::Stratified,
+        SamplerKind::Halton,
+        SamplerKind::Sobol,
+    ];
+
+    fn draw(sampler: &mut dyn Sampler, pixel: (u32, u32), index: usize) -> Vec<f32> {
+        sampler.start_pixel_sample(pixel, index);
+        let mut values = Vec::new();
+        for _ in 0..20 {
+            let (x, y) = sampler.get_2d();
+            values.extend(&[x, y, sampler.get_1d()]);
+        }
+        values
+    }
+
+    #[test]
+    fn samples_are_in_range_and_reproducible() {
+        for kind in KINDS {
+            let mut sampler = kind.build(16, 7);
+            for index in 0..32 {
+                let values = draw(&mut *sampler, (3, 5), index);
+                assert!(values.iter().all(|v| (0.0..1.0).contains(v)), "{:?}", kind);
+                assert_eq!(values, draw(&mut *kind.build(16, 7), (3, 5), index));
+            }
+        }
+    }
+
+    #[test]
+    fn pixel_samples_are_stratified() {
+        // With 16 samples, each of the 4x4 cells of the pixel gets one sample.
+        for kind in &[SamplerKind::Stratified, SamplerKind::Sobol] {
+            for dimension in 0..4 {
+                let mut sampler = kind.build(16, 11);
+                let mut cells = [0; 16];
+                for index in 0..16 {
+                    sampler.start_pixel_sample((1, 2), index);
+                    for _ in 0..dimension {
+                        sampler.get_2d();
+                    }
+                    let (x, y) = sampler.get_2d();
+                    cells[(y * 4.0) as usize * 4 + (x * 4.0) as usize] += 1;
+                }
+                assert_eq!(cells, [1; 16], "{:?} dimension {}", kind, dimension);
+            }
+        }
+    }
+}
@@ -0,0 +1,247 @@
+use std::f32::consts::{FRAC_PI_2, FRAC_PI_4, PI};
+
+use crate::geometry::Vec3;
+
+// Warps from uniform samples on [0, 1)^n to other domains. Each warp returns
+// the warped point along with its probability density with respect to the
+// measure of the target domain (area for disks, solid angle for spheres and
+// hemispheres, volume for balls). Hemispheres are oriented around +z.
+
+pub fn uniform_disk(u: (f32, f32)) -> ((f32, f32), f32) {
+    // Shirley-Chiu concentric mapping, which keeps strata adjacent.
+    let (a, b) = (2.0 * u.0 - 1.0, 2.0 * u.1 - 1.0);
+    let point = if a == 0.0 && b == 0.0 {
+        (0.0, 0.0)
+    } else if a.abs() > b.abs() {
+        let theta = FRAC_PI_4 * (b / a);
+        (a * theta.cos(), a * theta.sin())
+    } else {
+        let theta = FRAC_PI_2 - FRAC_PI_4 * (a / b);
+        (b * theta.cos(), b * theta.sin())
+    };
+    (point, uniform_disk_pdf())
+}
+
+pub fn uniform_disk_pdf() -> f32 {
+    1.0 / PI
+}
+
+pub fn uniform_sphere(u: (f32, f32)) -> (Vec3, f32) {
+    let z = 1.0 - 2.0 * u.0;
+    let r = (1.0 - z * z).max(0.0).sqrt();
+    let phi = 2.0 * PI * u.1;
+    (
+        Vec3::new(r * phi.cos(), r * phi.sin(), z),
+        uniform_sphere_pdf(),
+    )
+}
+
+pub fn uniform_sphere_pdf() -> f32 {
+    1.0 / (4.0 * PI)
+}
+
+pub fn uniform_ball(u: (f32, f32), w: f32) -> (Vec3, f32) {
+    let (direction, _) = uniform_sphere(u);
+    (w.cbrt() * direction, uniform_ball_pdf())
+}
+
+pub fn uniform_ball_pdf() -> f32 {
+    3.0 / (4.0 * PI)
+}
+
+pub fn uniform_hemisphere(u: (f32, f32)) -> (Vec3, f32) {
+    let z = u.0;
+    let r = (1.0 - z * z).max(0.0).sqrt();
+    let phi = 2.0 * PI * u.1;
+    (
+        Vec3::new(r * phi.cos(), r * phi.sin(), z),
+        uniform_hemisphere_pdf(),
+    )
+}
+
+pub fn uniform_hemisphere_pdf() -> f32 {
+    1.0 / (2.0 * PI)
+}
+
+pub fn cosine_hemisphere(u: (f32, f32)) -> (Vec3, f32) {
+    // Malley's method: project uniform disk samples up onto the hemisphere.
+    let ((x, y), _) = uniform_disk(u);
+    let z = (1.0 - x * x - y * y).max(0.0).sqrt();
+    (Vec3::new(x, y, z), cosine_hemisphere_pdf(z))
+}
+
+pub fn cosine_hemisphere_pdf(cos_theta: f32) -> f32 {
+    cos_theta.max(0.0) / PI
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    const SAMPLES: usize = 200_000;
+
+    /// Pearson's chi-square statistic, pooling bins whose expected count is
+    /// too small for the test to be meaningful.
+    pub fn chi_square(observed: &[f64], expected: &[f64]) -> (f64, usize) {
+        let mut statistic = 0.0;
+        let mut bins = 0;
+        let (mut pooled_observed, mut pooled_expected) = (0.0, 0.0);
+        for (o, e) in observed.iter().zip(expected) {
+            if *e < 5.0 {
+                pooled_observed += o;
+                pooled_expected += e;
+            } else {
+                statistic += (o - e) * (o - e) / e;
+                bins += 1;
+            }
+        }
+        if pooled_expected > 0.0 {
+            statistic += (pooled_observed - pooled_expected) * (pooled_observed - pooled_expected)
+                / pooled_expected;
+            bins += 1;
+        }
+        (statistic, bins - 1)
+    }
+
+    /// Wilson-Hilferty approximation of the chi-square quantile at a
+    /// significance level of 0.001.
+    pub fn critical_value(dof: usize) -> f64 {
+        const Z: f64 = 3.090;
+        let k = dof as f64;
+        let h = 2.0 / (9.0 * k);
+        k * (1.0 - h + Z * h.sqrt()).powi(3)
+    }
+
+    fn assert_fits(name: &str, observed: &[f64], expected: &[f64]) {
+        let total: f64 = expected.iter().sum();
+        assert!(
+            (total - SAMPLES as f64).abs() < 1e-3 * SAMPLES as f64,
+            "{}: expected frequencies sum to {}",
+            name,
+            total
+        );
+        let (statistic, dof) = chi_square(observed, expected);
+        let critical = critical_value(dof);
+        assert!(
+            statistic < critical,
+            "{}: chi-square statistic {} exceeds {} with {} degrees of freedom",
+            name,
+            statistic,
+            critical,
+            dof
+        );
+    }
+
+    fn rng() -> StdRng {
+        StdRng::seed_from_u64(0x5eed)
+    }
+
+    fn phi_bin(x: f32, y: f32, bins: usize) -> usize {
+        let phi = y.atan2(x).rem_euclid(2.0 * PI);
+        ((phi / (2.0 * PI) * bins as f32) as usize).min(bins - 1)
+    }
+
+    /// Bins directions by (z, phi), where equal steps in z subtend equal solid
+    /// angle. `density` is the integral of the pdf over [z0, z1] per radian.
+    fn test_directions<W, D>(name: &str, z_min: f32, warp: W, density: D)
+    where
+        W: Fn((f32, f32)) -> (Vec3, f32),
+        D: Fn(f64, f64) -> f64,
+    {
+        const Z_BINS: usize = 10;
+        const PHI_BINS: usize = 20;
+        let mut rng = rng();
+        let mut observed = vec![0.0; Z_BINS * PHI_BINS];
+        for _ in 0..SAMPLES {
+            let (v, pdf) = warp((rng.gen(), rng.gen()));
+            assert!(
+                (v.length() - 1.0).abs() < 1e-4,
+                "{}: {:?} not unit",
+                name,
+                v
+            );
+            assert!(pdf > 0.0, "{}: non-positive pdf", name);
+            let z = (v.z() - z_min) / (1.0 - z_min) * Z_BINS as f32;
+            let z = (z as usize).min(Z_BINS - 1);
+            observed[z * PHI_BINS + phi_bin(v.x(), v.y(), PHI_BINS)] += 1.0;
+        }
+        let d_phi = 2.0 * std::f64::consts::PI / PHI_BINS as f64;
+        let d_z = (1.0 - z_min as f64) / Z_BINS as f64;
+        let expected: Vec<f64> = (0..Z_BINS * PHI_BINS)
+            .map(|bin| {
+                let z0 = z_min as f64 + (bin / PHI_BINS) as f64 * d_z;
+                SAMPLES as f64 * density(z0, z0 + d_z) * d_phi
+            })
+            .collect();
+        assert_fits(name, &observed, &expected);
+    }
+
+    #[test]
+    fn uniform_sphere_is_uniform() {
+        let pdf = uniform_sphere_pdf() as f64;
+        test_directions("uniform_sphere", -1.0, uniform_sphere, |z0, z1| {
+            pdf * (z1 - z0)
+        });
+    }
+
+    #[test]
+    fn uniform_hemisphere_is_uniform() {
+        let pdf = uniform_hemisphere_pdf() as f64;
+        test_directions("uniform_hemisphere", 0.0, uniform_hemisphere, |z0, z1| {
+            pdf * (z1 - z0)
+        });
+    }
+
+    #[test]
+    fn cosine_hemisphere_is_cosine_weighted() {
+        test_directions("cosine_hemisphere", 0.0, cosine_hemisphere, |z0, z1| {
+            (z1 * z1 - z0 * z0) / (2.0 * std::f64::consts::PI)
+        });
+        let (v, pdf) = cosine_hemisphere((0.3, 0.7));
+        assert!((pdf - cosine_hemisphere_pdf(v.z())).abs() < 1e-6);
+    }
+
+    #[test]
+    fn uniform_disk_is_uniform() {
+        const R_BINS: usize = 10;
+        const PHI_BINS: usize = 20;
+        let mut rng = rng();
+        let mut observed = vec![0.0; R_BINS * PHI_BINS];
+        for _ in 0..SAMPLES {
+            let ((x, y), pdf) = uniform_disk((rng.gen(), rng.gen()));
+            assert!(x * x + y * y <= 1.0 + 1e-5);
+            assert!((pdf - uniform_disk_pdf()).abs() < 1e-6);
+            // Equal steps in r^2 cover equal areas.
+            let r = (((x * x + y * y) * R_BINS as f32) as usize).min(R_BINS - 1);
+            observed[r * PHI_BINS + phi_bin(x, y, PHI_BINS)] += 1.0;
+        }
+        let expected = vec![SAMPLES as f64 / (R_BINS * PHI_BINS) as f64; R_BINS * PHI_BINS];
+        assert_fits("uniform_disk", &observed, &expected);
+    }
+
+    #[test]
+    fn uniform_ball_is_uniform() {
+        const R_BINS: usize = 8;
+        const Z_BINS: usize = 5;
+        const PHI_BINS: usize = 10;
+        let mut rng = rng();
+        let mut observed = vec![0.0; R_BINS * Z_BINS * PHI_BINS];
+        for _ in 0..SAMPLES {
+            let (v, pdf) = uniform_ball((rng.gen(), rng.gen()), rng.gen());
+            let r = v.length();
+            assert!(r <= 1.0 + 1e-5);
+            assert!((pdf - uniform_ball_pdf()).abs() < 1e-6);
+            // Equal steps in r^3 and in z / r cover equal volumes.
+            let r_bin = ((r.powi(3) * R_BINS as f32) as usize).min(R_BINS - 1);
+            let cos_theta = if r > 0.0 { v.z() / r } else { 0.0 };
+            let z_bin = (((cos_theta + 1.0) / 2.0 * Z_BINS as f32) as usize).min(Z_BINS - 1);
+            let bin = (r_bin * Z_BINS + z_bin) * PHI_BINS + phi_bin(v.x(), v.y(), PHI_BINS);
+            observed[bin] += 1.0;
+        }
+        let bins = R_BINS * Z_BINS * PHI_BINS;
+        let expected = vec![SAMPLES as f64 / bins as f64; bins];
+        assert_fits("uniform_ball", &observed, &expected);
+    }
+}
@@ -0,0 +1,74 @@
+use crate::builtin::{Builtin, SceneDescription};
+use crate::camera::Camera;
+use crate::geometry::Vec3;
+use crate::object::World;
+
+/// The light arriving from beyond the scene along rays that miss everything.
+#[derive(Clone, Debug)]
+pub enum Background {
+    /// A gradient from white at the horizon to blue overhead.
+    Sky,
+    Uniform(Vec3),
+}
+
+impl Background {
+    pub fn radiance(&self, direction: &Vec3) -> Vec3 {
+        match self {
+            Background::Sky => {
+                let t = 0.5 * (direction.normalized().y() + 1.0);
+                (1.0 - t) * Vec3::new(1.0, 1.0, 1.0) + t * Vec3::new(0.5, 0.7, 1.0)
+            }
+            Background::Uniform(color) => color.clone(),
+        }
+    }
+}
+
+/// The objects to render, the camera to view them through and the light
+/// around them.
+pub struct Scene {
+    world: World,
+    camera: Camera,
+    background: Background,
+    description: Option<SceneDescription>,
+}
+
+impl Scene {
+    pub fn new(world: World, camera: Camera, background: Background) -> Scene {
+        Scene {
+            world,
+            camera,
+            background,
+            description: None,
+        }
+    }
+
+    /// The cover scene of *Ray Tracing in One Weekend*, randomized by `seed`
+    /// and viewed through a camera with the given aspect ratio.
+    pub fn random(seed: u64, aspect: f32) -> Scene {
+        SceneDescription::new(Builtin::Random, seed).build(aspect)
+    }
+
+    /// Records how the scene was built, so that it can be built again
+    /// elsewhere.
+    pub fn with_description(mut self, description: SceneDescription) -> Scene {
+        self.description = Some(description);
+        self
+    }
+
+    pub fn world(&self) -> &World {
+        &self.world
+    }
+
+    pub fn camera(&self) -> &Camera {
+        &self.camera
+    }
+
+    pub fn background(&self) -> &Background {
+        &self.background
+    }
+
+    /// How the scene was built, if it is a built-in scene.
+    pub fn description(&self) -> Option<&SceneDescription> {
+        self.description.as_ref()
+    }
+}
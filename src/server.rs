@@ -0,0 +1,759 @@
+use std::any::Any;
+use std::collections::BTreeMap;
+use std::convert::{Infallible, TryFrom};
+use std::io;
+use std::panic::{self, AssertUnwindSafe};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use hyper::body::HttpBody;
+use hyper::header::{self, HeaderValue};
+use hyper::server::conn::Http;
+use hyper::service::service_fn;
+use hyper::{Body, Method, Request, Response, StatusCode};
+use serde_json::{json, Map, Value};
+use tokio::net::TcpListener;
+
+use crate::builtin::{Builtin, SceneDescription};
+use crate::exr::{Compression, ExrOptions, PixelType};
+use crate::film::{Bounds, Film};
+use crate::output::{self, Format};
+use crate::progress::{CancellationToken, RenderObserver};
+use crate::renderer::{RenderOutput, RenderSettings, Renderer, StopReason, UNLIMITED_SAMPLES};
+use crate::scene::Scene;
+use crate::tonemap::{ToneCurve, ToneMapper};
+
+// Rendering over a local HTTP API, where each render is a job:
+//
+//   POST   /jobs               starts a job, returning its ID
+//   GET    /jobs               the status of every job
+//   GET    /jobs/{id}          the status of a job
+//   GET    /jobs/{id}/image    the image as of the last completed pass, as
+//                              ?format=ppm (the default), png, pfm or exr
+//   POST   /jobs/{id}/cancel   stops a job after its current pass
+//   DELETE /jobs/{id}          cancels a job and forgets it
+//
+// A job is posted as a JSON object such as
+//
+//   {"scene": {"name": "cornell", "seed": 3}, "settings": {"width": 400}}
+//
+// where the scene is named as by the command line's --builtin option, and
+// its seed defaults to the sampler's. The settings take the names of the
+// command line options, with durations in seconds and lists as arrays. Jobs
+// render on the runtime's blocking threads, so that requests are answered
+// while they run. Since any client may post jobs, the server limits their
+// size and number, and forgets the oldest finished jobs.
+
+/// The largest width or height of a job's image, which keeps the number of
+/// pixels well within what a film can hold.
+pub const MAX_SIZE: u32 = 16384;
+
+/// The largest sample budget of a job, which is also the budget of jobs that
+/// stop on time or noise instead.
+pub const MAX_SAMPLES: usize = UNLIMITED_SAMPLES;
+
+/// The longest job description the server reads, in bytes.
+pub const MAX_BODY: usize = 1 << 16;
+
+/// The most jobs that may render at once. Jobs posted beyond it are refused
+/// rather than queued.
+pub const MAX_RUNNING_JOBS: usize = 4;
+
+/// The most finished, failed or cancelled jobs the server keeps for their
+/// images, forgetting the oldest beyond it.
+pub const MAX_FINISHED_JOBS: usize = 16;
+
+/// A render requested of the server.
+pub struct JobDescription {
+    pub settings: RenderSettings,
+    pub scene: SceneDescription,
+    pub tone_mapper: ToneMapper,
+}
+
+fn number(value: &Value, key: &str) -> Result<f64, String> {
+    value
+        .as_f64()
+        .ok_or_else(|| format!("{} must be a number", key))
+}
+
+fn positive(value: &Value, key: &str) -> Result<f32, String> {
+    match number(value, key)? {
+        number if number > 0.0 => Ok(number as f32),
+        _ => Err(format!("{} must be positive", key)),
+    }
+}
+
+fn integer(value: &Value, key: &str) -> Result<u64, String> {
+    value
+        .as_u64()
+        .ok_or_else(|| format!("{} must be a non-negative integer", key))
+}
+
+fn positive_integer(value: &Value, key: &str) -> Result<u64, String> {
+    match integer(value, key)? {
+        0 => Err(format!("{} must be positive", key)),
+        integer => Ok(integer),
+    }
+}
+
+fn small_integer(value: &Value, key: &str) -> Result<u32, String> {
+    u32::try_from(integer(value, key)?).map_err(|_| format!("{} is too large", key))
+}
+
+fn samples(value: &Value, key: &str) -> Result<usize, String> {
+    match positive_integer(value, key)? {
+        samples if samples <= MAX_SAMPLES as u64 => Ok(samples as usize),
+        _ => Err(format!("{} must be at most {}", key, MAX_SAMPLES)),
+    }
+}
+
+fn size(value: &Value, key: &str) -> Result<u32, String> {
+    match u32::try_from(positive_integer(value, key)?) {
+        Ok(size) if size <= MAX_SIZE => Ok(size),
+        _ => Err(format!("{} must be at most {}", key, MAX_SIZE)),
+    }
+}
+
+fn name<T>(value: &Value, key: &str) -> Result<T, String>
+where
+    T: FromStr<Err = String>,
+{
+    value
+        .as_str()
+        .ok_or_else(|| format!("{} must be a string", key))?
+        .parse()
+}
+
+fn parse_settings(object: &Map<String, Value>) -> Result<(RenderSettings, ToneMapper), String> {
+    let mut settings = RenderSettings::default();
+    let mut samples = None;
+    let (mut exposure, mut white_balance, mut tone_curve) = (0.0, None, ToneCurve::Clamp);
+    for (key, value) in object {
+        match key.as_str() {
+            "width" => settings.width = size(value, key)?,
+            "height" => settings.height = size(value, key)?,
+            "samples" => samples = Some(self::samples(value, key)?),
+            "time_limit" => {
+                let seconds = positive(value, key)?;
+                let limit = Duration::try_from_secs_f32(seconds)
+                    .map_err(|_| format!("{} is too long", key))?;
+                settings.time_limit = Some(limit);
+            }
+            "target_noise" => settings.target_noise = Some(positive(value, key)?),
+            "pass_samples" => settings.pass_samples = self::samples(value, key)?,
+            "max_depth" => settings.max_depth = small_integer(value, key)?,
+            "roulette_depth" => settings.roulette_depth = small_integer(value, key)?,
+            "sampler" => settings.sampler = name(value, key)?,
+            "seed" => settings.seed = integer(value, key)?,
+            "adaptive" => settings.adaptive_threshold = Some(positive(value, key)?),
+            "min_samples" => settings.min_samples = integer(value, key)? as usize,
+            "aov" => {
+                settings.aovs = value
+                    .as_array()
+                    .ok_or_else(|| format!("{} must be an array", key))?
+                    .iter()
+                    .map(|aov| name(aov, key))
+                    .collect::<Result<_, _>>()?
+            }
+            "filter" => settings.filter = name(value, key)?,
+            "filter_radius" => settings.filter_radius = Some(positive(value, key)?),
+            "exposure" => exposure = number(value, key)? as f32,
+            "white_balance" => white_balance = Some(positive(value, key)?),
+            "tonemap" => tone_curve = name(value, key)?,
+            _ => return Err(format!("unknown setting: {}", key)),
+        }
+    }
+    settings.samples = match samples {
+        Some(samples) => samples,
+        None if settings.time_limit.is_some() || settings.target_noise.is_some() => {
+            UNLIMITED_SAMPLES
+        }
+        None => settings.samples,
+    };
+    settings.validate()?;
+    let tone_mapper = ToneMapper::new(exposure, white_balance, tone_curve);
+    Ok((settings, tone_mapper))
+}
+
+impl JobDescription {
+    /// Parses a job as posted to the server.
+    pub fn from_json(body: &[u8]) -> Result<JobDescription, String> {
+        let body: Value = serde_json::from_slice(body).map_err(|error| error.to_string())?;
+        let body = body.as_object().ok_or("the job must be an object")?;
+        if let Some(key) = body
+            .keys()
+            .find(|key| *key != "scene" && *key != "settings")
+        {
+            return Err(format!("unknown field: {}", key));
+        }
+
+        let settings = match body.get("settings") {
+            Some(settings) => settings.as_object().ok_or("settings must be an object")?,
+            None => &Map::new(),
+        };
+        let (settings, tone_mapper) = parse_settings(settings)?;
+        let scene = match body.get("scene") {
+            Some(scene) => SceneDescription::from_json(scene, settings.seed)?,
+            None => SceneDescription::new(Builtin::Random, settings.seed),
+        };
+        Ok(JobDescription {
+            settings,
+            scene,
+            tone_mapper,
+        })
+    }
+
+    pub fn scene(&self) -> Scene {
+        self.scene
+            .build(self.settings.width as f32 / self.settings.height as f32)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum State {
+    Running,
+    Finished,
+    Cancelled,
+    Failed(String),
+}
+
+/// How far a job has got.
+struct Progress {
+    state: State,
+    passes: usize,
+    total_passes: usize,
+    pixels: usize,
+    total_pixels: usize,
+    /// The image as of the last completed pass, shared so that it can be
+    /// read without holding the lock.
+    film: Option<Arc<Film>>,
+    stopped: Option<StopReason>,
+    elapsed: Option<Duration>,
+}
+
+struct Job {
+    id: u64,
+    tone_mapper: ToneMapper,
+    cancellation: CancellationToken,
+    started: Instant,
+    progress: Mutex<Progress>,
+}
+
+impl Job {
+    fn is_running(&self) -> bool {
+        self.progress.lock().unwrap().state == State::Running
+    }
+
+    /// The image as of the last completed pass, if any pass has completed.
+    fn film(&self) -> Option<Arc<Film>> {
+        self.progress.lock().unwrap().film.clone()
+    }
+
+    fn status(&self) -> Value {
+        let progress = self.progress.lock().unwrap();
+        let (state, error) = match &progress.state {
+            State::Running => ("running", None),
+            State::Finished => ("finished", None),
+            State::Cancelled => ("cancelled", None),
+            State::Failed(error) => ("failed", Some(error.clone())),
+        };
+        let fraction = match progress.state {
+            State::Finished => 1.0,
+            _ if progress.total_pixels > 0 => progress.pixels as f64 / progress.total_pixels as f64,
+            _ => 0.0,
+        };
+        let stopped = progress.stopped.map(|reason| match reason {
+            StopReason::TimeLimit => "time_limit",
+            StopReason::TargetNoise => "target_noise",
+            StopReason::Cancelled => "cancelled",
+        });
+        let elapsed = progress.elapsed.unwrap_or_else(|| self.started.elapsed());
+        let (passes, total_passes) = (progress.passes, progress.total_passes);
+        // The film's statistics take a while to compute on large images.
+        let film = progress.film.clone();
+        drop(progress);
+        json!({
+            "id": self.id,
+            "state": state,
+            "error": error,
+            "stopped": stopped,
+            "passes": passes,
+            "total_passes": total_passes,
+            "progress": fraction,
+            "elapsed": elapsed.as_secs_f64(),
+            "average_samples": film.as_deref().map(Film::average_samples),
+            "estimated_error": film.as_deref().map(Film::estimated_error),
+        })
+    }
+}
+
+/// Keeps a job's progress up to date.
+struct JobObserver(Arc<Job>);
+
+impl RenderObserver for JobObserver {
+    fn on_start(&mut self, passes: usize, tiles: &[Bounds]) {
+        let mut progress = self.0.progress.lock().unwrap();
+        progress.total_passes = passes;
+        progress.total_pixels = passes * tiles.iter().map(Bounds::area).sum::<usize>();
+    }
+
+    fn on_tile(&mut self, _pass: usize, tile: Bounds) {
+        self.0.progress.lock().unwrap().pixels += tile.area();
+    }
+
+    fn on_pass(&mut self, film: &Film, passes: usize) -> io::Result<()> {
+        let mut progress = self.0.progress.lock().unwrap();
+        progress.film = Some(Arc::new(film.clone()));
+        progress.passes = passes;
+        Ok(())
+    }
+}
+
+/// What a panic was raised with, if it was a message.
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    match payload.downcast_ref::<&str>() {
+        Some(message) => message,
+        None => payload
+            .downcast_ref::<String>()
+            .map_or("unknown error", String::as_str),
+    }
+}
+
+/// Renders the job to the end, on the thread it's called from. A panic fails
+/// the job rather than leaving it running forever.
+fn run(job: Arc<Job>, description: JobDescription) {
+    let rendered = panic::catch_unwind(AssertUnwindSafe(|| {
+        let scene = description.scene();
+        let renderer = Renderer::new(description.settings)
+            .cancellation(job.cancellation.clone())
+            .observer(JobObserver(job.clone()));
+        futures::executor::block_on(renderer.render(&scene))
+    }));
+    finish(&job, rendered);
+}
+
+/// Records how the job's render ended, whether by finishing, failing or
+/// panicking.
+fn finish(job: &Job, rendered: thread::Result<io::Result<RenderOutput>>) {
+    let mut progress = job.progress.lock().unwrap();
+    progress.elapsed = Some(job.started.elapsed());
+    match rendered {
+        Err(payload) => {
+            progress.state =
+                State::Failed(format!("the render panicked: {}", panic_message(&*payload)))
+        }
+        Ok(Ok(rendered)) => {
+            progress.state = match rendered.stopped {
+                Some(StopReason::Cancelled) => State::Cancelled,
+                _ => State::Finished,
+            };
+            progress.stopped = rendered.stopped;
+            progress.passes = rendered.passes;
+            progress.film = Some(Arc::new(rendered.film));
+        }
+        Ok(Err(error)) => progress.state = State::Failed(error.to_string()),
+    }
+}
+
+/// The jobs of a server.
+#[derive(Default)]
+struct Jobs {
+    next_id: u64,
+    jobs: BTreeMap<u64, Arc<Job>>,
+}
+
+type SharedJobs = Arc<Mutex<Jobs>>;
+
+fn respond(status: StatusCode, content_type: &'static str, body: Body) -> Response<Body> {
+    let mut response = Response::new(body);
+    *response.status_mut() = status;
+    let headers = response.headers_mut();
+    headers.insert(header::CONTENT_TYPE, HeaderValue::from_static(content_type));
+    // The API is meant for tools such as web previews served from elsewhere.
+    headers.insert(
+        header::ACCESS_CONTROL_ALLOW_ORIGIN,
+        HeaderValue::from_static("*"),
+    );
+    response
+}
+
+fn respond_json(status: StatusCode, value: Value) -> Response<Body> {
+    respond(status, "application/json", Body::from(value.to_string()))
+}
+
+fn respond_error(status: StatusCode, error: &str) -> Response<Body> {
+    respond_json(status, json!({ "error": error }))
+}
+
+fn find(jobs: &SharedJobs, id: &str) -> Option<Arc<Job>> {
+    let id = id.parse().ok()?;
+    jobs.lock().unwrap().jobs.get(&id).cloned()
+}
+
+/// Reads a request's body, unless it's longer than `MAX_BODY`.
+async fn read_body(mut body: Body) -> Result<Vec<u8>, Response<Body>> {
+    let mut bytes = Vec::new();
+    while let Some(chunk) = body.data().await {
+        let chunk =
+            chunk.map_err(|error| respond_error(StatusCode::BAD_REQUEST, &error.to_string()))?;
+        if bytes.len() + chunk.len() > MAX_BODY {
+            let error = format!("the job must be at most {} bytes", MAX_BODY);
+            return Err(respond_error(StatusCode::PAYLOAD_TOO_LARGE, &error));
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+    Ok(bytes)
+}
+
+impl Jobs {
+    /// Forgets the oldest jobs that are done beyond the `MAX_FINISHED_JOBS`
+    /// most recent.
+    fn evict(&mut self) {
+        let done: Vec<u64> = self
+            .jobs
+            .values()
+            .filter(|job| !job.is_running())
+            .map(|job| job.id)
+            .collect();
+        for id in &done[..done.len().saturating_sub(MAX_FINISHED_JOBS)] {
+            self.jobs.remove(id);
+        }
+    }
+}
+
+async fn create(jobs: &SharedJobs, request: Request<Body>) -> Response<Body> {
+    let body = match read_body(request.into_body()).await {
+        Ok(body) => body,
+        Err(response) => return response,
+    };
+    let description = match JobDescription::from_json(&body) {
+        Ok(description) => description,
+        Err(error) => return respond_error(StatusCode::BAD_REQUEST, &error),
+    };
+
+    let job = {
+        let mut jobs = jobs.lock().unwrap();
+        jobs.evict();
+        let running = jobs.jobs.values().filter(|job| job.is_running()).count();
+        if running >= MAX_RUNNING_JOBS {
+            let error = format!("at most {} jobs may run at once", MAX_RUNNING_JOBS);
+            return respond_error(StatusCode::SERVICE_UNAVAILABLE, &error);
+        }
+        jobs.next_id += 1;
+        let job = Arc::new(Job {
+            id: jobs.next_id,
+            tone_mapper: description.tone_mapper.clone(),
+            cancellation: CancellationToken::new(),
+            started: Instant::now(),
+            progress: Mutex::new(Progress {
+                state: State::Running,
+                passes: 0,
+                total_passes: description.settings.passes(),
+                pixels: 0,
+                total_pixels: 0,
+                film: None,
+                stopped: None,
+                elapsed: None,
+            }),
+        });
+        jobs.jobs.insert(job.id, job.clone());
+        job
+    };
+    let running = job.clone();
+    tokio::task::spawn_blocking(move || run(running, description));
+
+    let mut response = respond_json(StatusCode::CREATED, job.status());
+    if let Ok(location) = HeaderValue::from_str(&format!("/jobs/{}", job.id)) {
+        response.headers_mut().insert(header::LOCATION, location);
+    }
+    response
+}
+
+fn image(job: &Job, query: Option<&str>) -> Response<Body> {
+    let mut format = Format::Ppm;
+    for (key, value) in query
+        .unwrap_or("")
+        .split('&')
+        .filter_map(|pair| pair.find('=').map(|index| pair.split_at(index)))
+    {
+        match (key, value[1..].parse()) {
+            ("format", Ok(value)) => format = value,
+            ("format", Err(error)) => return respond_error(StatusCode::BAD_REQUEST, &error),
+            _ => {}
+        }
+    }
+
+    // Encoded from a snapshot, so that the render isn't held up meanwhile.
+    let film = match job.film() {
+        Some(film) => film,
+        None => return respond_error(StatusCode::CONFLICT, "no pass has completed yet"),
+    };
+    let exr = ExrOptions {
+        pixel_type: PixelType::Half,
+        compression: Compression::Zip,
+        attributes: Vec::new(),
+    };
+    let mut bytes = Vec::new();
+    if let Err(error) = output::write_image(&film, &job.tone_mapper, &exr, format, &mut bytes) {
+        return respond_error(StatusCode::INTERNAL_SERVER_ERROR, &error.to_string());
+    }
+    let content_type = match format {
+        Format::Ppm => "image/x-portable-pixmap",
+        Format::Png => "image/png",
+        Format::Pfm => "image/x-portable-floatmap",
+        Format::Exr => "image/x-exr",
+    };
+    respond(StatusCode::OK, content_type, Body::from(bytes))
+}
+
+async fn handle(jobs: SharedJobs, request: Request<Body>) -> Response<Body> {
+    let path = request.uri().path().trim_matches('/').to_string();
+    let segments: Vec<&str> = path.split('/').collect();
+    let job = match segments.as_slice() {
+        ["jobs", id, ..] => match find(&jobs, id) {
+            Some(job) => Some(job),
+            None => return respond_error(StatusCode::NOT_FOUND, "no such job"),
+        },
+        _ => None,
+    };
+    match (request.method(), segments.as_slice(), job) {
+        (&Method::OPTIONS, _, _) => {
+            let mut response = respond(StatusCode::NO_CONTENT, "text/plain", Body::empty());
+            let headers = response.headers_mut();
+            headers.insert(
+                header::ACCESS_CONTROL_ALLOW_METHODS,
+                HeaderValue::from_static("GET, POST, DELETE"),
+            );
+            headers.insert(
+                header::ACCESS_CONTROL_ALLOW_HEADERS,
+                HeaderValue::from_static("content-type"),
+            );
+            response
+        }
+        (&Method::GET, ["jobs"], _) => {
+            let jobs: Vec<_> = jobs.lock().unwrap().jobs.values().cloned().collect();
+            respond_json(
+                StatusCode::OK,
+                Value::Array(jobs.iter().map(|job| job.status()).collect()),
+            )
+        }
+        (&Method::POST, ["jobs"], _) => create(&jobs, request).await,
+        (&Method::GET, ["jobs", _], Some(job)) => respond_json(StatusCode::OK, job.status()),
+        (&Method::GET, ["jobs", _, "image"], Some(job)) => image(&job, request.uri().query()),
+        (&Method::POST, ["jobs", _, "cancel"], Some(job)) => {
+            job.cancellation.cancel();
+            respond_json(StatusCode::ACCEPTED, job.status())
+        }
+        (&Method::DELETE, ["jobs", _], Some(job)) => {
+            job.cancellation.cancel();
+            jobs.lock().unwrap().jobs.remove(&job.id);
+            respond(StatusCode::NO_CONTENT, "text/plain", Body::empty())
+        }
+        _ => respond_error(StatusCode::NOT_FOUND, "no such endpoint"),
+    }
+}
+
+/// Serves the render API to clients that connect to `listener`, until
+/// accepting a connection fails.
+pub async fn serve(listener: std::net::TcpListener) -> io::Result<()> {
+    // The listener is bound by the standard library, as tokio's own binding
+    // mislays the address on newer compilers.
+    let mut listener = TcpListener::from_std(listener)?;
+    let jobs = SharedJobs::default();
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let jobs = jobs.clone();
+        let service = service_fn(move |request| {
+            let jobs = jobs.clone();
+            async move { Ok::<_, Infallible>(handle(jobs, request).await) }
+        });
+        tokio::spawn(async move {
+            // A connection that fails only affects its own client.
+            let _ = Http::new()
+                .http1_only(true)
+                .serve_connection(stream, service)
+                .await;
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn request(
+        jobs: &SharedJobs,
+        method: Method,
+        uri: &str,
+        body: &str,
+    ) -> (StatusCode, Vec<u8>) {
+        let request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let response = handle(jobs.clone(), request).await;
+        let status = response.status();
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        (status, body.to_vec())
+    }
+
+    async fn status(jobs: &SharedJobs, id: u64) -> Value {
+        let (status, body) = request(jobs, Method::GET, &format!("/jobs/{}", id), "").await;
+        assert_eq!(status, StatusCode::OK);
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    /// Polls the job until it stops running, returning its last status.
+    async fn wait(jobs: &SharedJobs, id: u64) -> Value {
+        loop {
+            let status = status(jobs, id).await;
+            if status["state"] != "running" {
+                return status;
+            }
+            tokio::time::delay_for(Duration::from_millis(10)).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn serves_the_same_image_as_a_local_render() {
+        let jobs = SharedJobs::default();
+        let job = r#"{
+            "scene": {"name": "random", "seed": 5},
+            "settings": {"width": 24, "height": 16, "samples": 4, "pass_samples": 2, "seed": 5}
+        }"#;
+        let (status, body) = request(&jobs, Method::POST, "/jobs", job).await;
+        assert_eq!(status, StatusCode::CREATED);
+        let id = serde_json::from_slice::<Value>(&body).unwrap()["id"]
+            .as_u64()
+            .unwrap();
+
+        let finished = wait(&jobs, id).await;
+        assert_eq!(finished["state"], "finished");
+        assert_eq!(finished["passes"], 2);
+        assert_eq!(finished["progress"], 1.0);
+
+        let settings = RenderSettings {
+            width: 24,
+            height: 16,
+            samples: 4,
+            pass_samples: 2,
+            seed: 5,
+            ..RenderSettings::default()
+        };
+        let scene = Scene::random(5, 1.5);
+        let local = Renderer::new(settings).render(&scene).await.unwrap();
+        let mut expected = Vec::new();
+        output::write_pfm(&local.film, &mut expected).unwrap();
+        let uri = format!("/jobs/{}/image?format=pfm", id);
+        let (status, image) = request(&jobs, Method::GET, &uri, "").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(image, expected);
+    }
+
+    #[tokio::test]
+    async fn cancels_jobs() {
+        let jobs = SharedJobs::default();
+        let job =
+            r#"{"settings": {"width": 64, "height": 64, "samples": 65536, "pass_samples": 1}}"#;
+        let (status, _) = request(&jobs, Method::POST, "/jobs", job).await;
+        assert_eq!(status, StatusCode::CREATED);
+
+        let (status, _) = request(&jobs, Method::POST, "/jobs/1/cancel", "").await;
+        assert_eq!(status, StatusCode::ACCEPTED);
+        assert_eq!(wait(&jobs, 1).await["state"], "cancelled");
+
+        let (status, _) = request(&jobs, Method::DELETE, "/jobs/1", "").await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        let (status, _) = request(&jobs, Method::GET, "/jobs/1", "").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn rejects_invalid_jobs() {
+        let jobs = SharedJobs::default();
+        for job in &[
+            "not json",
+            r#"{"settings": {"width": 0}}"#,
+            r#"{"settings": {"width": 70000, "height": 70000}}"#,
+            r#"{"settings": {"height": 4294967297}}"#,
+            r#"{"settings": {"max_depth": 4294967296}}"#,
+            r#"{"settings": {"samples": 65537}}"#,
+            r#"{"settings": {"pass_samples": 1000000}}"#,
+            r#"{"settings": {"time_limit": 1e30}}"#,
+            r#"{"settings": {"sampler": "best"}}"#,
+            r#"{"settings": {"colour": true}}"#,
+            r#"{"scene": {"name": "teapot"}}"#,
+        ] {
+            let (status, body) = request(&jobs, Method::POST, "/jobs", job).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST, "{}", job);
+            assert!(serde_json::from_slice::<Value>(&body).unwrap()["error"].is_string());
+        }
+        assert_eq!(request(&jobs, Method::GET, "/jobs", "").await.1, b"[]");
+    }
+
+    fn job(id: u64, state: State) -> Arc<Job> {
+        Arc::new(Job {
+            id,
+            tone_mapper: ToneMapper::new(0.0, None, ToneCurve::Clamp),
+            cancellation: CancellationToken::new(),
+            started: Instant::now(),
+            progress: Mutex::new(Progress {
+                state,
+                passes: 0,
+                total_passes: 0,
+                pixels: 0,
+                total_pixels: 0,
+                film: None,
+                stopped: None,
+                elapsed: None,
+            }),
+        })
+    }
+
+    #[tokio::test]
+    async fn limits_the_size_and_number_of_jobs() {
+        let jobs = SharedJobs::default();
+        let padded = format!(
+            r#"{{"settings": {{}}, "scene": "{}"}}"#,
+            " ".repeat(MAX_BODY)
+        );
+        let (status, _) = request(&jobs, Method::POST, "/jobs", &padded).await;
+        assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+
+        for id in 1..=MAX_RUNNING_JOBS as u64 {
+            jobs.lock()
+                .unwrap()
+                .jobs
+                .insert(id, job(id, State::Running));
+        }
+        let (status, _) = request(&jobs, Method::POST, "/jobs", "{}").await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    #[test]
+    fn forgets_the_oldest_finished_jobs() {
+        let mut jobs = Jobs::default();
+        let finished = MAX_FINISHED_JOBS as u64 + 3;
+        for id in 1..=finished {
+            jobs.jobs.insert(id, job(id, State::Finished));
+        }
+        jobs.jobs.insert(0, job(0, State::Running));
+        jobs.evict();
+        let ids: Vec<u64> = jobs.jobs.keys().cloned().collect();
+        let mut expected = vec![0];
+        expected.extend(4..=finished);
+        assert_eq!(ids, expected);
+    }
+
+    #[test]
+    fn fails_jobs_that_panic() {
+        let job = job(1, State::Running);
+        finish(&job, panic::catch_unwind(|| panic!("out of luck")));
+        let status = job.status();
+        assert_eq!(status["state"], "failed");
+        assert_eq!(status["error"], "the render panicked: out of luck");
+    }
+}
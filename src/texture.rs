@@ -42,3 +42,129 @@ impl Texture for Checkered {
         }
     }
 }
+
+/// A checkerboard over the surface's texture coordinates, with `rows` squares
+/// from pole to pole and twice as many around.
+pub struct UvCheckered {
+    odd: Box<dyn Texture>,
+    even: Box<dyn Texture>,
+    rows: f32,
+}
+
+impl UvCheckered {
+    pub fn new(odd: Box<dyn Texture>, even: Box<dyn Texture>, rows: f32) -> UvCheckered {
+        UvCheckered { odd, even, rows }
+    }
+}
+
+impl Texture for UvCheckered {
+    fn value(&self, u: f32, v: f32, p: &Vec3) -> Vec3 {
+        let square = (2.0 * self.rows * u).floor() + (self.rows * v).floor();
+        if square.rem_euclid(2.0) < 1.0 {
+            self.even.value(u, v, p)
+        } else {
+            self.odd.value(u, v, p)
+        }
+    }
+}
+
+const PERLIN_POINTS: usize = 256;
+
+/// Gradient noise on a lattice of random unit vectors (Perlin 2002).
+struct Perlin {
+    gradients: Vec<Vec3>,
+    permutations: [Vec<usize>; 3],
+}
+
+impl Perlin {
+    fn new(seed: u64) -> Perlin {
+        use rand::rngs::StdRng;
+        use rand::seq::SliceRandom;
+        use rand::{Rng, SeedableRng};
+        let mut rng = StdRng::seed_from_u64(seed);
+        let gradients = (0..PERLIN_POINTS)
+            .map(|_| {
+                let (u, v): (f32, f32) = (rng.gen(), rng.gen());
+                crate::sampling::uniform_sphere((u, v)).0
+            })
+            .collect();
+        let mut permutation = || {
+            let mut permutation: Vec<usize> = (0..PERLIN_POINTS).collect();
+            permutation.shuffle(&mut rng);
+            permutation
+        };
+        let permutations = [permutation(), permutation(), permutation()];
+        Perlin {
+            gradients,
+            permutations,
+        }
+    }
+
+    /// Noise in [-1, 1] at `p`.
+    fn noise(&self, p: &Vec3) -> f32 {
+        let cell = [p.x().floor(), p.y().floor(), p.z().floor()];
+        let fraction = [p.x() - cell[0], p.y() - cell[1], p.z() - cell[2]];
+        // Hermite smoothing hides the lattice.
+        let smooth: Vec<f32> = fraction.iter().map(|f| f * f * (3.0 - 2.0 * f)).collect();
+        let mut sum = 0.0;
+        for corner in 0..8 {
+            let offset = [corner & 1, (corner >> 1) & 1, (corner >> 2) & 1];
+            let index = (0..3).fold(0, |index, axis| {
+                let lattice = (cell[axis] as i64 + offset[axis] as i64) as usize;
+                index ^ self.permutations[axis][lattice % PERLIN_POINTS]
+            });
+            let mut weight = 1.0;
+            for axis in 0..3 {
+                weight *= if offset[axis] == 1 {
+                    smooth[axis]
+                } else {
+                    1.0 - smooth[axis]
+                };
+            }
+            let to_point = Vec3::new(
+                fraction[0] - offset[0] as f32,
+                fraction[1] - offset[1] as f32,
+                fraction[2] - offset[2] as f32,
+            );
+            sum += weight * self.gradients[index].dot(&to_point);
+        }
+        sum
+    }
+
+    /// The sum of `octaves` octaves of the noise's magnitude.
+    fn turbulence(&self, p: &Vec3, octaves: usize) -> f32 {
+        let mut p = p.clone();
+        let mut sum = 0.0;
+        let mut weight = 1.0;
+        for _ in 0..octaves {
+            sum += weight * self.noise(&p);
+            weight *= 0.5;
+            p = 2.0 * p;
+        }
+        sum.abs()
+    }
+}
+
+/// A marble-like pattern of veins perturbed by turbulence.
+pub struct Marble {
+    perlin: Perlin,
+    color: Vec3,
+    scale: f32,
+}
+
+impl Marble {
+    pub fn new(seed: u64, color: Vec3, scale: f32) -> Marble {
+        Marble {
+            perlin: Perlin::new(seed),
+            color,
+            scale,
+        }
+    }
+}
+
+impl Texture for Marble {
+    fn value(&self, _u: f32, _v: f32, p: &Vec3) -> Vec3 {
+        let phase = self.scale * p.z() + 10.0 * self.perlin.turbulence(p, 7);
+        0.5 * (1.0 + phase.sin()) * &self.color
+    }
+}
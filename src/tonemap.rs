@@ -0,0 +1,363 @@
+use std::str::FromStr;
+
+use crate::film::Film;
+use crate::geometry::Vec3;
+
+type Matrix = [[f32; 3]; 3];
+
+fn transform(m: &Matrix, v: &Vec3) -> Vec3 {
+    Vec3::new(
+        m[0][0] * v.x() + m[0][1] * v.y() + m[0][2] * v.z(),
+        m[1][0] * v.x() + m[1][1] * v.y() + m[1][2] * v.z(),
+        m[2][0] * v.x() + m[2][1] * v.y() + m[2][2] * v.z(),
+    )
+}
+
+fn multiply(a: &Matrix, b: &Matrix) -> Matrix {
+    let mut m = [[0.0; 3]; 3];
+    for (i, row) in m.iter_mut().enumerate() {
+        for (j, entry) in row.iter_mut().enumerate() {
+            *entry = (0..3).map(|k| a[i][k] * b[k][j]).sum();
+        }
+    }
+    m
+}
+
+fn diagonal(v: &Vec3) -> Matrix {
+    [[v.x(), 0.0, 0.0], [0.0, v.y(), 0.0], [0.0, 0.0, v.z()]]
+}
+
+const SRGB_TO_XYZ: Matrix = [
+    [0.412_456_4, 0.357_576_1, 0.180_437_5],
+    [0.212_672_9, 0.715_152_2, 0.072_175],
+    [0.019_333_9, 0.119_192, 0.950_304_1],
+];
+
+const XYZ_TO_SRGB: Matrix = [
+    [3.240_454_2, -1.537_138_5, -0.498_531_4],
+    [-0.969_266, 1.876_010_8, 0.041_556],
+    [0.055_643_4, -0.204_025_9, 1.057_225_2],
+];
+
+const BRADFORD: Matrix = [
+    [0.8951, 0.2664, -0.1614],
+    [-0.7502, 1.7135, 0.0367],
+    [0.0389, -0.0685, 1.0296],
+];
+
+const BRADFORD_INVERSE: Matrix = [
+    [0.986_992_9, -0.147_054_3, 0.159_962_7],
+    [0.432_305_3, 0.518_360_3, 0.049_291_2],
+    [-0.008_528_7, 0.040_042_8, 0.968_486_7],
+];
+
+/// The CIE xy chromaticity of a blackbody at the given temperature in kelvin,
+/// using the cubic fit of Kim et al. to the Planckian locus.
+fn planckian_chromaticity(temperature: f32) -> (f32, f32) {
+    let t = temperature.clamp(1667.0, 25000.0);
+    let (t2, t3) = (t * t, t * t * t);
+    let x = if t <= 4000.0 {
+        -0.266_123_9e9 / t3 - 0.234_358_9e6 / t2 + 0.877_695_6e3 / t + 0.179_910
+    } else {
+        -3.025_846_9e9 / t3 + 2.107_038e6 / t2 + 0.222_634_7e3 / t + 0.240_390
+    };
+    let (x2, x3) = (x * x, x * x * x);
+    let y = if t <= 2222.0 {
+        -1.106_381_4 * x3 - 1.348_110_2 * x2 + 2.185_558_3 * x - 0.202_196_83
+    } else if t <= 4000.0 {
+        -0.954_947_6 * x3 - 1.374_185_9 * x2 + 2.091_37 * x - 0.167_488_67
+    } else {
+        3.081_758 * x3 - 5.873_387 * x2 + 3.751_13 * x - 0.370_014_83
+    };
+    (x, y)
+}
+
+/// Returns the matrix that adapts linear sRGB colors lit by a blackbody
+/// illuminant at `temperature` to the D65 white point of sRGB.
+fn white_balance_matrix(temperature: f32) -> Matrix {
+    let (x, y) = planckian_chromaticity(temperature);
+    let source = transform(&BRADFORD, &Vec3::new(x / y, 1.0, (1.0 - x - y) / y));
+    let target = transform(&BRADFORD, &Vec3::new(0.950_47, 1.0, 1.088_83));
+    let scale = Vec3::new(
+        target.x() / source.x(),
+        target.y() / source.y(),
+        target.z() / source.z(),
+    );
+    let adaptation = multiply(&BRADFORD_INVERSE, &multiply(&diagonal(&scale), &BRADFORD));
+    multiply(&XYZ_TO_SRGB, &multiply(&adaptation, &SRGB_TO_XYZ))
+}
+
+/// A curve compressing scene-referred radiance into the displayable range.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ToneCurve {
+    /// Clips values outside [0, 1].
+    Clamp,
+    /// Reinhard's L / (1 + L) applied to luminance, preserving hue.
+    Reinhard,
+    /// Stephen Hill's fit of the ACES reference rendering and output
+    /// transforms.
+    Aces,
+    /// A polynomial fit of Troy Sobotka's AgX base curve.
+    Agx,
+}
+
+impl ToneCurve {
+    pub const NAMES: &'static [&'static str] = &["clamp", "reinhard", "aces", "agx"];
+
+    /// Maps a linear scene-referred color to a linear display-referred color.
+    fn apply(self, color: &Vec3) -> Vec3 {
+        match self {
+            ToneCurve::Clamp => color.clone(),
+            ToneCurve::Reinhard => {
+                let luminance = color.luminance();
+                if luminance > 0.0 {
+                    color / (1.0 + luminance)
+                } else {
+                    Vec3::default()
+                }
+            }
+            ToneCurve::Aces => {
+                const INPUT: Matrix = [
+                    [0.59719, 0.35458, 0.04823],
+                    [0.07600, 0.90834, 0.01566],
+                    [0.02840, 0.13383, 0.83777],
+                ];
+                const OUTPUT: Matrix = [
+                    [1.60475, -0.53108, -0.07367],
+                    [-0.10208, 1.10813, -0.00605],
+                    [-0.00327, -0.07276, 1.07602],
+                ];
+                let fit = |v: f32| {
+                    (v * (v + 0.024_578_6) - 0.000_090_537)
+                        / (v * (0.983_729 * v + 0.432_951) + 0.238_081)
+                };
+                let v = transform(&INPUT, color);
+                transform(&OUTPUT, &Vec3::new(fit(v.x()), fit(v.y()), fit(v.z())))
+            }
+            ToneCurve::Agx => {
+                const INSET: Matrix = [
+                    [0.842_479_06, 0.078_433_6, 0.079_223_745],
+                    [0.042_328_242, 0.878_468_6, 0.079_166_13],
+                    [0.042_375_655, 0.078_433_6, 0.879_143],
+                ];
+                const OUTSET: Matrix = [
+                    [1.196_879, -0.098_020_88, -0.099_029_74],
+                    [-0.052_896_85, 1.151_903_1, -0.098_961_18],
+                    [-0.052_971_635, -0.098_043_45, 1.151_073_7],
+                ];
+                const MIN_EV: f32 = -12.473_931;
+                const MAX_EV: f32 = 4.026_069;
+                let curve = |v: f32| {
+                    let v =
+                        (v.max(1e-10).log2().clamp(MIN_EV, MAX_EV) - MIN_EV) / (MAX_EV - MIN_EV);
+                    let (v2, v4) = (v * v, v * v * v * v);
+                    15.5 * v4 * v2 - 40.14 * v4 * v + 31.96 * v4 - 6.868 * v2 * v
+                        + 0.4298 * v2
+                        + 0.1191 * v
+                        - 0.002_32
+                };
+                let v = transform(&INSET, color);
+                let v = transform(
+                    &OUTSET,
+                    &Vec3::new(curve(v.x()), curve(v.y()), curve(v.z())),
+                );
+                // The curve's output is encoded for a display with gamma 2.2.
+                let linear = |v: f32| v.max(0.0).powf(2.2);
+                Vec3::new(linear(v.x()), linear(v.y()), linear(v.z()))
+            }
+        }
+    }
+}
+
+impl FromStr for ToneCurve {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<ToneCurve, String> {
+        match s {
+            "clamp" => Ok(ToneCurve::Clamp),
+            "reinhard" => Ok(ToneCurve::Reinhard),
+            "aces" => Ok(ToneCurve::Aces),
+            "agx" => Ok(ToneCurve::Agx),
+            _ => Err(format!("unknown tone curve: {}", s)),
+        }
+    }
+}
+
+/// The sRGB transfer function from linear to encoded values.
+pub fn srgb_encode(v: f32) -> f32 {
+    if v <= 0.003_130_8 {
+        12.92 * v
+    } else {
+        1.055 * v.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// The inverse of `srgb_encode`, from encoded to linear values.
+pub fn srgb_decode(v: f32) -> f32 {
+    if v <= 0.040_45 {
+        v / 12.92
+    } else {
+        ((v + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Converts linear scene radiance into 8-bit sRGB display values.
+#[derive(Clone, Debug)]
+pub struct ToneMapper {
+    scale: f32,
+    white_balance: Option<Matrix>,
+    curve: ToneCurve,
+}
+
+impl ToneMapper {
+    /// Creates a tone mapper that scales radiance by 2^`exposure`, adapts the
+    /// white point from a blackbody illuminant at `white_balance` kelvin if
+    /// given, and then applies `curve`.
+    pub fn new(exposure: f32, white_balance: Option<f32>, curve: ToneCurve) -> ToneMapper {
+        ToneMapper {
+            scale: exposure.exp2(),
+            white_balance: white_balance.map(white_balance_matrix),
+            curve,
+        }
+    }
+
+    /// Maps a linear scene-referred color to a linear display color in [0, 1].
+    pub fn map(&self, color: &Vec3) -> Vec3 {
+        let color = self.scale * color;
+        let color = match &self.white_balance {
+            Some(matrix) => transform(matrix, &color),
+            None => color,
+        };
+        let color = self.curve.apply(&color);
+        let clamp = |v: f32| if v.is_nan() { 0.0 } else { v.clamp(0.0, 1.0) };
+        Vec3::new(clamp(color.r()), clamp(color.g()), clamp(color.b()))
+    }
+
+    pub fn to_srgb8(&self, color: &Vec3) -> (u8, u8, u8) {
+        let color = self.map(color);
+        let quantize = |v: f32| (255.0 * srgb_encode(v) + 0.5) as u8;
+        (
+            quantize(color.r()),
+            quantize(color.g()),
+            quantize(color.b()),
+        )
+    }
+
+    /// Tone maps every pixel of the film, in rows from the top.
+    pub fn apply(&self, film: &Film) -> Vec<(u8, u8, u8)> {
+        film.pixels()
+            .iter()
+            .map(|pixel| self.to_srgb8(&pixel.color()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CURVES: [ToneCurve; 4] = [
+        ToneCurve::Clamp,
+        ToneCurve::Reinhard,
+        ToneCurve::Aces,
+        ToneCurve::Agx,
+    ];
+
+    fn channels(v: &Vec3) -> [f32; 3] {
+        [v.r(), v.g(), v.b()]
+    }
+
+    fn assert_close(a: &Vec3, b: &Vec3, tolerance: f32) {
+        let (a, b) = (channels(a), channels(b));
+        assert!(
+            (0..3).all(|i| (a[i] - b[i]).abs() <= tolerance),
+            "{:?} differs from {:?}",
+            a,
+            b
+        );
+    }
+
+    #[test]
+    fn srgb_transfer_functions_are_inverses() {
+        assert_eq!(srgb_encode(0.0), 0.0);
+        assert!((srgb_encode(1.0) - 1.0).abs() < 1e-6);
+        for i in 0..=1000 {
+            let v = i as f32 / 1000.0;
+            assert!(
+                (srgb_decode(srgb_encode(v)) - v).abs() <= 1e-5 * v.max(1e-2),
+                "{}",
+                v
+            );
+            assert!(
+                (srgb_encode(srgb_decode(v)) - v).abs() <= 1e-5 * v.max(1e-2),
+                "{}",
+                v
+            );
+        }
+        // The linear and power segments meet at the knee.
+        let knee = 0.003_130_8;
+        assert!((srgb_encode(knee) - srgb_encode(knee + 1e-7)).abs() < 1e-5);
+    }
+
+    #[test]
+    fn curves_map_into_the_unit_range_monotonically() {
+        let colors = [
+            Vec3::new(1.0, 1.0, 1.0),
+            Vec3::new(1.0, 0.2, 0.05),
+            Vec3::new(0.1, 0.3, 1.0),
+        ];
+        for &curve in &CURVES {
+            let mapper = ToneMapper::new(0.0, None, curve);
+            for color in &colors {
+                let mut last = channels(&mapper.map(&Vec3::default()));
+                assert_eq!(last, [0.0; 3], "{:?}", curve);
+                // Radiance from 2^-16 to 2^16 in quarter stops.
+                for stop in -64..=64 {
+                    let mapped = channels(&mapper.map(&(color * (stop as f32 / 4.0).exp2())));
+                    assert!((0..3).all(|i| (0.0..=1.0).contains(&mapped[i])));
+                    // The curves are monotonic on each channel of a gray, but
+                    // mix the channels of colors, desaturating bright ones.
+                    if color.x() == color.z() {
+                        assert!(
+                            (0..3).all(|i| mapped[i] >= last[i]),
+                            "{:?} darkens at stop {}",
+                            curve,
+                            stop
+                        );
+                        // Before clipping, only the fit to ACES strays, by a
+                        // little, out of the unit range.
+                        let (low, high) = match curve {
+                            ToneCurve::Clamp => (f32::MIN, f32::MAX),
+                            ToneCurve::Aces => (-1e-3, 1.02),
+                            _ => (0.0, 1.0),
+                        };
+                        let curved = channels(&curve.apply(&(color * (stop as f32 / 4.0).exp2())));
+                        assert!((0..3).all(|i| (low..=high).contains(&curved[i])));
+                    }
+                    last = mapped;
+                }
+            }
+            assert!(mapper.map(&Vec3::new(f32::INFINITY, 1.0, 1.0)).r() <= 1.0);
+        }
+    }
+
+    #[test]
+    fn applies_the_curve_after_exposure_and_white_balance() {
+        let color = Vec3::new(0.3, 0.25, 0.2);
+        let mapper = ToneMapper::new(1.5, Some(4500.0), ToneCurve::Reinhard);
+        let balance = white_balance_matrix(4500.0);
+        let expected = ToneCurve::Reinhard.apply(&transform(&balance, &(1.5f32.exp2() * &color)));
+        assert!(channels(&expected).iter().all(|c| (0.0..=1.0).contains(c)));
+        assert_close(&mapper.map(&color), &expected, 1e-6);
+        // The curve isn't linear, so exposing after it would differ.
+        let curved = ToneCurve::Reinhard.apply(&transform(&balance, &color));
+        assert!((mapper.map(&color).g() - (1.5f32.exp2() * curved).g()).abs() > 0.01);
+
+        // White lit by the illuminant is balanced to white.
+        let (x, y) = planckian_chromaticity(3200.0);
+        let illuminant = Vec3::new(x / y, 1.0, (1.0 - x - y) / y);
+        let white = transform(&XYZ_TO_SRGB, &illuminant);
+        let balanced = transform(&white_balance_matrix(3200.0), &white);
+        assert_close(&balanced, &Vec3::new(1.0, 1.0, 1.0), 2e-3);
+    }
+}
@@ -0,0 +1,101 @@
+// White furnace tests: an object that loses no energy, lit by a uniform white
+// environment, scatters back exactly the light it blocks, so it must vanish
+// into the background however it scatters. Any pixel that isn't white within
+// noise points to a material or integrator that creates or destroys energy.
+
+use ray_tracer::camera::Camera;
+use ray_tracer::compare::Comparison;
+use ray_tracer::geometry::Vec3;
+use ray_tracer::material::{Dielectric, Lambertian, Material, Metal};
+use ray_tracer::object::{ConstantMedium, Hittable, Sphere, World};
+use ray_tracer::scene::{Background, Scene};
+use ray_tracer::texture::Uniform;
+use ray_tracer::{Film, RenderSettings, Renderer};
+
+const SIZE: u32 = 16;
+
+fn white() -> Vec3 {
+    Vec3::new(1.0, 1.0, 1.0)
+}
+
+/// Renders the objects in the furnace, compared to the background.
+fn render(objects: Vec<Box<dyn Hittable + Send + Sync>>) -> Comparison {
+    let camera = Camera::from_fov(
+        Vec3::new(0.0, 0.0, 4.0),
+        Vec3::new(0.0, 0.0, 0.0),
+        Vec3::new(0.0, 1.0, 0.0),
+        35.0,
+        1.0,
+    );
+    let scene = Scene::new(World::new(objects), camera, Background::Uniform(white()));
+    let settings = RenderSettings {
+        width: SIZE,
+        height: SIZE,
+        samples: 64,
+        ..RenderSettings::default()
+    };
+    let film = futures::executor::block_on(Renderer::new(settings).render(&scene))
+        .unwrap()
+        .film;
+    let mut background = Film::new(SIZE, SIZE, &[]);
+    for (x, y) in background.bounds().pixels() {
+        background.set_color(x, y, &white());
+    }
+    Comparison::new(&film, &background)
+}
+
+fn assert_invisible(name: &str, objects: Vec<Box<dyn Hittable + Send + Sync>>) {
+    let comparison = render(objects);
+    assert!(
+        comparison.is_plausible(0.01, 4.0),
+        "{} shows in the furnace: {:.2}% of pixels are outliers and the mean luminance is off \
+         by {:.2} standard errors",
+        name,
+        100.0 * comparison.outliers,
+        comparison.bias
+    );
+}
+
+fn sphere<M: Material>(radius: f32, material: M) -> Box<Sphere<M>> {
+    Box::new(Sphere::new(Vec3::default(), radius, material))
+}
+
+#[test]
+fn lambertian_is_invisible() {
+    let material = Lambertian::new(Box::new(Uniform::new(white())));
+    assert_invisible("a white Lambertian sphere", vec![sphere(1.0, material)]);
+}
+
+#[test]
+fn mirror_is_invisible() {
+    let material = Metal::new(white(), 0.0);
+    assert_invisible("a white mirror sphere", vec![sphere(1.0, material)]);
+}
+
+#[test]
+fn glass_is_invisible() {
+    assert_invisible("a glass sphere", vec![sphere(1.0, Dielectric::new(1.5))]);
+    assert_invisible(
+        "a hollow glass sphere",
+        vec![
+            sphere(1.0, Dielectric::new(1.5)),
+            sphere(-0.9, Dielectric::new(1.5)),
+        ],
+    );
+}
+
+#[test]
+fn white_medium_is_invisible() {
+    let boundary = sphere(1.0, Dielectric::new(1.0));
+    let medium = ConstantMedium::new(boundary, 2.0, Box::new(Uniform::new(white())));
+    assert_invisible("a white medium", vec![Box::new(medium)]);
+}
+
+#[test]
+fn lossy_materials_are_visible() {
+    let gray = Lambertian::new(Box::new(Uniform::new(Vec3::new(0.95, 0.95, 0.95))));
+    assert!(!render(vec![sphere(1.0, gray)]).is_plausible(0.01, 4.0));
+    // Fuzzy metal absorbs what it scatters into its surface.
+    let fuzzy = Metal::new(white(), 0.5);
+    assert!(!render(vec![sphere(1.0, fuzzy)]).is_plausible(0.01, 4.0));
+}
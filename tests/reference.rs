@@ -0,0 +1,126 @@
+// Renders each built-in scene small and compares it to its reference image in
+// tests/references, failing if the render differs by more than noise would
+// explain. The references are film states rather than image files, so that
+// they keep the sample statistics of each pixel to judge the noise by. On
+// failure, the render, the reference and a false-color image of each pixel's
+// difference in standard errors are left in the target directory. Run with
+// UPDATE_REFERENCES=1 to render the references anew after a change that is
+// meant to change the images.
+
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+
+use ray_tracer::builtin::{Builtin, SceneDescription};
+use ray_tracer::compare::Comparison;
+use ray_tracer::output::{write_pfm, write_ppm};
+use ray_tracer::tonemap::{ToneCurve, ToneMapper};
+use ray_tracer::{Film, RenderSettings, Renderer};
+
+/// The most pixels that may be outliers, allowing for the heavy tails of
+/// noise from small lights.
+const MAX_OUTLIERS: f32 = 0.01;
+
+/// The most standard errors the mean luminance may be off by.
+const MAX_BIAS: f32 = 4.0;
+
+fn render(builtin: Builtin) -> Film {
+    let settings = RenderSettings {
+        width: 32,
+        height: 24,
+        samples: 256,
+        seed: 1,
+        ..RenderSettings::default()
+    };
+    let scene = SceneDescription::new(builtin, 1).build(32.0 / 24.0);
+    futures::executor::block_on(Renderer::new(settings).render(&scene))
+        .unwrap()
+        .film
+}
+
+fn save_pfm(film: &Film, path: &Path) {
+    write_pfm(film, &mut BufWriter::new(File::create(path).unwrap())).unwrap();
+}
+
+fn read_reference(path: &Path) -> io::Result<Film> {
+    Film::read_state(&mut BufReader::new(File::open(path)?))
+}
+
+fn check(builtin: Builtin) {
+    let film = render(builtin);
+    let reference_path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/references")
+        .join(format!("{}.film", builtin));
+    if env::var_os("UPDATE_REFERENCES").is_some() {
+        let mut out = BufWriter::new(File::create(&reference_path).unwrap());
+        film.write_state(&mut out).unwrap();
+        return;
+    }
+
+    let reference = read_reference(&reference_path).unwrap_or_else(|error| {
+        panic!(
+            "can't read {}: {}; run with UPDATE_REFERENCES=1 to render it",
+            reference_path.display(),
+            error
+        )
+    });
+    let comparison = Comparison::new(&film, &reference);
+    if comparison.is_plausible(MAX_OUTLIERS, MAX_BIAS) {
+        return;
+    }
+
+    let diffs: PathBuf = Path::new(env!("CARGO_TARGET_TMPDIR")).join("reference-diffs");
+    fs::create_dir_all(&diffs).unwrap();
+    let render_path = diffs.join(format!("{}.pfm", builtin));
+    save_pfm(&film, &render_path);
+    save_pfm(
+        &reference,
+        &diffs.join(format!("{}.reference.pfm", builtin)),
+    );
+    let diff_path = diffs.join(format!("{}.diff.ppm", builtin));
+    let tone_mapper = ToneMapper::new(0.0, None, ToneCurve::Clamp);
+    let mut out = BufWriter::new(File::create(&diff_path).unwrap());
+    write_ppm(&comparison.score_image(), &tone_mapper, &mut out).unwrap();
+    panic!(
+        "{} differs from its reference by more than noise: {:.2}% of pixels are outliers \
+         and the mean luminance is off by {:.2} standard errors, with an RMSE of {:.5}. \
+         See {} and {}.",
+        builtin,
+        100.0 * comparison.outliers,
+        comparison.bias,
+        comparison.rmse,
+        render_path.display(),
+        diff_path.display()
+    );
+}
+
+#[test]
+fn random() {
+    check(Builtin::Random);
+}
+
+#[test]
+fn demo() {
+    check(Builtin::Demo);
+}
+
+#[test]
+fn cornell() {
+    check(Builtin::Cornell);
+}
+
+#[test]
+fn material_grid() {
+    check(Builtin::MaterialGrid);
+}
+
+#[test]
+fn texture_showcase() {
+    check(Builtin::TextureShowcase);
+}
+
+#[test]
+fn volume() {
+    check(Builtin::Volume);
+}